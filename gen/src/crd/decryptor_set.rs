@@ -0,0 +1,314 @@
+use schemars::{
+    JsonSchema,
+    gen::SchemaGenerator,
+    schema::{Schema, SchemaObject, InstanceType}
+};
+use serde::{Serialize, Deserialize};
+use chrono::Utc;
+use kube::{
+    CustomResource,
+    Client,
+    Api,
+    api::{Patch, PatchParams},
+};
+use super::{DecryptorSpec, repo::Repository};
+use super::git_repository::{self, RepositoryRef};
+use crate::err::Error;
+use crate::util::{get_short_hash, MAX_LABEL_LENGTH};
+
+// Constant
+const DEFAULT_NAMESPACE: &str = "default";
+const PRESERVE_UNKNOWN_FIELDS: &str = "x-kubernetes-preserve-unknown-fields";
+
+/// DecryptorSet generates a Decryptor for each encrypted file of the repository matching the path glob.
+/// The spec of the generated Decryptors is built from the template. Decryptors are created, updated and deleted
+/// as the matching files appear or disappear in the repository
+///
+/// # Example
+/// apiVersion: jiemi.cr/v1alpha1
+/// kind: DecryptorSet
+/// metadata:
+///   name: services
+/// spec:
+///   generator:
+///     repositoryRef:
+///       name: secrets
+///     path: services/**/secret.enc.yaml
+///     sopsPath: .sops.yaml
+///   template:
+///     spec:
+///       providerRef:
+///         kind: ClusterDecryptionProvider
+///         name: gcp-kms
+#[derive(Debug, CustomResource, Serialize, Deserialize, Clone, JsonSchema)]
+#[kube(status = "DecryptorSetStatus")]
+#[kube(group = "jiemi.cr", version = "v1alpha1", kind = "DecryptorSet", namespaced)]
+pub struct DecryptorSetSpec {
+    pub generator: GitGenerator,
+    pub template: DecryptorTemplate
+}
+
+/// GitGenerator list the encrypted files of the repository which match the path glob
+#[derive(Debug, JsonSchema, Clone, Serialize, Deserialize)]
+pub struct GitGenerator {
+    pub repository: Option<Repository>,
+    #[serde(rename = "repositoryRef")]
+    pub repository_ref: Option<RepositoryRef>,
+    pub path: String,
+    #[serde(rename = "sopsPath")]
+    pub sops_path: String
+}
+
+/// DecryptorTemplate is the spec of the generated Decryptors w/o the source which is set by the generator
+#[derive(Debug, JsonSchema, Clone, Serialize, Deserialize, Default)]
+pub struct DecryptorTemplate {
+    #[serde(default)]
+    #[schemars(schema_with = "preserve_unknown_fields")]
+    pub spec: Option<serde_json::Value>
+}
+
+/// Status of the DecryptorSet
+///
+/// # Example
+/// Status:
+///     decryptors:
+///         - services-foo-secret-enc-yaml
+///     last_sync_time:      2022-03-03T20:37:59+00:00
+///     observed_generation: 1
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema, Default, PartialEq)]
+pub struct DecryptorSetStatus {
+    pub decryptors: Option<Vec<String>>,
+    pub last_sync_time: Option<String>,
+    pub error_message: Option<String>,
+    pub observed_generation: Option<i64>
+}
+
+/// Schema of an object which can contain any fields
+///
+/// # Arguments
+/// * `_` - &mut SchemaGenerator
+fn preserve_unknown_fields(_: &mut SchemaGenerator) -> Schema {
+    let mut schema = SchemaObject {
+        instance_type: Some(InstanceType::Object.into()),
+        ..Default::default()
+    };
+    schema.extensions.insert(PRESERVE_UNKNOWN_FIELDS.to_owned(), serde_json::Value::Bool(true));
+
+    Schema::Object(schema)
+}
+
+/// Get the name of a generated Decryptor from the name of the DecryptorSet and the path of the file.
+/// The name is suffixed with a hash of the path as different paths may be normalized to the same name.
+/// The name is also used as a label value and is therefore limited to 63 characters
+///
+/// # Arguments
+/// * `set` - &str
+/// * `file` - &str
+fn get_child_name(set: &str, file: &str) -> String {
+    let normalized: String = file.to_lowercase()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect();
+
+    let normalized = normalized.split('-')
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("-");
+
+    let hash = get_short_hash(file);
+    let mut name = format!("{set}-{normalized}");
+    name.truncate(MAX_LABEL_LENGTH - hash.len() - 1);
+
+    format!("{}-{hash}", name.trim_end_matches('-'))
+}
+
+impl DecryptorSetStatus {
+    /// Create a new status from the generated Decryptors. The Decryptors which could not be generated
+    /// are reported in the error message
+    ///
+    /// # Arguments
+    /// * `decryptors` - Vec<String>
+    /// * `failures` - Vec<String>
+    pub fn synced(decryptors: Vec<String>, failures: Vec<String>) -> Self {
+        DecryptorSetStatus {
+            decryptors: Some(decryptors),
+            last_sync_time: Some(Utc::now().to_rfc3339()),
+            error_message: (!failures.is_empty()).then(|| failures.join(", ")),
+            ..Default::default()
+        }
+    }
+
+    /// Create a new status with an error. The generated Decryptors are kept
+    ///
+    /// # Arguments
+    /// * `prev` - Option<&DecryptorSetStatus>
+    /// * `err` - String
+    pub fn failed(prev: Option<&DecryptorSetStatus>, err: String) -> Self {
+        DecryptorSetStatus {
+            error_message: Some(err),
+            ..prev.cloned().unwrap_or_default()
+        }
+    }
+}
+
+impl DecryptorSet {
+    /// Get the name and the namespace of the DecryptorSet
+    ///
+    /// # Arguments
+    /// * `&self` - &Self
+    pub fn get_metadata_info(&self) -> Result<(String, String), Error> {
+        let name = self.metadata.name.to_owned()
+            .ok_or_else(|| Error::MissingMetadata("name".to_owned()))?;
+        let ns = self.metadata.namespace.to_owned()
+            .unwrap_or_else(|| DEFAULT_NAMESPACE.to_owned());
+
+        Ok((name, ns))
+    }
+
+    /// Check whenever the current generation of the DecryptorSet has already been reconciled
+    ///
+    /// # Arguments
+    /// * `&self` - &Self
+    pub fn is_reconciled(&self) -> bool {
+        let observed = self.status.as_ref().and_then(|st| st.observed_generation);
+        observed.is_some() && observed == self.metadata.generation
+    }
+
+    /// Get the repository of the generator alongside the revision of the repository
+    ///
+    /// # Arguments
+    /// * `&self` - &Self
+    /// * `ns` - &str
+    pub async fn get_repository(&self, ns: &str) -> Result<(Repository, Option<String>), Error> {
+        let generator = &self.spec.generator;
+
+        git_repository::resolve_repository(
+            generator.repository.as_ref(),
+            generator.repository_ref.as_ref(),
+            ns
+        ).await
+    }
+
+    /// Get the name and the spec of the Decryptors to generate for the matching files
+    ///
+    /// # Arguments
+    /// * `&self` - &Self
+    /// * `files` - &[String]
+    pub fn get_children(&self, files: &[String]) -> Result<Vec<(String, DecryptorSpec)>, Error> {
+        let (name, _) = self.get_metadata_info()?;
+        let generator = &self.spec.generator;
+
+        let mut children = Vec::new();
+        for file in files {
+            let mut spec = self.spec.template.spec.to_owned()
+                .unwrap_or_else(|| serde_json::json!({}));
+
+            spec["source"] = serde_json::json!({
+                "repository": generator.repository,
+                "repositoryRef": generator.repository_ref,
+                "fileToDecrypt": file,
+                "sopsPath": generator.sops_path
+            });
+
+            let spec: DecryptorSpec = serde_json::from_value(spec)?;
+            children.push((get_child_name(&name, file), spec));
+        }
+
+        Ok(children)
+    }
+
+    /// Update the status of the DecryptorSet
+    ///
+    /// # Arguments
+    /// * `&mut self` - &mut Self
+    /// * `mut status` - DecryptorSetStatus
+    pub async fn update_status(&mut self, mut status: DecryptorSetStatus) -> Result<(), Error> {
+        let (name, ns) = self.get_metadata_info()?;
+        status.observed_generation = self.metadata.generation;
+
+        let client = Client::try_default().await?;
+        let api = Api::<DecryptorSet>::namespaced(client, &ns);
+        let patch = serde_json::json!({
+            "status": status
+        });
+
+        api.patch_status(&name, &PatchParams::default(), &Patch::Merge(&patch)).await?;
+        self.status = Some(status);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kube::core::ObjectMeta;
+
+    fn get_decryptor_set(template: serde_json::Value) -> DecryptorSet {
+        DecryptorSet {
+            metadata: ObjectMeta {
+                name: Some("services".to_owned()),
+                ..Default::default()
+            },
+            spec: DecryptorSetSpec {
+                generator: GitGenerator {
+                    repository: None,
+                    repository_ref: Some(RepositoryRef { name: "secrets".to_owned() }),
+                    path: "services/**/secret.enc.yaml".to_owned(),
+                    sops_path: ".sops.yaml".to_owned()
+                },
+                template: DecryptorTemplate {
+                    spec: Some(template)
+                }
+            },
+            status: None
+        }
+    }
+
+    #[test]
+    fn expect_to_get_child_name() {
+        let name = get_child_name("services", "services/Foo/secret.enc.yaml");
+        assert_eq!(name, format!("services-services-foo-secret-enc-yaml-{}", get_short_hash("services/Foo/secret.enc.yaml")));
+    }
+
+    #[test]
+    fn expect_child_names_to_be_unique_and_bounded() {
+        let underscore = get_child_name("services", "a/foo_bar.yaml");
+        let dash = get_child_name("services", "a/foo-bar.yaml");
+        assert_ne!(underscore, dash);
+
+        let prefix = "a/".repeat(200);
+        let first = get_child_name("services", &format!("{prefix}foo.yaml"));
+        let second = get_child_name("services", &format!("{prefix}bar.yaml"));
+        assert_ne!(first, second);
+        assert!(first.len() <= MAX_LABEL_LENGTH);
+        assert!(second.len() <= MAX_LABEL_LENGTH);
+    }
+
+    #[test]
+    fn expect_to_get_children() {
+        let set = get_decryptor_set(serde_json::json!({
+            "fieldManager": "jiemi-set"
+        }));
+
+        let files = vec!["services/foo/secret.enc.yaml".to_owned()];
+        let children = set.get_children(&files).unwrap();
+
+        assert_eq!(children.len(), 1);
+        let (name, spec) = &children[0];
+        assert_eq!(name, &get_child_name("services", "services/foo/secret.enc.yaml"));
+        assert_eq!(spec.source.file_to_decrypt, "services/foo/secret.enc.yaml");
+        assert_eq!(spec.source.repository_ref.as_ref().unwrap().name, "secrets");
+        assert_eq!(spec.field_manager.as_deref(), Some("jiemi-set"));
+    }
+
+    #[test]
+    fn expect_invalid_template_to_return_err() {
+        let set = get_decryptor_set(serde_json::json!({
+            "fieldManager": 1
+        }));
+
+        let res = set.get_children(&["foo.enc.yaml".to_owned()]);
+        assert!(res.is_err());
+    }
+}
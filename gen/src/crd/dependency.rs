@@ -0,0 +1,26 @@
+use schemars::JsonSchema;
+use serde::{Serialize, Deserialize};
+
+/// DependencyRef reference a Decryptor which needs to be Ready before the Decryptor is applied.
+/// The namespace of the Decryptor is used when the namespace isn't specified
+///
+/// # Example
+/// dependsOn:
+///   - name: namespaces
+///     namespace: jiemi
+#[derive(Debug, JsonSchema, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DependencyRef {
+    pub name: String,
+    pub namespace: Option<String>
+}
+
+impl DependencyRef {
+    /// Get the namespace of the referenced Decryptor
+    ///
+    /// # Arguments
+    /// * `&self` - &Self
+    /// * `ns` - &str
+    pub fn get_namespace(&self, ns: &str) -> String {
+        self.namespace.to_owned().unwrap_or_else(|| ns.to_owned())
+    }
+}
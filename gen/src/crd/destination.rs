@@ -0,0 +1,44 @@
+use schemars::JsonSchema;
+use serde::{Serialize, Deserialize};
+
+// Constant
+const DEFAULT_KUBECONFIG_KEY: &str = "value";
+
+/// Destination is the cluster where the decrypted file is applied. The cluster where jiemi
+/// is running is used when no destination is specified
+///
+/// # Example
+/// destination:
+///   kubeConfig:
+///     secretRef:
+///       name: prod-kubeconfig
+///       key: value
+#[derive(Debug, JsonSchema, Clone, Serialize, Deserialize, Default)]
+pub struct Destination {
+    #[serde(rename = "kubeConfig")]
+    pub kube_config: Option<KubeConfig>
+}
+
+/// KubeConfig reference a Secret, in the namespace of the Decryptor, which contains the kubeconfig of the cluster
+#[derive(Debug, JsonSchema, Clone, Serialize, Deserialize, Default)]
+pub struct KubeConfig {
+    #[serde(rename = "secretRef")]
+    pub secret_ref: SecretRef
+}
+
+#[derive(Debug, JsonSchema, Clone, Serialize, Deserialize, Default)]
+pub struct SecretRef {
+    pub name: String,
+    pub key: Option<String>
+}
+
+impl SecretRef {
+    /// Get the key of the Secret which contains the kubeconfig. Default to value
+    ///
+    /// # Arguments
+    /// * `&self` - &Self
+    pub fn get_key(&self) -> String {
+        self.key.to_owned()
+            .unwrap_or_else(|| DEFAULT_KUBECONFIG_KEY.to_owned())
+    }
+}
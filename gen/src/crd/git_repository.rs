@@ -0,0 +1,255 @@
+use schemars::JsonSchema;
+use serde::{Serialize, Deserialize};
+use chrono::{TimeZone, Utc};
+use kube::{
+    CustomResource,
+    Client,
+    Api,
+    api::{Patch, PatchParams},
+};
+use super::repo::{Repository, RepositoryCredentials};
+use crate::err::Error;
+use crate::util;
+
+// Constant
+const DEFAULT_NAMESPACE: &str = "default";
+const MISSING_REPOSITORY_ERR: &str = "Either the repository or the repositoryRef needs to be specified";
+
+/// GitRepository holds the settings of a repository which can be referenced by the Decryptors of the namespace.
+/// The revision can be a branch, a tag or a commit. The default branch of the repository is used otherwise
+///
+/// # Example
+/// apiVersion: jiemi.cr/v1alpha1
+/// kind: GitRepository
+/// metadata:
+///   name: secrets
+/// spec:
+///   url: https://github.com/foo/secrets.git
+///   revision: main
+#[derive(Debug, CustomResource, Serialize, Deserialize, Clone, JsonSchema)]
+#[kube(status = "GitRepositoryStatus")]
+#[kube(group = "jiemi.cr", version = "v1alpha1", kind = "GitRepository", namespaced)]
+pub struct GitRepositorySpec {
+    pub url: String,
+    #[schemars(regex(pattern = r"^[^-\s][^\s]*$"))]
+    pub revision: Option<String>,
+    pub credentials: Option<RepositoryCredentials>
+}
+
+/// Status of the GitRepository. It represents the last fetch of the repository by krapao
+///
+/// # Example
+/// Status:
+///     last_fetched_commit: a888f02e1111beb2c543d729faa5d516ecaa9e12
+///     last_fetch_time:     2022-03-03T20:37:59+00:00
+///     observed_generation: 1
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema, Default, PartialEq)]
+pub struct GitRepositoryStatus {
+    pub last_fetched_commit: Option<String>,
+    pub last_fetch_time: Option<String>,
+    pub error_message: Option<String>,
+    pub observed_generation: Option<i64>
+}
+
+/// RepositoryRef reference a GitRepository in the namespace of the Decryptor
+#[derive(Debug, JsonSchema, Clone, Serialize, Deserialize)]
+pub struct RepositoryRef {
+    pub name: String
+}
+
+/// Resolve the repository alongside the revision of the repository.
+/// The GitRepository referenced by the repositoryRef takes precedence over the repository
+///
+/// # Arguments
+/// * `repository` - Option<&Repository>
+/// * `repository_ref` - Option<&RepositoryRef>
+/// * `ns` - &str
+pub async fn resolve_repository(
+    repository: Option<&Repository>,
+    repository_ref: Option<&RepositoryRef>,
+    ns: &str
+) -> Result<(Repository, Option<String>), Error> {
+    if let Some(repository_ref) = repository_ref {
+        let res = GitRepository::get(repository_ref, ns).await?;
+        return Ok((res.spec.get_repository(), res.spec.get_revision()?));
+    }
+
+    match repository {
+        Some(repository) => Ok((repository.to_owned(), None)),
+        None => Err(Error::Repository(MISSING_REPOSITORY_ERR.to_owned()))
+    }
+}
+
+impl GitRepositoryStatus {
+    /// Create a new status from the last fetch of the repository
+    ///
+    /// # Arguments
+    /// * `commit` - String
+    /// * `fetched_at` - i64
+    pub fn fetched(commit: String, fetched_at: i64) -> Self {
+        GitRepositoryStatus {
+            last_fetched_commit: Some(commit),
+            last_fetch_time: Some(Utc.timestamp(fetched_at, 0).to_rfc3339()),
+            ..Default::default()
+        }
+    }
+
+    /// Create a new status with an error. The last fetch is kept
+    ///
+    /// # Arguments
+    /// * `prev` - Option<&GitRepositoryStatus>
+    /// * `err` - String
+    pub fn failed(prev: Option<&GitRepositoryStatus>, err: String) -> Self {
+        GitRepositoryStatus {
+            error_message: Some(err),
+            ..prev.cloned().unwrap_or_default()
+        }
+    }
+}
+
+impl GitRepositorySpec {
+    /// Get the Repository used to clone the repository
+    ///
+    /// # Arguments
+    /// * `&self` - &Self
+    pub fn get_repository(&self) -> Repository {
+        Repository {
+            url: self.url.to_owned(),
+            credentials: self.credentials.to_owned()
+        }
+    }
+
+    /// Get the revision of the repository. A revision which can't be passed to git is rejected
+    ///
+    /// # Arguments
+    /// * `&self` - &Self
+    pub fn get_revision(&self) -> Result<Option<String>, Error> {
+        match &self.revision {
+            Some(revision) if !util::is_valid_revision(revision) => {
+                Err(Error::Repository(format!("{revision:?} is not a valid revision")))
+            },
+            revision => Ok(revision.to_owned())
+        }
+    }
+}
+
+impl GitRepository {
+    /// Get the name and the namespace of the GitRepository
+    ///
+    /// # Arguments
+    /// * `&self` - &Self
+    pub fn get_metadata_info(&self) -> Result<(String, String), Error> {
+        let name = self.metadata.name.to_owned()
+            .ok_or_else(|| Error::MissingMetadata("name".to_owned()))?;
+        let ns = self.metadata.namespace.to_owned()
+            .unwrap_or_else(|| DEFAULT_NAMESPACE.to_owned());
+
+        Ok((name, ns))
+    }
+
+    /// Check whenever the current generation of the GitRepository has already been reconciled
+    ///
+    /// # Arguments
+    /// * `&self` - &Self
+    pub fn is_reconciled(&self) -> bool {
+        let observed = self.status.as_ref().and_then(|st| st.observed_generation);
+        observed.is_some() && observed == self.metadata.generation
+    }
+
+    /// Check whenever the status of the GitRepository is already set to the given status
+    ///
+    /// # Arguments
+    /// * `&self` - &Self
+    /// * `status` - &GitRepositoryStatus
+    fn has_status(&self, status: &GitRepositoryStatus) -> bool {
+        self.status.as_ref() == Some(status)
+    }
+
+    /// Update the status of the GitRepository. The status is not patched when it did not change
+    ///
+    /// # Arguments
+    /// * `&mut self` - &mut Self
+    /// * `mut status` - GitRepositoryStatus
+    pub async fn update_status(&mut self, mut status: GitRepositoryStatus) -> Result<(), Error> {
+        let (name, ns) = self.get_metadata_info()?;
+        status.observed_generation = self.metadata.generation;
+        if self.has_status(&status) {
+            return Ok(());
+        }
+
+        let client = Client::try_default().await?;
+        let api = Api::<GitRepository>::namespaced(client, &ns);
+        let patch = serde_json::json!({
+            "status": status
+        });
+
+        api.patch_status(&name, &PatchParams::default(), &Patch::Merge(&patch)).await?;
+        self.status = Some(status);
+
+        Ok(())
+    }
+
+    /// Get the GitRepository referenced by the Decryptor
+    ///
+    /// # Arguments
+    /// * `repository_ref` - &RepositoryRef
+    /// * `ns` - &str
+    pub async fn get(repository_ref: &RepositoryRef, ns: &str) -> Result<GitRepository, Error> {
+        let client = Client::try_default().await?;
+        let api = Api::<GitRepository>::namespaced(client, ns);
+        let repository = api.get(&repository_ref.name).await?;
+
+        Ok(repository)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expect_to_reject_revision_as_option() {
+        let mut spec = GitRepositorySpec {
+            url: "https://github.com/foo/secrets.git".to_owned(),
+            revision: Some("main".to_owned()),
+            credentials: None
+        };
+        assert_eq!(spec.get_revision().unwrap().unwrap(), "main");
+
+        spec.revision = Some("--output=/tmp/x".to_owned());
+        assert!(spec.get_revision().is_err());
+    }
+
+    #[test]
+    fn expect_to_create_fetched_status() {
+        let status = GitRepositoryStatus::fetched("foo".to_owned(), 0);
+
+        assert_eq!(status.last_fetched_commit.unwrap(), "foo");
+        assert_eq!(status.last_fetch_time.unwrap(), "1970-01-01T00:00:00+00:00");
+    }
+
+    #[test]
+    fn expect_failed_status_to_keep_last_fetch() {
+        let prev = GitRepositoryStatus::fetched("foo".to_owned(), 0);
+        let status = GitRepositoryStatus::failed(Some(&prev), "bar".to_owned());
+
+        assert_eq!(status.last_fetched_commit.unwrap(), "foo");
+        assert_eq!(status.error_message.unwrap(), "bar");
+    }
+
+    #[test]
+    fn expect_unchanged_status_to_be_detected() {
+        let mut repository = GitRepository::new("repo", GitRepositorySpec {
+            url: "https://github.com/foo/bar.git".to_owned(),
+            revision: None,
+            credentials: None
+        });
+
+        let status = GitRepositoryStatus::fetched("foo".to_owned(), 0);
+        assert!(!repository.has_status(&status));
+
+        repository.status = Some(status.clone());
+        assert!(repository.has_status(&status));
+        assert!(!repository.has_status(&GitRepositoryStatus::fetched("bar".to_owned(), 0)));
+    }
+}
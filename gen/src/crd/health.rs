@@ -0,0 +1,40 @@
+use schemars::JsonSchema;
+use serde::{Serialize, Deserialize};
+
+// Constant
+const DEFAULT_READY_CONDITION: &str = "Ready";
+
+/// HealthCheck reference a resource which is evaluated once the decrypted file has been applied.
+/// Deployment and StatefulSet have a built-in check. Other resources are evaluated by looking
+/// for a readiness condition in their status (default to Ready). Only the referenced resources are
+/// evaluated, the objects applied by the Decryptor need to be listed in order to be assessed
+/// 
+/// # Example
+/// healthChecks:
+///   - apiVersion: apps/v1
+///     kind: Deployment
+///     name: app
+///   - apiVersion: cert-manager.io/v1
+///     kind: Certificate
+///     name: app-tls
+///     condition: Ready
+#[derive(Debug, JsonSchema, Clone, Serialize, Deserialize, Default)]
+pub struct HealthCheck {
+    #[serde(rename = "apiVersion")]
+    pub api_version: String,
+    pub kind: String,
+    pub name: String,
+    pub namespace: Option<String>,
+    pub condition: Option<String>
+}
+
+impl HealthCheck {
+    /// Get the readiness condition used to evaluate generic resources
+    /// 
+    /// # Arguments
+    /// * `&self` - &Self
+    pub fn get_condition(&self) -> String {
+        self.condition.to_owned()
+            .unwrap_or_else(|| DEFAULT_READY_CONDITION.to_owned())
+    }
+}
@@ -1,7 +1,6 @@
-use std::collections::VecDeque;
-
 use schemars::JsonSchema;
 use serde::{Serialize, Deserialize};
+use chrono::Utc;
 use kube::{
     CustomResource,
     CustomResourceExt,
@@ -11,13 +10,22 @@ use kube::{
 };
 use status::DecryptorStatus;
 use crate::err::Error;
+use crate::util;
 use provider::AsyncTryFrom;
-use self::status::Status;
 
 pub mod status;
 pub mod repo;
 pub mod provider;
 pub mod secret;
+pub mod health;
+pub mod output;
+pub mod post_render;
+pub mod destination;
+pub mod provider_ref;
+pub mod git_repository;
+pub mod decryptor_set;
+pub mod dependency;
+pub mod revision;
 
 // Constant
 const DEFAULT_NAMESPACE: &str = "default";
@@ -29,11 +37,39 @@ const DEFAULT_NAMESPACE: &str = "default";
 #[kube(status = "DecryptorStatus")]
 #[kube(group = "jiemi.cr", version = "v1alpha1", kind = "Decryptor", namespaced)]
 pub struct DecryptorSpec {
+    #[serde(default)]
     pub provider: Provider,
-    pub source: Source
+    #[serde(rename = "providerRef")]
+    pub provider_ref: Option<provider_ref::ProviderRef>,
+    pub source: Source,
+    pub rollback: Option<Rollback>,
+    /// Resources whose health is evaluated once the decrypted file has been applied. The health is opt-in:
+    /// only the listed resources are evaluated, the objects applied by the Decryptor are not assessed by default
+    #[serde(rename = "healthChecks")]
+    pub health_checks: Option<Vec<health::HealthCheck>>,
+    #[serde(rename = "conflictPolicy")]
+    pub conflict_policy: Option<ConflictPolicy>,
+    #[serde(rename = "fieldManager")]
+    pub field_manager: Option<String>,
+    #[serde(rename = "allowCrossNamespace")]
+    pub allow_cross_namespace: Option<bool>,
+    pub output: Option<output::Output>,
+    #[serde(rename = "postRender")]
+    pub post_render: Option<post_render::PostRender>,
+    pub destination: Option<destination::Destination>,
+    #[serde(rename = "serviceAccountName")]
+    pub service_account_name: Option<String>,
+    #[serde(rename = "dependsOn")]
+    pub depends_on: Option<Vec<dependency::DependencyRef>>,
+    /// Delete and re-create the objects whose immutable fields changed. The object keeps its name hence the
+    /// workloads which reference it are not rolled out and it's missing between the delete and the create
+    #[serde(rename = "replaceOnImmutableChange")]
+    pub replace_on_immutable_change: Option<bool>,
+    #[serde(rename = "revisionHistoryLimit")]
+    pub revision_history_limit: Option<u32>
 }
 
-#[derive(Debug, JsonSchema, Clone, Serialize, Deserialize)]
+#[derive(Debug, JsonSchema, Clone, Serialize, Deserialize, Default)]
 pub struct Provider {
     gcp: Option<provider::GcpCredentials>,
     aws: Option<provider::AwsCredentials>,
@@ -44,19 +80,51 @@ pub struct Provider {
 
 #[derive(Debug, JsonSchema, Clone, Serialize, Deserialize)]
 pub struct Source {
-    pub repository: repo::Repository,
+    pub repository: Option<repo::Repository>,
+    #[serde(rename = "repositoryRef")]
+    pub repository_ref: Option<git_repository::RepositoryRef>,
     #[serde(rename = "fileToDecrypt")]
     pub file_to_decrypt: String,
     #[serde(rename = "sopsPath")]
     pub sops_path: String
 }
 
-/// Generate a CRD which is used to be applied in a Kubernetes cluster
+/// Rollback allows to pin the Decryptor to a previous revision. The revision can either be
+/// a commit sha or the id of a synchronization which is recorded by a DecryptorRevision
+#[derive(Debug, JsonSchema, Clone, Serialize, Deserialize, Default)]
+pub struct Rollback {
+    #[serde(rename = "toRevision")]
+    #[schemars(regex(pattern = r"^[^-\s][^\s]*$"))]
+    pub to_revision: Option<String>,
+    #[serde(rename = "toId")]
+    pub to_id: Option<u64>
+}
+
+/// ConflictPolicy define how field ownership conflicts with other managers (helm, kubectl...) are handled
+///     - Force: take over the fields owned by other managers
+///     - Fail: report the conflict in the status of the Decryptor
+///     - Skip: do not apply the object which has a conflict
+#[derive(Debug, JsonSchema, Clone, Serialize, Deserialize, PartialEq, Default)]
+pub enum ConflictPolicy {
+    #[default]
+    Force,
+    Fail,
+    Skip
+}
+
+/// Generate the CRDs which are used to be applied in a Kubernetes cluster
 ///     The final example of how the crd looks can be founded on the example folder
 pub fn generate_crd() -> Result<String, Box<dyn std::error::Error>> {
-    let res = serde_yaml::to_string(&Decryptor::crd())?;
-
-    Ok(res)
+    let crds = [
+        serde_yaml::to_string(&Decryptor::crd())?,
+        serde_yaml::to_string(&provider_ref::DecryptionProvider::crd())?,
+        serde_yaml::to_string(&provider_ref::ClusterDecryptionProvider::crd())?,
+        serde_yaml::to_string(&git_repository::GitRepository::crd())?,
+        serde_yaml::to_string(&decryptor_set::DecryptorSet::crd())?,
+        serde_yaml::to_string(&revision::DecryptorRevision::crd())?
+    ];
+
+    Ok(crds.concat())
 }
 
 impl Provider {
@@ -91,6 +159,38 @@ impl Provider {
     }
 }
 
+impl DecryptorSpec {
+    /// Get the credentials of the provider. The provider referenced by the providerRef
+    /// takes precedence over the provider section
+    /// 
+    /// # Arguments
+    /// * `&self` - &Self
+    /// * `ns` - &str
+    pub async fn get_credentials(&self, ns: &str) -> Result<provider::ProviderList, Error> {
+        match &self.provider_ref {
+            Some(provider_ref) => {
+                let (provider, secret_ns) = provider_ref.get_provider(ns).await?;
+                provider.get_credentials(&secret_ns).await
+            },
+            None => self.provider.get_credentials(ns).await
+        }
+    }
+
+    /// Get the repository of the Decryptor alongside the revision of the repository.
+    /// The GitRepository referenced by the repositoryRef takes precedence over the repository section
+    /// 
+    /// # Arguments
+    /// * `&self` - &Self
+    /// * `ns` - &str
+    pub async fn get_repository(&self, ns: &str) -> Result<(repo::Repository, Option<String>), Error> {
+        git_repository::resolve_repository(
+            self.source.repository.as_ref(),
+            self.source.repository_ref.as_ref(),
+            ns
+        ).await
+    }
+}
+
 impl Decryptor {
     /// Get the metadata info needed to perform some operation on the crd
     /// 
@@ -115,16 +215,84 @@ impl Decryptor {
     /// * `&mut self` - &Self
     /// * `status` - DecryptorStatus
     pub fn set_status(&mut self, mut status: DecryptorStatus) {
-        // get a new history of the status
-        let (history, current_status_id) = self.create_new_status_history();
-        status.history = history;
+        // the history of the statuses is recorded by the DecryptorRevision objects
+        status.current.id = self.status.as_ref()
+            .map(|prev| prev.current.id)
+            .unwrap_or_default() + 1;
         // set other field which come from the decryptor
-        status.current.id = current_status_id + 1;
         status.current.file_to_decrypt = self.spec.source.file_to_decrypt.to_owned();
+        // keep track of the rollback in order to show that the decryptor is pinned to a revision
+        status.pinned_revision = self.get_pinned_revision(&status.current.revision);
+        status.observed_generation = self.metadata.generation;
+        // conditions which are not part of the new status are kept
+        // the history is kept until it's migrated to the DecryptorRevision objects
+        if let Some(prev) = self.status.as_ref() {
+            status.merge_conditions(prev.conditions.to_owned());
+            status.history = prev.history.to_owned();
+        }
         
         self.status = Some(status);
     }
 
+    /// Check whenever the current generation of the Decryptor has been processed
+    /// 
+    /// # Arguments
+    /// * `&self` - &Self
+    pub fn is_observed(&self) -> bool {
+        let observed = self.status.as_ref().and_then(|st| st.observed_generation);
+        observed.is_some() && observed == self.metadata.generation
+    }
+
+    /// Check whenever the Decryptor is Ready at its current generation. A Decryptor is Ready when the
+    /// decrypted file has been synced and the resources referenced by the health checks are healthy
+    /// 
+    /// # Arguments
+    /// * `&self` - &Self
+    pub fn is_ready(&self) -> bool {
+        let status = match &self.status {
+            Some(status) if self.is_observed() => status,
+            _ => return false
+        };
+
+        let ready = status.get_condition(status::READY_CONDITION)
+            .map(|c| c.status == status::CONDITION_TRUE)
+            .unwrap_or_default();
+        let healthy = status.get_condition(status::HEALTHY_CONDITION)
+            .map(|c| c.status == status::CONDITION_TRUE)
+            .unwrap_or(true);
+
+        ready && healthy
+    }
+
+    /// Check whenever the retries of the Decryptor are delayed after consecutive failures.
+    /// The delay is ignored when the spec of the Decryptor or the commit of the repository changed
+    /// 
+    /// # Arguments
+    /// * `&self` - &Self
+    /// * `commit` - Option<&str>
+    pub fn is_backing_off(&self, commit: Option<&str>) -> bool {
+        let status = match &self.status {
+            Some(status) if self.is_observed() => status,
+            _ => return false
+        };
+
+        if status.failed_commit.as_deref() != commit {
+            return false;
+        }
+
+        matches!(status.get_next_retry(), Some(date) if date > Utc::now())
+    }
+
+    /// Check whenever the last synchronization of the Decryptor failed and needs to be retried
+    /// 
+    /// # Arguments
+    /// * `&self` - &Self
+    pub fn has_failed(&self) -> bool {
+        self.status.as_ref()
+            .map(|status| status.next_retry_at.is_some())
+            .unwrap_or_default()
+    }
+
     /// Update the status of the Decrytpro
     /// 
     /// # Arguments
@@ -147,18 +315,80 @@ impl Decryptor {
         Ok(())
     }
 
-    /// Create a new status history by moving the current status to the list of history of statuses
+    /// Get the revision targeted by the rollback field of the spec if any.
+    /// When the rollback use an id, the revision is retrieved from the status or from
+    /// the DecryptorRevision of the synchronization
     /// 
     /// # Arguments
     /// * `&self` - &Self
-    pub fn create_new_status_history(&self) -> (Option<VecDeque<Status>>, u64) {
-        match self.status.to_owned() {
-            Some(mut prev) => {
-                prev.add_current_to_history();
-                (prev.history, prev.current.id)
-            },
-            None => (Some(VecDeque::new()), 0_u64)
+    pub async fn get_rollback_revision(&self) -> Result<Option<String>, Error> {
+        // the DecryptorRevision is only fetched when the synchronization is not part of the status
+        let missing_id = self.spec.rollback.as_ref()
+            .filter(|rollback| rollback.to_revision.is_none())
+            .and_then(|rollback| rollback.to_id)
+            .filter(|id| self.status.as_ref().and_then(|st| st.get_status_by_id(*id)).is_none());
+
+        let mut revisions = Vec::new();
+        if let Some(id) = missing_id {
+            let (name, _, ns) = self.get_metadata_info()?;
+            let uid = self.metadata.uid.to_owned()
+                .ok_or_else(|| Error::MissingMetadata("uid".to_owned()))?;
+            revisions.extend(revision::get_decryptor_revision(&name, &uid, &ns, id).await?);
         }
+
+        self.get_rollback_revision_from(&revisions)
+    }
+
+    /// Get the revision targeted by the rollback field of the spec from the status or the
+    /// DecryptorRevision of the Decryptor
+    /// 
+    /// # Arguments
+    /// * `&self` - &Self
+    /// * `revisions` - &[DecryptorRevision]
+    pub fn get_rollback_revision_from(&self, revisions: &[revision::DecryptorRevision]) -> Result<Option<String>, Error> {
+        let rollback = match &self.spec.rollback {
+            Some(rollback) => rollback,
+            None => return Ok(None)
+        };
+
+        if let Some(revision) = rollback.to_revision.to_owned() {
+            if !util::is_valid_revision(&revision) {
+                return Err(Error::Rollback(format!("{revision:?} is not a valid revision")));
+            }
+
+            return Ok(Some(revision));
+        }
+
+        if let Some(id) = rollback.to_id {
+            if let Some(status) = self.status.as_ref().and_then(|st| st.get_status_by_id(id)) {
+                return Ok(Some(status.revision.to_owned()));
+            }
+
+            let revision = revisions.iter()
+                .find(|revision| revision.spec.id == id)
+                .ok_or_else(|| Error::Rollback(format!("id {id} could not be founded in the revision history")))?;
+
+            return Ok(Some(revision.spec.revision.to_owned()));
+        }
+
+        Ok(None)
+    }
+
+    /// Get the revision the Decryptor is pinned to by the rollback field. The rendered revision
+    /// is used when the rollback targets the id of a synchronization
+    /// 
+    /// # Arguments
+    /// * `&self` - &Self
+    /// * `rendered` - &str
+    fn get_pinned_revision(&self, rendered: &str) -> Option<String> {
+        let rollback = self.spec.rollback.as_ref()?;
+        if let Some(revision) = rollback.to_revision.to_owned() {
+            return Some(revision);
+        }
+
+        rollback.to_id
+            .filter(|_| !rendered.is_empty())
+            .map(|_| rendered.to_owned())
     }
 }
 
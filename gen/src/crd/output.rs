@@ -0,0 +1,100 @@
+use std::collections::BTreeMap;
+use schemars::JsonSchema;
+use serde::{Serialize, Deserialize};
+
+// Constant
+const DOTENV_EXTENSION: &str = ".env";
+const JSON_EXTENSION: &str = ".json";
+
+/// Output allows to generate Kubernetes objects from a decrypted file which is not a Kubernetes manifest
+#[derive(Debug, JsonSchema, Clone, Serialize, Deserialize, Default)]
+pub struct Output {
+    pub secret: Option<SecretOutput>
+}
+
+/// SecretOutput generate a Secret from a decrypted key / value document (YAML, JSON or dotenv).
+/// By default each top level key of the document is a key of the Secret. The mapping allows
+/// to pick values with a JSON path instead
+///
+/// # Example
+/// output:
+///   secret:
+///     name: app-secret
+///     type: Opaque
+///     labels:
+///       app: foo
+///     mapping:
+///       - path: $.database.password
+///         key: DB_PASSWORD
+#[derive(Debug, JsonSchema, Clone, Serialize, Deserialize, Default)]
+pub struct SecretOutput {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub type_: Option<String>,
+    pub labels: Option<BTreeMap<String, String>>,
+    pub format: Option<DocumentFormat>,
+    pub mapping: Option<Vec<KeyMapping>>
+}
+
+/// KeyMapping map a value of the decrypted document to a key of the Secret
+#[derive(Debug, JsonSchema, Clone, Serialize, Deserialize, Default)]
+pub struct KeyMapping {
+    pub path: String,
+    pub key: String
+}
+
+/// DocumentFormat of the decrypted file
+#[derive(Debug, JsonSchema, Clone, Serialize, Deserialize, PartialEq)]
+pub enum DocumentFormat {
+    Yaml,
+    Json,
+    Dotenv
+}
+
+impl SecretOutput {
+    /// Get the format of the decrypted document. When the format isn't specified
+    /// the format is guessed from the extension of the file
+    ///
+    /// # Arguments
+    /// * `&self` - &Self
+    /// * `file` - &str
+    pub fn get_format(&self, file: &str) -> DocumentFormat {
+        if let Some(format) = self.format.to_owned() {
+            return format;
+        }
+
+        if file.ends_with(DOTENV_EXTENSION) {
+            return DocumentFormat::Dotenv;
+        }
+
+        if file.ends_with(JSON_EXTENSION) {
+            return DocumentFormat::Json;
+        }
+
+        DocumentFormat::Yaml
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expect_to_guess_format_from_file() {
+        let output = SecretOutput::default();
+
+        assert_eq!(output.get_format("secrets/app.env"), DocumentFormat::Dotenv);
+        assert_eq!(output.get_format("secrets/app.enc.json"), DocumentFormat::Json);
+        assert_eq!(output.get_format("secrets/app.yaml"), DocumentFormat::Yaml);
+    }
+
+    #[test]
+    fn expect_to_use_specified_format() {
+        let output = SecretOutput {
+            format: Some(DocumentFormat::Dotenv),
+            ..Default::default()
+        };
+
+        assert_eq!(output.get_format("secrets/app.yaml"), DocumentFormat::Dotenv);
+    }
+}
@@ -0,0 +1,98 @@
+use std::collections::BTreeMap;
+use schemars::JsonSchema;
+use serde::{Serialize, Deserialize};
+use kube::{Client, Api};
+use k8s_openapi::api::core::v1::{ConfigMap, Secret};
+use crate::util;
+use crate::err::Error;
+
+/// PostRender contains the operations applied on the decrypted file before it's applied on the cluster
+#[derive(Debug, JsonSchema, Clone, Serialize, Deserialize, Default)]
+pub struct PostRender {
+    pub substitute: Option<Substitute>
+}
+
+/// Substitute replace the ${VAR} of the decrypted file with the variables. Variables are loaded
+/// from the ConfigMaps and Secrets in the order of the list. Inline variables take precedence
+/// over the referenced ones
+///
+/// # Example
+/// postRender:
+///   substitute:
+///     variables:
+///       CLUSTER_NAME: prod
+///     from:
+///       - kind: ConfigMap
+///         name: cluster-vars
+#[derive(Debug, JsonSchema, Clone, Serialize, Deserialize, Default)]
+pub struct Substitute {
+    pub variables: Option<BTreeMap<String, String>>,
+    pub from: Option<Vec<SubstituteFrom>>
+}
+
+/// SubstituteFrom reference a ConfigMap or a Secret in the namespace of the Decryptor
+#[derive(Debug, JsonSchema, Clone, Serialize, Deserialize)]
+pub struct SubstituteFrom {
+    pub kind: SubstituteKind,
+    pub name: String
+}
+
+#[derive(Debug, JsonSchema, Clone, Serialize, Deserialize, PartialEq)]
+pub enum SubstituteKind {
+    ConfigMap,
+    Secret
+}
+
+impl SubstituteFrom {
+    /// Get the variables contained in the referenced ConfigMap or Secret
+    ///
+    /// # Arguments
+    /// * `&self` - &Self
+    /// * `client` - &Client
+    /// * `ns` - &str
+    async fn get_variables(&self, client: &Client, ns: &str) -> Result<BTreeMap<String, String>, Error> {
+        match self.kind {
+            SubstituteKind::ConfigMap => {
+                let api: Api<ConfigMap> = Api::namespaced(client.clone(), ns);
+                let configmap = api.get(&self.name).await?;
+
+                Ok(configmap.data.unwrap_or_default())
+            },
+            SubstituteKind::Secret => {
+                let api: Api<Secret> = Api::namespaced(client.clone(), ns);
+                let secret = api.get(&self.name).await?;
+
+                let mut variables = BTreeMap::new();
+                for (key, value) in secret.data.unwrap_or_default() {
+                    variables.insert(key, util::decode_byte(&value)?);
+                }
+
+                Ok(variables)
+            }
+        }
+    }
+}
+
+impl Substitute {
+    /// Get the variables used to substitute the decrypted file
+    ///
+    /// # Arguments
+    /// * `&self` - &Self
+    /// * `client` - &Client
+    /// * `ns` - &str
+    pub async fn get_variables(&self, client: &Client, ns: &str) -> Result<BTreeMap<String, String>, Error> {
+        let mut variables = BTreeMap::new();
+        if let Some(from) = &self.from {
+            for reference in from {
+                variables.extend(reference.get_variables(client, ns).await?);
+            }
+        }
+
+        if let Some(inline) = self.variables.to_owned() {
+            variables.extend(inline);
+        }
+
+        Ok(variables)
+    }
+}
+
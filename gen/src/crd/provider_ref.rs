@@ -0,0 +1,71 @@
+use schemars::JsonSchema;
+use serde::{Serialize, Deserialize};
+use kube::{
+    CustomResource,
+    Client,
+    Api
+};
+use super::Provider;
+use crate::err::Error;
+
+/// DecryptionProvider holds the credentials of a provider which can be shared by the Decryptors
+/// of the namespace. Secrets referenced by the provider are retrieved in the namespace of the DecryptionProvider
+#[derive(Debug, CustomResource, Serialize, Deserialize, Clone, JsonSchema)]
+#[kube(group = "jiemi.cr", version = "v1alpha1", kind = "DecryptionProvider", namespaced)]
+pub struct DecryptionProviderSpec {
+    pub provider: Provider
+}
+
+/// ClusterDecryptionProvider holds the credentials of a provider which can be shared by the Decryptors
+/// of every namespaces. Secrets referenced by the provider are retrieved in the secretNamespace.
+/// Retrieving the provider requires a cluster wide permission, even when the watched namespaces are restricted
+#[derive(Debug, CustomResource, Serialize, Deserialize, Clone, JsonSchema)]
+#[kube(group = "jiemi.cr", version = "v1alpha1", kind = "ClusterDecryptionProvider")]
+pub struct ClusterDecryptionProviderSpec {
+    pub provider: Provider,
+    #[serde(rename = "secretNamespace")]
+    pub secret_namespace: String
+}
+
+/// ProviderRef reference a DecryptionProvider in the namespace of the Decryptor or a ClusterDecryptionProvider
+///
+/// # Example
+/// providerRef:
+///   kind: ClusterDecryptionProvider
+///   name: gcp-kms
+#[derive(Debug, JsonSchema, Clone, Serialize, Deserialize)]
+pub struct ProviderRef {
+    pub kind: ProviderKind,
+    pub name: String
+}
+
+#[derive(Debug, JsonSchema, Clone, Serialize, Deserialize, PartialEq)]
+pub enum ProviderKind {
+    DecryptionProvider,
+    ClusterDecryptionProvider
+}
+
+impl ProviderRef {
+    /// Get the referenced provider alongside the namespace where the Secrets of the provider are retrieved
+    ///
+    /// # Arguments
+    /// * `&self` - &Self
+    /// * `ns` - &str
+    pub async fn get_provider(&self, ns: &str) -> Result<(Provider, String), Error> {
+        let client = Client::try_default().await?;
+        match self.kind {
+            ProviderKind::DecryptionProvider => {
+                let api: Api<DecryptionProvider> = Api::namespaced(client, ns);
+                let res = api.get(&self.name).await?;
+
+                Ok((res.spec.provider, ns.to_owned()))
+            },
+            ProviderKind::ClusterDecryptionProvider => {
+                let api: Api<ClusterDecryptionProvider> = Api::all(client);
+                let res = api.get(&self.name).await?;
+
+                Ok((res.spec.provider, res.spec.secret_namespace))
+            }
+        }
+    }
+}
@@ -0,0 +1,180 @@
+// This mod is used to store the history of the synchronizations of a Decryptor. Each synchronization is recorded
+// in a DecryptorRevision owned by the Decryptor which keeps the status of the Decryptor small
+use schemars::JsonSchema;
+use serde::{Serialize, Deserialize};
+use kube::{
+    CustomResource,
+    Client,
+    Api,
+    Error as KubeError
+};
+use super::status::{Status, SyncStatus, Reason, CommitInfo};
+use crate::err::Error;
+use crate::util::get_short_hash;
+
+// Constant
+pub const DECRYPTOR_LABEL: &str = "jiemi.cr/decryptor";
+pub const DEFAULT_REVISION_HISTORY_LIMIT: u32 = 10;
+const NOT_FOUND_CODE: u16 = 404;
+const MAX_NAME_LENGTH: usize = 253;
+
+/// DecryptorRevision records a synchronization of a Decryptor. The name of a revision is the name
+/// of the Decryptor followed by a hash of its uid and the id of the synchronization
+///
+/// # Example
+/// apiVersion: jiemi.cr/v1alpha1
+/// kind: DecryptorRevision
+/// metadata:
+///   name: pgp-decryptor-5c3f1a2b4d-2
+/// spec:
+///   decryptor: pgp-decryptor
+///   id: 2
+///   revision: a888f02e1111beb2c543d729faa5d516ecaa9e12
+///   fileToDecrypt: pgp/secret.enc.yaml
+///   deployedAt: 2022-03-03T20:37:59.024362965+00:00
+///   result: Sync
+///   trigger: Spec
+///   triggeredBy: kubectl-client-side-apply
+#[derive(Debug, CustomResource, Serialize, Deserialize, Clone, JsonSchema)]
+#[kube(group = "jiemi.cr", version = "v1alpha1", kind = "DecryptorRevision", namespaced)]
+pub struct DecryptorRevisionSpec {
+    pub decryptor: String,
+    pub id: u64,
+    pub revision: String,
+    #[serde(rename = "fileToDecrypt")]
+    pub file_to_decrypt: String,
+    #[serde(rename = "deployedAt")]
+    pub deployed_at: String,
+    pub result: SyncStatus,
+    #[serde(rename = "errorMessage")]
+    pub error_message: Option<String>,
+    pub reason: Option<Reason>,
+    pub commit: Option<CommitInfo>,
+    pub trigger: Trigger,
+    #[serde(rename = "triggeredBy")]
+    pub triggered_by: Option<String>
+}
+
+/// Trigger represents what caused the synchronization of the Decryptor
+///     - Spec: a new generation of the Decryptor
+///     - Commit: a new commit in the repository
+///     - Retry: a retry of a failed synchronization
+///     - Migration: a synchronization recorded in the history of the status before the DecryptorRevision existed
+#[derive(Debug, JsonSchema, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum Trigger {
+    Spec,
+    Commit,
+    Retry,
+    Migration
+}
+
+impl DecryptorRevisionSpec {
+    /// Create a new DecryptorRevisionSpec from the current status of the Decryptor
+    ///
+    /// # Arguments
+    /// * `decryptor` - &str
+    /// * `status` - &Status
+    /// * `trigger` - Trigger
+    /// * `triggered_by` - Option<String>
+    pub fn new(decryptor: &str, status: &Status, trigger: Trigger, triggered_by: Option<String>) -> Self {
+        DecryptorRevisionSpec {
+            decryptor: decryptor.to_owned(),
+            id: status.id,
+            revision: status.revision.to_owned(),
+            file_to_decrypt: status.file_to_decrypt.to_owned(),
+            deployed_at: status.deployed_at.to_owned(),
+            result: status.status.to_owned(),
+            error_message: status.error_message.to_owned(),
+            reason: status.reason,
+            commit: status.commit.to_owned(),
+            trigger,
+            triggered_by
+        }
+    }
+}
+
+/// Get the name of the DecryptorRevision of a synchronization. The hash of the uid of the Decryptor prevents
+/// a recreated Decryptor to reuse the revisions of the previous one which are waiting to be garbage collected.
+/// The name of the Decryptor is truncated in order to not exceed 253 characters
+///
+/// # Arguments
+/// * `decryptor` - &str
+/// * `uid` - &str
+/// * `id` - u64
+pub fn get_revision_name(decryptor: &str, uid: &str, id: u64) -> String {
+    let suffix = format!("{}-{id}", get_short_hash(uid));
+    let mut prefix: String = decryptor.chars()
+        .take(MAX_NAME_LENGTH - suffix.len() - 1)
+        .collect();
+    while prefix.ends_with(|c: char| !c.is_ascii_alphanumeric()) {
+        prefix.pop();
+    }
+
+    format!("{prefix}-{suffix}")
+}
+
+/// Check whenever the DecryptorRevision is owned by the Decryptor with the uid
+///
+/// # Arguments
+/// * `revision` - &DecryptorRevision
+/// * `uid` - &str
+pub fn is_owned_by(revision: &DecryptorRevision, uid: &str) -> bool {
+    revision.metadata.owner_references.iter()
+        .flatten()
+        .any(|reference| reference.uid == uid)
+}
+
+/// Get the DecryptorRevision of a synchronization. None is returned when the revision does not exist
+/// (e.g: it has been removed as the history exceeded the revisionHistoryLimit) or is not owned by the Decryptor
+///
+/// # Arguments
+/// * `decryptor` - &str
+/// * `uid` - &str
+/// * `ns` - &str
+/// * `id` - u64
+pub async fn get_decryptor_revision(decryptor: &str, uid: &str, ns: &str, id: u64) -> Result<Option<DecryptorRevision>, Error> {
+    let client = Client::try_default().await?;
+    let api: Api<DecryptorRevision> = Api::namespaced(client, ns);
+
+    match api.get(&get_revision_name(decryptor, uid, id)).await {
+        Ok(revision) => Ok(Some(revision).filter(|revision| is_owned_by(revision, uid))),
+        Err(KubeError::Api(err)) if err.code == NOT_FOUND_CODE => Ok(None),
+        Err(err) => Err(Error::from(err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crd::status::DecryptorStatus;
+
+    #[test]
+    fn expect_to_create_revision_from_status() {
+        let mut status = DecryptorStatus::new(
+            SyncStatus::NotSync,
+            Some("sops".to_owned()),
+            Some("foo".to_owned())
+        );
+        status.set_reason(Reason::DecryptFailed);
+        status.current.id = 3;
+
+        let spec = DecryptorRevisionSpec::new("app", &status.current, Trigger::Commit, None);
+        assert_eq!(get_revision_name(&spec.decryptor, "uid", spec.id), format!("app-{}-3", get_short_hash("uid")));
+        assert_eq!(spec.revision, "foo");
+        assert_eq!(spec.result, SyncStatus::NotSync);
+        assert_eq!(spec.error_message.unwrap(), "sops");
+        assert_eq!(spec.reason, Some(Reason::DecryptFailed));
+    }
+
+    #[test]
+    fn expect_recreated_decryptor_to_not_reuse_revisions() {
+        assert_ne!(get_revision_name("app", "uid-a", 1), get_revision_name("app", "uid-b", 1));
+    }
+
+    #[test]
+    fn expect_revision_name_to_be_bounded() {
+        let name = get_revision_name(&"a".repeat(300), "uid", u64::MAX);
+        assert!(name.len() <= MAX_NAME_LENGTH);
+        assert!(name.ends_with(&format!("-{}", u64::MAX)));
+    }
+}
@@ -1,10 +1,18 @@
 use std::collections::VecDeque;
 use schemars::JsonSchema;
 use serde::{Serialize, Deserialize};
-use chrono::Utc;
+use chrono::{DateTime, Duration, Utc};
 
 // constant
-const MAX_QUEUE_SIZE: usize = 10;
+pub const READY_CONDITION: &str = "Ready";
+pub const HEALTHY_CONDITION: &str = "Healthy";
+pub const CONDITION_TRUE: &str = "True";
+const CONDITION_FALSE: &str = "False";
+const SYNCED_REASON: &str = "Synced";
+const SYNC_FAILED_REASON: &str = "SyncFailed";
+const BACKOFF_BASE_SECONDS: i64 = 180;
+const BACKOFF_MAX_SECONDS: i64 = 3600;
+const BACKOFF_MAX_SHIFT: u32 = 16;
 
 /// Status field of the CRD. It represent the Sync status of the CRD. See below to see how it looks
 /// 
@@ -16,39 +24,149 @@ const MAX_QUEUE_SIZE: usize = 10;
 ///         file_to_decrypt:  pgp/secret.enc.yaml
 ///         Id:               1
 ///         Revision:         a888f02e1111beb2c543d729faa5d516ecaa9e12
+///         Replaced Objects: Secret foo
+///         Reason:           <nil>
+///         Commit:
+///             Author:  foo <foo@bar.com>
+///             Date:    2022-03-03T20:30:12+01:00
+///             Hash:    a888f02e1111beb2c543d729faa5d516ecaa9e12
+///             Subject: rotate the token
 ///         Status:  Sync
-///     History:
-///         List of previous statuses...
+///     Pinned Revision: a888f02e1111beb2c543d729faa5d516ecaa9e12
+///     Conditions:
+///         Type:    Ready
+///         Status:  True
+///         Reason:  Synced
+///     Observed Generation: 1
+///     Consecutive Failures: <nil>
+///     Next Retry At: <nil>
 #[derive(Debug, Serialize, Deserialize, Clone, JsonSchema, Default)]
 pub struct DecryptorStatus {
     pub current: Status,
+    // statuses recorded before the synchronizations were recorded by the DecryptorRevision objects.
+    // The history is migrated to DecryptorRevision objects by miwen and then removed
     pub history: Option<VecDeque<Status>>,
+    pub pinned_revision: Option<String>,
+    pub conditions: Option<Vec<Condition>>,
+    pub observed_generation: Option<i64>,
+    pub consecutive_failures: Option<u32>,
+    pub next_retry_at: Option<String>,
+    pub failed_commit: Option<String>
 }
 
-#[derive(Debug, JsonSchema, Clone, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, JsonSchema, Clone, Serialize, Deserialize, PartialEq, Default)]
 pub enum SyncStatus {
+    #[default]
     Sync,
     NotSync
 }
 
-impl Default for SyncStatus {
-    fn default() -> Self {
-        SyncStatus::Sync
+/// Reason of a failed synchronization. It allows to tell failures apart and to decide whenever
+/// a failure is worth being retried before the Decryptor or the repository changed
+#[derive(Debug, JsonSchema, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum Reason {
+    GitAuthFailed,
+    RepoNotFound,
+    CloneFailed,
+    PullFailed,
+    InvalidRepository,
+    RevisionNotFound,
+    DecryptFailed,
+    ProviderAuthFailed,
+    InvalidSpec,
+    ApplyFailed,
+    DeletionBlocked,
+    Conflict,
+    PolicyViolation,
+    Unavailable,
+    Internal
+}
+
+impl Reason {
+    /// Check whenever the failure may resolve by itself (e.g: network, api server). Other failures
+    /// need a change of the Decryptor, of its credentials or of the repository
+    /// 
+    /// # Arguments
+    /// * `&self` - &Self
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            Reason::CloneFailed | Reason::PullFailed | Reason::Unavailable | Reason::Internal | Reason::RepoNotFound | Reason::DeletionBlocked
+        )
     }
 }
 
 #[derive(Debug, JsonSchema, Clone, Serialize, Deserialize, PartialEq, Default)]
 pub struct Status {
-    deployed_at: String,
+    pub deployed_at: String,
     pub id: u64,
     pub revision: String,
     pub file_to_decrypt: String,
-    status: SyncStatus,
-    error_message: Option<String>
+    pub status: SyncStatus,
+    pub error_message: Option<String>,
+    pub replaced_objects: Option<Vec<String>>,
+    pub rolled_back: Option<bool>,
+    pub reason: Option<Reason>,
+    pub commit: Option<CommitInfo>
+}
+
+/// Last commit which changed the decrypted file. It allows to know who changed the file and when
+#[derive(Debug, JsonSchema, Clone, Serialize, Deserialize, PartialEq, Default)]
+pub struct CommitInfo {
+    pub hash: String,
+    pub author: String,
+    pub date: String,
+    pub subject: String
+}
+
+/// Condition represent an observation of the Decryptor which is not related to a synchronization
+#[derive(Debug, JsonSchema, Clone, Serialize, Deserialize, PartialEq, Default)]
+pub struct Condition {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub status: String,
+    pub reason: String,
+    pub message: Option<String>,
+    pub last_transition_time: String
+}
+
+impl Condition {
+    /// Create a new Condition
+    /// 
+    /// # Arguments
+    /// * `kind` - &str
+    /// * `status` - bool
+    /// * `reason` - &str
+    /// * `message` - Option<String>
+    pub fn new(kind: &str, status: bool, reason: &str, message: Option<String>) -> Self {
+        let status = match status {
+            true => CONDITION_TRUE,
+            false => CONDITION_FALSE
+        };
+
+        Condition {
+            kind: kind.to_owned(),
+            status: status.to_owned(),
+            reason: reason.to_owned(),
+            message,
+            last_transition_time: Utc::now().to_rfc3339()
+        }
+    }
+}
+
+/// Get the delay in seconds before retrying a Decryptor which failed consecutively
+/// 
+/// # Arguments
+/// * `failures` - u32
+fn get_backoff_delay(failures: u32) -> i64 {
+    let shift = failures.saturating_sub(1).min(BACKOFF_MAX_SHIFT);
+
+    BACKOFF_BASE_SECONDS.saturating_mul(1 << shift).min(BACKOFF_MAX_SECONDS)
 }
 
 impl DecryptorStatus {
-    /// Create a new Decryptor Status struct. This status is used by the Controller to update the k8s status
+    /// Create a new Decryptor Status struct. This status is used by the Controller to update the k8s status.
+    /// The Ready condition reflects the result of the synchronization
     /// 
     /// # Arguments
     /// * `status` - SyncStatus
@@ -59,6 +177,11 @@ impl DecryptorStatus {
         err: Option<String>,
         revision: Option<String>,
     ) -> Self {
+        let ready = match status {
+            SyncStatus::Sync => Condition::new(READY_CONDITION, true, SYNCED_REASON, None),
+            SyncStatus::NotSync => Condition::new(READY_CONDITION, false, SYNC_FAILED_REASON, err.clone())
+        };
+
         let status = Status {
             deployed_at: Utc::now().to_rfc3339(),
             revision: revision.unwrap_or_default(),
@@ -69,22 +192,140 @@ impl DecryptorStatus {
 
         DecryptorStatus {
             current: status,
+            conditions: Some(vec![ready]),
             ..Default::default()
         }
     }
 
-    /// Update the history of the status by adding the current struct status
-    /// to the history. The current status will then be replaced with a new one...
+    /// Record the objects which have been deleted and re-created as an immutable field changed
     /// 
     /// # Arguments
     /// * `&mut self` - Self
-    pub fn add_current_to_history(&mut self) {
-        if let Some(queue) = self.history.as_mut() {
-            if queue.len() > MAX_QUEUE_SIZE {
-                queue.pop_front();
-            }
+    /// * `replaced` - Vec<String>
+    pub fn set_replaced_objects(&mut self, replaced: Vec<String>) {
+        if !replaced.is_empty() {
+            self.current.replaced_objects = Some(replaced);
+        }
+    }
 
-            queue.push_back(self.current.clone());
+    /// Set the last commit which changed the decrypted file
+    /// 
+    /// # Arguments
+    /// * `&mut self` - Self
+    /// * `commit` - Option<CommitInfo>
+    pub fn set_commit(&mut self, commit: Option<CommitInfo>) {
+        self.current.commit = commit;
+    }
+
+    /// Set the reason of the failed synchronization
+    /// 
+    /// # Arguments
+    /// * `&mut self` - Self
+    /// * `reason` - Reason
+    pub fn set_reason(&mut self, reason: Reason) {
+        self.current.reason = Some(reason);
+    }
+
+    /// Record that the objects have been restored to their previous state as the apply failed
+    /// 
+    /// # Arguments
+    /// * `&mut self` - Self
+    pub fn set_rolled_back(&mut self) {
+        self.current.rolled_back = Some(true);
+    }
+
+    /// Record a failed synchronization and schedule the next retry. Failures are counted as long as the
+    /// generation of the Decryptor and the commit of the repository stay the same. The delay before the
+    /// next retry doubles with each consecutive failure up to a cap. Transient failures are retried w/o delay increase
+    /// 
+    /// # Arguments
+    /// * `&mut self` - Self
+    /// * `prev` - Option<&DecryptorStatus>
+    /// * `generation` - Option<i64>
+    /// * `commit` - Option<String>
+    pub fn set_failure(&mut self, prev: Option<&DecryptorStatus>, generation: Option<i64>, commit: Option<String>) {
+        let failures = match prev {
+            Some(prev) if prev.observed_generation == generation && prev.failed_commit == commit => {
+                prev.consecutive_failures.unwrap_or_default() + 1
+            },
+            _ => 1
+        };
+
+        let delay = match self.current.reason {
+            Some(reason) if reason.is_transient() => BACKOFF_BASE_SECONDS,
+            _ => get_backoff_delay(failures)
+        };
+
+        self.consecutive_failures = Some(failures);
+        self.next_retry_at = Some((Utc::now() + Duration::seconds(delay)).to_rfc3339());
+        self.failed_commit = commit;
+    }
+
+    /// Get the date of the next retry of a failed synchronization
+    /// 
+    /// # Arguments
+    /// * `&self` - &Self
+    pub fn get_next_retry(&self) -> Option<DateTime<Utc>> {
+        self.next_retry_at.as_ref()
+            .and_then(|date| DateTime::parse_from_rfc3339(date).ok())
+            .map(|date| date.with_timezone(&Utc))
+    }
+
+    /// Set a condition in the status. A condition with the same type is replaced.
+    /// The transition time is kept if the status of the condition didn't change
+    /// 
+    /// # Arguments
+    /// * `&mut self` - Self
+    /// * `condition` - Condition
+    pub fn set_condition(&mut self, mut condition: Condition) {
+        let conditions = self.conditions.get_or_insert_with(Vec::new);
+        match conditions.iter_mut().find(|c| c.kind == condition.kind) {
+            Some(existing) => {
+                if existing.status == condition.status {
+                    condition.last_transition_time = existing.last_transition_time.to_owned();
+                }
+
+                *existing = condition;
+            },
+            None => conditions.push(condition)
+        }
+    }
+
+    /// Get a condition by it's type
+    /// 
+    /// # Arguments
+    /// * `&self` - &Self
+    /// * `kind` - &str
+    pub fn get_condition(&self, kind: &str) -> Option<&Condition> {
+        self.conditions.as_ref()
+            .and_then(|conditions| conditions.iter().find(|c| c.kind == kind))
+    }
+
+    /// Retrieve a status by it's id from the current status or the history which has not been migrated yet
+    /// 
+    /// # Arguments
+    /// * `&self` - &Self
+    /// * `id` - u64
+    pub fn get_status_by_id(&self, id: u64) -> Option<&Status> {
+        if self.current.id == id {
+            return Some(&self.current);
+        }
+
+        self.history.as_ref()
+            .and_then(|history| history.iter().find(|st| st.id == id))
+    }
+
+    /// Merge the previous conditions with the current one. Conditions of the current status take precedence
+    /// 
+    /// # Arguments
+    /// * `&mut self` - Self
+    /// * `prev` - Option<Vec<Condition>>
+    pub fn merge_conditions(&mut self, prev: Option<Vec<Condition>>) {
+        let current = self.conditions.take();
+        self.conditions = prev;
+
+        for condition in current.unwrap_or_default() {
+            self.set_condition(condition);
         }
     }
 }
@@ -93,8 +334,9 @@ impl DecryptorStatus {
 mod tests {
     use kube::core::ObjectMeta;
     use kube::{Client, Api};
-    use crate::crd::{DecryptorSpec, Provider, Source};
+    use crate::crd::{DecryptorSpec, Provider, Source, Rollback};
     use crate::crd::repo::Repository;
+    use crate::crd::revision::{DecryptorRevision, DecryptorRevisionSpec, Trigger, get_revision_name};
     use super::super::Decryptor;
     use super::*;
 
@@ -108,14 +350,28 @@ mod tests {
                     pgp: None,
                     vault: None
                 },
+                provider_ref: None,
                 source: Source {
-                    repository: Repository {
+                    repository: Some(Repository {
                         url: "https://foo.bar".to_owned(),
                         credentials: None
-                    },
+                    }),
+                    repository_ref: None,
                     file_to_decrypt: "foo".to_owned(),
                     sops_path: "bar".to_owned()
-                }
+                },
+                rollback: None,
+                health_checks: None,
+                conflict_policy: None,
+                field_manager: None,
+                allow_cross_namespace: None,
+                output: None,
+                post_render: None,
+                destination: None,
+                service_account_name: None,
+                depends_on: None,
+                replace_on_immutable_change: None,
+                revision_history_limit: None
             },
             status: None
         }
@@ -143,6 +399,11 @@ mod tests {
             Some("foo".to_owned()),
         ));
 
+        // history of a Decryptor which has not been migrated to the DecryptorRevision objects yet
+        let mut status = decryptor.status.take().unwrap();
+        status.history = Some(VecDeque::from([status.current.clone()]));
+        decryptor.status = Some(status);
+
         decryptor.set_status(DecryptorStatus::new(
             SyncStatus::Sync, 
             None, 
@@ -153,6 +414,167 @@ mod tests {
         assert!(status.history.is_some());
         let history = status.history.unwrap();
         assert_eq!(history.len(), 1);
+        assert_eq!(status.current.id, 2);
+    }
+
+    #[test]
+    fn expect_to_get_rollback_revision_by_id() {
+        let mut decryptor = get_decryptor();
+        decryptor.set_status(DecryptorStatus::new(
+            SyncStatus::Sync, 
+            None, 
+            Some("foo".to_owned()),
+        ));
+
+        let first = decryptor.status.as_ref().unwrap();
+        let revisions = vec![DecryptorRevision::new(
+            &get_revision_name("foo", "uid", first.current.id),
+            DecryptorRevisionSpec::new("foo", &first.current, Trigger::Spec, None)
+        )];
+
+        decryptor.set_status(DecryptorStatus::new(
+            SyncStatus::Sync, 
+            None, 
+            Some("bar".to_owned()),
+        ));
+
+        decryptor.spec.rollback = Some(Rollback {
+            to_id: Some(1),
+            ..Default::default()
+        });
+
+        let revision = decryptor.get_rollback_revision_from(&revisions).unwrap();
+        assert_eq!(revision.unwrap(), "foo");
+
+        decryptor.set_status(DecryptorStatus::new(
+            SyncStatus::Sync, 
+            None, 
+            Some("foo".to_owned()),
+        ));
+
+        let status = decryptor.status.unwrap();
+        assert_eq!(status.pinned_revision.unwrap(), "foo");
+    }
+
+    #[test]
+    fn expect_to_not_get_unknown_rollback_id() {
+        let mut decryptor = get_decryptor();
+        decryptor.spec.rollback = Some(Rollback {
+            to_id: Some(10),
+            ..Default::default()
+        });
+
+        let revision = decryptor.get_rollback_revision_from(&[]);
+        assert!(revision.is_err());
+    }
+
+    #[test]
+    fn expect_to_replace_condition() {
+        let mut status = DecryptorStatus::default();
+        status.set_condition(Condition::new("Healthy", true, "Healthy", None));
+        status.set_condition(Condition::new("Healthy", false, "Degraded", Some("foo".to_owned())));
+
+        let conditions = status.conditions.as_ref().unwrap();
+        assert_eq!(conditions.len(), 1);
+
+        let healthy = status.get_condition("Healthy").unwrap();
+        assert_eq!(healthy.status, "False");
+        assert_eq!(healthy.reason, "Degraded");
+    }
+
+    #[test]
+    fn expect_to_keep_conditions_across_statuses() {
+        let mut decryptor = get_decryptor();
+        let mut status = DecryptorStatus::new(
+            SyncStatus::Sync, 
+            None, 
+            Some("foo".to_owned()),
+        );
+        status.set_condition(Condition::new("Healthy", true, "Healthy", None));
+        decryptor.set_status(status);
+
+        decryptor.set_status(DecryptorStatus::new(
+            SyncStatus::Sync, 
+            None, 
+            Some("bar".to_owned()),
+        ));
+
+        let status = decryptor.status.unwrap();
+        assert!(status.get_condition("Healthy").is_some());
+    }
+
+    #[test]
+    fn expect_decryptor_to_be_ready_at_current_generation() {
+        let mut decryptor = get_decryptor();
+        decryptor.metadata.generation = Some(1);
+        assert!(!decryptor.is_ready());
+
+        decryptor.set_status(DecryptorStatus::new(
+            SyncStatus::Sync,
+            None,
+            Some("foo".to_owned()),
+        ));
+        assert!(decryptor.is_ready());
+
+        decryptor.metadata.generation = Some(2);
+        assert!(!decryptor.is_ready());
+    }
+
+    #[test]
+    fn expect_degraded_decryptor_to_not_be_ready() {
+        let mut decryptor = get_decryptor();
+        decryptor.metadata.generation = Some(1);
+
+        let mut status = DecryptorStatus::new(
+            SyncStatus::Sync,
+            None,
+            Some("foo".to_owned()),
+        );
+        status.set_condition(Condition::new(HEALTHY_CONDITION, false, "Degraded", None));
+        decryptor.set_status(status);
+
+        assert!(!decryptor.is_ready());
+    }
+
+    #[test]
+    fn expect_backoff_delay_to_be_capped() {
+        assert_eq!(get_backoff_delay(1), 180);
+        assert_eq!(get_backoff_delay(2), 360);
+        assert_eq!(get_backoff_delay(4), 1440);
+        assert_eq!(get_backoff_delay(5), 2880);
+        assert_eq!(get_backoff_delay(6), 3600);
+        assert_eq!(get_backoff_delay(100), 3600);
+    }
+
+    #[test]
+    fn expect_to_count_consecutive_failures() {
+        let mut decryptor = get_decryptor();
+        decryptor.metadata.generation = Some(1);
+
+        for _ in 0..3 {
+            let mut status = DecryptorStatus::new(SyncStatus::NotSync, Some("sops".to_owned()), None);
+            status.set_reason(Reason::DecryptFailed);
+            status.set_failure(decryptor.status.as_ref(), decryptor.metadata.generation, Some("foo".to_owned()));
+            decryptor.set_status(status);
+        }
+
+        assert_eq!(decryptor.status.as_ref().unwrap().consecutive_failures, Some(3));
+        assert!(decryptor.is_backing_off(Some("foo")));
+
+        // a new commit reset the backoff
+        assert!(!decryptor.is_backing_off(Some("bar")));
+        let mut status = DecryptorStatus::new(SyncStatus::NotSync, Some("sops".to_owned()), None);
+        status.set_failure(decryptor.status.as_ref(), decryptor.metadata.generation, Some("bar".to_owned()));
+        decryptor.set_status(status);
+        assert_eq!(decryptor.status.as_ref().unwrap().consecutive_failures, Some(1));
+
+        // a new generation reset the backoff
+        decryptor.metadata.generation = Some(2);
+        assert!(!decryptor.is_backing_off(Some("bar")));
+        assert!(decryptor.has_failed());
+
+        decryptor.set_status(DecryptorStatus::new(SyncStatus::Sync, None, Some("bar".to_owned())));
+        assert!(!decryptor.has_failed());
     }
 
     #[tokio::test]
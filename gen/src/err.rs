@@ -2,8 +2,11 @@
 pub enum Error {
     MissingMetadata(String),
     Kube(String),
+    Api(String),
     DecodedBytes(String),
-    Encoding(String)
+    Encoding(String),
+    Rollback(String),
+    Repository(String)
 }
 
 impl std::fmt::Display for Error {
@@ -11,8 +14,11 @@ impl std::fmt::Display for Error {
         match self {
             Error::MissingMetadata(key) => write!(f, "Key: {key} is not present within the metadata"),
             Error::Kube(msg) => write!(f, "Error while looking for kube resource {msg}"),
+            Error::Api(msg) => write!(f, "Unable to communicate with the Kubernetes API: {msg}"),
             Error::DecodedBytes(msg) => write!(f, "Unable to decoded bytes for reasons: {msg}"),
-            Error::Encoding(msg) => write!(f, "Unable to encoded value to json: {msg}")
+            Error::Encoding(msg) => write!(f, "Unable to encoded value to json: {msg}"),
+            Error::Rollback(msg) => write!(f, "Unable to rollback to the targeted revision: {msg}"),
+            Error::Repository(msg) => write!(f, "Unable to get the repository: {msg}")
         }
     }
 }
@@ -21,7 +27,12 @@ impl std::error::Error for Error {}
 
 impl From<kube::Error> for Error {
     fn from(err: kube::Error) -> Self {
-        Error::Kube(err.to_string())
+        match err {
+            // the request has been rejected because of the referenced resource
+            kube::Error::Api(res) if matches!(res.code, 400 | 404 | 422) => Error::Kube(res.to_string()),
+            // the api server or the network failed which may be resolved by retrying
+            _ => Error::Api(err.to_string())
+        }
     }
 }
 
@@ -1,3 +1,3 @@
 pub mod crd;
 pub mod err;
-mod util;
\ No newline at end of file
+pub mod util;
\ No newline at end of file
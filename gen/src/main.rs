@@ -4,7 +4,7 @@ use color_eyre::Result;
 
 pub mod crd;
 mod err;
-mod util;
+pub mod util;
 
 /// Setup different logging & debugging services
 fn setup() -> Result<()> {
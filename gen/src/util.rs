@@ -1,6 +1,11 @@
 use k8s_openapi::ByteString;
+use sha2::{Sha256, Digest};
 use crate::err::Error;
 
+// Constant
+pub const MAX_LABEL_LENGTH: usize = 63;
+const SHORT_HASH_LENGTH: usize = 10;
+
 /// Decode a Base64 to a string
 ///
 /// # Arguments
@@ -11,3 +16,78 @@ pub(crate) fn decode_byte(base: &ByteString) -> Result<String, Error> {
 
     Ok(value)
 }
+
+/// Check that a revision (branch, tag, commit) can be passed to git. A revision which starts with '-'
+/// or which contains a whitespace is rejected
+///
+/// # Arguments
+/// * `revision` - &str
+pub fn is_valid_revision(revision: &str) -> bool {
+    !revision.is_empty()
+        && !revision.starts_with('-')
+        && !revision.chars().any(|c| c.is_whitespace() || c.is_control())
+}
+
+/// Get a short hash of a value. The hash is used to derive unique and bounded names from long values
+///
+/// # Arguments
+/// * `value` - &str
+pub fn get_short_hash(value: &str) -> String {
+    let digest = Sha256::digest(value.as_bytes());
+    let mut hash: String = digest.iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+
+    hash.truncate(SHORT_HASH_LENGTH);
+    hash
+}
+
+/// Get a value which fit in a label. Values longer than 63 characters are truncated and
+/// suffixed with a hash of the value in order to stay unique
+///
+/// # Arguments
+/// * `value` - &str
+pub fn get_label_value(value: &str) -> String {
+    if value.len() <= MAX_LABEL_LENGTH {
+        return value.to_owned();
+    }
+
+    let mut prefix: String = value.chars()
+        .take(MAX_LABEL_LENGTH - SHORT_HASH_LENGTH - 1)
+        .collect();
+    while prefix.ends_with(|c: char| !c.is_ascii_alphanumeric()) {
+        prefix.pop();
+    }
+
+    format!("{prefix}-{}", get_short_hash(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expect_to_reject_revision_as_option() {
+        assert!(is_valid_revision("main"));
+        assert!(is_valid_revision("a888f02e1111beb2c543d729faa5d516ecaa9e12"));
+        assert!(!is_valid_revision(""));
+        assert!(!is_valid_revision("--output=/tmp/x:foo"));
+        assert!(!is_valid_revision("main --output=/tmp/x"));
+    }
+
+    #[test]
+    fn expect_short_value_to_be_kept_as_label() {
+        assert_eq!(get_label_value("pgp-decryptor"), "pgp-decryptor");
+    }
+
+    #[test]
+    fn expect_long_value_to_be_hashed_as_label() {
+        let first = format!("{}-a", "a".repeat(100));
+        let second = format!("{}-b", "a".repeat(100));
+
+        let label = get_label_value(&first);
+        assert_eq!(label.len(), MAX_LABEL_LENGTH);
+        assert_ne!(label, get_label_value(&second));
+        assert_eq!(label, get_label_value(&first));
+    }
+}
@@ -4,7 +4,8 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         .build_client(false)
         .compile(&[
             "../proto/repository.proto",
-            "../proto/crd.proto"
+            "../proto/crd.proto",
+            "../proto/error.proto"
         ], 
         &["../proto"]
     )?;
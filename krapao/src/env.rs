@@ -14,8 +14,7 @@ pub struct GitCredentials {
     pub token: Option<String>,
     pub repository: String,
     pub target: PathBuf,
-    pub ssh: Option<String>,
-    pub sync_interval: Option<u64>
+    pub ssh: Option<String>
 }
 
 impl From<Payload> for GitCredentials {
@@ -29,7 +28,6 @@ impl From<Payload> for GitCredentials {
         let mut env = GitCredentials {
             repository: p.url,
             target: dir,
-            sync_interval: Some(1800),
             ..Default::default()
         };
 
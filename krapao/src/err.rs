@@ -1,5 +1,12 @@
 use std::ffi::OsString;
-use tonic::Status;
+use bytes::Bytes;
+use prost::Message;
+use tonic::{Code, Status};
+use self::proto::{ErrorDetails, Reason};
+
+pub mod proto {
+    tonic::include_proto!("error");
+}
 
 #[derive(Debug, PartialEq)]
 pub enum Error {
@@ -17,7 +24,9 @@ pub enum Error {
     Sops(String),
     Encoding(String),
     Io(String),
-    ProviderAuth(String)
+    ProviderAuth(String),
+    Revision(String),
+    MissingRepository(String)
 }
 
 impl std::fmt::Display for Error {
@@ -37,7 +46,9 @@ impl std::fmt::Display for Error {
             Error::Sops(msg) => write!(f, "Error with SOPS: {msg}"),
             Error::Encoding(msg) => write!(f, "Error while encoding data: {msg}"),
             Error::Io(msg) => write!(f, "Error while processing doing I/O: {msg}"),
-            Error::ProviderAuth(msg) => write!(f, "Error while authenticating with provider to decrypt SOPS file: {msg}")
+            Error::ProviderAuth(msg) => write!(f, "Error while authenticating with provider to decrypt SOPS file: {msg}"),
+            Error::Revision(msg) => write!(f, "Unable to retrieve the file at the targeted revision: {msg}"),
+            Error::MissingRepository(owner) => write!(f, "No repository is referenced by {owner}")
         }
     }
 }
@@ -80,9 +91,43 @@ impl From<serde_json::Error> for Error {
     }
 }
 
+impl Error {
+    /// Get the gRPC code and the reason of the error. The reason is sent to miwen in the details
+    /// of the status in order to distinguish failures which share the same code
+    /// 
+    /// # Arguments
+    /// * `&self` - &Self
+    fn get_code(&self) -> (Code, Reason) {
+        match self {
+            Error::Auth(_) => (Code::Unauthenticated, Reason::GitAuthFailed),
+            Error::EmptyRepoURI | Error::MalformattedURI => (Code::InvalidArgument, Reason::InvalidRepository),
+            Error::Clone(_) => (Code::Unavailable, Reason::CloneFailed),
+            Error::Pull(_) | Error::MaxPullRetry => (Code::Unavailable, Reason::PullFailed),
+            Error::Sops(_) => (Code::FailedPrecondition, Reason::DecryptFailed),
+            Error::ProviderAuth(_) => (Code::PermissionDenied, Reason::ProviderAuthFailed),
+            Error::Revision(_) => (Code::InvalidArgument, Reason::RevisionNotFound),
+            // a distinct code allows the client to clone the repository again
+            Error::MissingRepository(_) => (Code::NotFound, Reason::RepoNotFound),
+            Error::Config(_)
+            | Error::RefreshDuration
+            | Error::Server(_)
+            | Error::Bootstrap(_)
+            | Error::Sync(_)
+            | Error::Encoding(_)
+            | Error::Io(_) => (Code::Internal, Reason::Internal)
+        }
+    }
+}
+
 impl From<Error> for Status {
     fn from(err: Error) -> Self {
-        Status::internal(err.to_string())
+        let (code, reason) = err.get_code();
+        let details = ErrorDetails {
+            reason: reason as i32,
+            message: err.to_string()
+        };
+
+        Status::with_details(code, err.to_string(), Bytes::from(details.encode_to_vec()))
     }
 }
 
@@ -90,4 +135,28 @@ impl From<toml::ser::Error> for Error {
     fn from(err: toml::ser::Error) -> Self {
         Error::Sops(err.to_string())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expect_status_to_contain_reason() {
+        let status = Status::from(Error::MissingRepository("Decryptor/default/foo".to_owned()));
+        assert_eq!(status.code(), Code::NotFound);
+
+        let details = ErrorDetails::decode(status.details()).unwrap();
+        assert_eq!(details.reason(), Reason::RepoNotFound);
+        assert_eq!(details.message, "No repository is referenced by Decryptor/default/foo");
+    }
+
+    #[test]
+    fn expect_sops_error_to_be_failed_precondition() {
+        let status = Status::from(Error::Sops("no key could decrypt the data key".to_owned()));
+        assert_eq!(status.code(), Code::FailedPrecondition);
+
+        let details = ErrorDetails::decode(status.details()).unwrap();
+        assert_eq!(details.reason(), Reason::DecryptFailed);
+    }
+}
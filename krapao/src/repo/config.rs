@@ -1,24 +1,44 @@
 use std::fs;
+use std::collections::BTreeSet;
 use std::path::{Path, PathBuf};
 use std::process::Command;
 use serde::{Serialize, Deserialize};
+use sha2::{Sha256, Digest};
 use crate::err::Error;
 use crate::helper;
 use crate::env::GitCredentials;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+// Constant
+const REVISION_PATH: &str = "krapao/revision";
+const REMOTE_PREFIX: &str = "origin";
+const FETCH_HEAD_PATH: &str = ".git/FETCH_HEAD";
+const GIT_PATH: &str = ".git";
+const COMMIT_FORMAT: &str = "--format=%H%x00%an <%ae>%x00%cI%x00%s";
+const COMMIT_SEPARATOR: char = '\0';
+const ANONYMOUS_FINGERPRINT: &str = "anonymous";
+const FINGERPRINT_LENGTH: usize = 16;
+// git prompts for the credentials when they're rejected. Prompts are disabled as krapao is not interactive
+const GIT_TERMINAL_PROMPT_ENV: &str = "GIT_TERMINAL_PROMPT";
+// git reads the arguments which follow as revisions or paths even if they start with '-'
+const END_OF_OPTIONS: &str = "--end-of-options";
+const AUTH_FAILURE_PATTERNS: [&str; 7] = [
+    "authentication failed",
+    "could not read username",
+    "could not read password",
+    "terminal prompts disabled",
+    "invalid username or password",
+    "permission denied (publickey",
+    "http basic: access denied"
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
 pub enum Credentials {
     Token(String, String),
     Ssh(String),
+    #[default]
     Empty
 }
 
-impl Default for Credentials {
-    fn default() -> Self {
-        Credentials::Empty
-    }
-}
-
 impl Credentials {
     /// Create a new Credential from the Env
     /// 
@@ -36,13 +56,103 @@ impl Credentials {
 
         Credentials::Empty
     }
+
+    /// Get a fingerprint of the credentials. The credentials are hashed in order to not be exposed
+    /// by the key of the repository
+    /// 
+    /// # Arguments
+    /// * `&self` - &Self
+    pub fn get_fingerprint(&self) -> String {
+        let value = match self {
+            Credentials::Token(username, token) => format!("token:{username}:{token}"),
+            Credentials::Ssh(key) => format!("ssh:{key}"),
+            Credentials::Empty => return ANONYMOUS_FINGERPRINT.to_owned()
+        };
+
+        let mut fingerprint: String = Sha256::digest(value.as_bytes())
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect();
+
+        fingerprint.truncate(FINGERPRINT_LENGTH);
+        fingerprint
+    }
 } 
 
+/// Get the key of a repository in the state. A repository is cloned per url and credentials. Hence a
+/// resource can't read a repository which has been cloned with the credentials of another resource
+/// 
+/// # Arguments
+/// * `url` - &str
+/// * `credentials` - &Credentials
+pub fn get_repository_key(url: &str, credentials: &Credentials) -> String {
+    format!("{url}#{}", credentials.get_fingerprint())
+}
+
+/// Commit which last changed a file of the repository
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct FileCommit {
+    pub hash: String,
+    pub author: String,
+    pub date: String,
+    pub subject: String
+}
+
+/// Check that a revision provided by a user can be passed to git. A revision which starts with '-'
+/// would be read as an option of the git command (e.g: --output)
+///
+/// # Arguments
+/// * `revision` - &str
+pub fn check_revision(revision: &str) -> Result<(), Error> {
+    if revision.is_empty() || revision.starts_with('-') || revision.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        return Err(Error::Revision(format!("{revision:?} is not a valid revision")));
+    }
+
+    Ok(())
+}
+
+/// Get the error of a git command which failed. Failures caused by the credentials are reported as an
+/// authentication error as they won't be fixed by retrying the command
+///
+/// # Arguments
+/// * `stderr` - &[u8]
+/// * `err` - fn(String) -> Error
+fn get_git_error(stderr: &[u8], err: fn(String) -> Error) -> Error {
+    let msg = String::from_utf8_lossy(stderr).trim().to_owned();
+    let lowercase = msg.to_lowercase();
+
+    if AUTH_FAILURE_PATTERNS.iter().any(|pattern| lowercase.contains(pattern)) {
+        return Error::Auth(msg);
+    }
+
+    err(msg)
+}
+
+/// Parse the output of git log formatted with the COMMIT_FORMAT
+/// 
+/// # Arguments
+/// * `output` - &str
+fn parse_commit(output: &str) -> Option<FileCommit> {
+    let mut parts = output.trim_end().splitn(4, COMMIT_SEPARATOR);
+    let hash = parts.next().filter(|hash| !hash.is_empty())?;
+
+    Some(FileCommit {
+        hash: hash.to_owned(),
+        author: parts.next()?.to_owned(),
+        date: parts.next()?.to_owned(),
+        subject: parts.next().unwrap_or_default().to_owned()
+    })
+}
+
+/// GitConfig of a cloned repository. The owners are the resources (kind/namespace/name) which reference
+/// the repository. The repository is deleted once it does not have any owner
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
 pub struct GitConfig {
     auth_method: Credentials,
     pub repo_uri: String,
-    pub target: PathBuf
+    pub target: PathBuf,
+    #[serde(default)]
+    pub owners: BTreeSet<String>
 }
 
 impl GitConfig {
@@ -60,10 +170,19 @@ impl GitConfig {
         Ok(GitConfig {
             auth_method,
             repo_uri: repo_uri.to_owned(),
-            target
+            target,
+            ..Default::default()
         })
     }
 
+    /// Get the key of the repository in the state
+    /// 
+    /// # Arguments
+    /// * `&self` - &Self
+    pub fn get_key(&self) -> String {
+        get_repository_key(&self.repo_uri, &self.auth_method)
+    }
+
     /// Init the repository. Create if the repo exist or just skip it
     /// 
     /// # Arguments
@@ -121,14 +240,15 @@ impl GitConfig {
         }
 
         // clone repository
-        let status = Command::new("git")
+        let output = Command::new("git")
+            .env(GIT_TERMINAL_PROMPT_ENV, "0")
             .arg("clone")
             .arg(uri)
             .arg(&self.target)
-            .status()?;
+            .output()?;
         
-        if !status.success() {
-            return Err(Error::Clone(status.to_string()))
+        if !output.status.success() {
+            return Err(get_git_error(&output.stderr, Error::Clone))
         }
 
         info!("Repository has been clone in the path {}", self.repo_uri);
@@ -152,16 +272,17 @@ impl GitConfig {
     /// * `target` - String
     pub fn pull(&self) -> Result<(), Error> {
         info!("Pulling change from upstream for {}", self.repo_uri);
-        let status = Command::new("git")
+        let output = Command::new("git")
+            .env(GIT_TERMINAL_PROMPT_ENV, "0")
             .arg("-C")
             .arg(self.target.clone())
             .arg("pull")
             .arg("--rebase")
-            .status()?;
+            .output()?;
 
-        if !status.success() {
+        if !output.status.success() {
             error!("Fail to pull repository");
-            return Err(Error::Pull(status.to_string()));
+            return Err(get_git_error(&output.stderr, Error::Pull));
         }
 
         info!("Local repository cache has been updated");
@@ -173,19 +294,150 @@ impl GitConfig {
     /// # Arguments
     /// * `&self` - &Self
     pub fn get_commit_hash(&self) -> Option<String> {
+        self.get_revision_hash("HEAD")
+    }
+
+    /// Get the commit hash of the targeted revision. None is returned when the revision is not a commit
+    /// 
+    /// # Arguments
+    /// * `&self` - &Self
+    /// * `revision` - &str
+    pub fn get_revision_hash(&self, revision: &str) -> Option<String> {
         let output = Command::new("git")
             .arg("-C")
             .arg(self.target.clone())
-            .arg("rev-parse")
-            .arg("HEAD")
+            .args(["rev-parse", "--verify", "--quiet", END_OF_OPTIONS])
+            .arg(format!("{revision}^{{commit}}"))
             .output();
 
-        if let Ok(o) = output {
-            let out = o.stdout;
-            return String::from_utf8(out).ok();
+        match output {
+            Ok(o) if o.status.success() => String::from_utf8(o.stdout)
+                .ok()
+                .map(|hash| hash.trim().to_owned())
+                .filter(|hash| !hash.is_empty()),
+            _ => None
+        }
+    }
+
+    /// Get the last commit which changed the file as of the targeted revision
+    /// 
+    /// # Arguments
+    /// * `&self` - &Self
+    /// * `revision` - &str
+    /// * `file` - &str
+    pub fn get_file_commit(&self, revision: &str, file: &str) -> Result<Option<FileCommit>, Error> {
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(self.target.clone())
+            .args(["log", "-1", COMMIT_FORMAT, END_OF_OPTIONS, revision, "--", file])
+            .output()?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(Error::Revision(format!("Unable to get the last commit of {file}: {stderr}")));
         }
 
-        None
+        Ok(parse_commit(&String::from_utf8_lossy(&output.stdout)))
+    }
+
+    /// Resolve the revision provided by a user to the hash of its commit. Branches are resolved to
+    /// the remote tracking branch as only the default branch is checked out. Tags and commits are resolved as it is.
+    /// The hash is used by the other git commands in order to never pass the revision of the user to git
+    /// 
+    /// # Arguments
+    /// * `&self` - &Self
+    /// * `revision` - &str
+    pub fn resolve_revision(&self, revision: &str) -> Result<String, Error> {
+        check_revision(revision)?;
+
+        let remote = format!("{REMOTE_PREFIX}/{revision}");
+        self.get_revision_hash(&remote)
+            .or_else(|| self.get_revision_hash(revision))
+            .ok_or_else(|| Error::Revision(format!("Unable to resolve the revision {revision}")))
+    }
+
+    /// Get the time of the last fetch of the repository as a unix timestamp. The time of the clone
+    /// is used when the repository has not been pulled yet
+    /// 
+    /// # Arguments
+    /// * `&self` - &Self
+    pub fn get_last_fetch_time(&self) -> Result<i64, Error> {
+        let fetch_head = self.target.join(FETCH_HEAD_PATH);
+        let path = match fetch_head.exists() {
+            true => fetch_head,
+            false => self.target.join(GIT_PATH)
+        };
+
+        let modified = fs::metadata(path)?.modified()?;
+        let timestamp = modified.duration_since(std::time::UNIX_EPOCH)
+            .map_err(|err| Error::Io(err.to_string()))?
+            .as_secs();
+
+        Ok(timestamp as i64)
+    }
+
+    /// List the path of every files of the repository as of the targeted revision
+    /// 
+    /// # Arguments
+    /// * `&self` - &Self
+    /// * `revision` - &str
+    pub fn list_files(&self, revision: &str) -> Result<Vec<String>, Error> {
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(self.target.clone())
+            .arg("ls-tree")
+            .arg("-r")
+            .arg("--name-only")
+            .arg(END_OF_OPTIONS)
+            .arg(revision)
+            .output()?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(Error::Revision(format!("Unable to list the files at {revision}: {stderr}")));
+        }
+
+        let files = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(str::to_owned)
+            .collect();
+
+        Ok(files)
+    }
+
+    /// Export the file as of the targeted revision in a temporary folder.
+    /// The path of the file within the repository is kept as SOPS rely on the extension of the file
+    /// 
+    /// # Arguments
+    /// * `&self` - &Self
+    /// * `revision` - &str
+    /// * `file_path` - &str
+    pub fn export_file_at_revision(&self, revision: &str, file_path: &str) -> Result<PathBuf, Error> {
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(self.target.clone())
+            .arg("show")
+            .arg(END_OF_OPTIONS)
+            .arg(format!("{revision}:{file_path}"))
+            .output()?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(Error::Revision(stderr.to_string()));
+        }
+
+        let mut path = std::env::temp_dir();
+        path.push(REVISION_PATH);
+        path.push(revision);
+        path.push(file_path);
+
+        if let Some(parent) = path.parent() {
+            helper::create_path(parent)?;
+        }
+
+        fs::write(&path, output.stdout)?;
+
+        Ok(path)
     }
 }
 
@@ -243,6 +495,119 @@ mod tests {
         assert_eq!(handle.unwrap_err(), Error::EmptyRepoURI);
     }
 
+    #[test]
+    fn expect_rejected_credentials_to_be_auth_error() {
+        let https = b"remote: Invalid username or password.\nfatal: Authentication failed for 'https://github.com/foo/bar.git/'\n";
+        assert!(matches!(get_git_error(https, Error::Clone), Error::Auth(_)));
+
+        let ssh = b"git@github.com: Permission denied (publickey).\nfatal: Could not read from remote repository.\n";
+        assert!(matches!(get_git_error(ssh, Error::Pull), Error::Auth(_)));
+
+        let prompt = b"fatal: could not read Username for 'https://github.com': terminal prompts disabled\n";
+        assert!(matches!(get_git_error(prompt, Error::Clone), Error::Auth(_)));
+    }
+
+    #[test]
+    fn expect_other_failures_to_keep_their_error() {
+        let stderr = b"fatal: unable to access 'https://github.com/foo/bar.git/': Could not resolve host: github.com\n";
+        assert_eq!(
+            get_git_error(stderr, Error::Clone),
+            Error::Clone("fatal: unable to access 'https://github.com/foo/bar.git/': Could not resolve host: github.com".to_owned())
+        );
+    }
+
+    #[test]
+    fn expect_credentials_to_be_part_of_the_key() {
+        let repo_uri = "https://github.com/shigedangao/gogo.git";
+        let anonymous = get_repository_key(repo_uri, &Credentials::Empty);
+        let token = get_repository_key(repo_uri, &Credentials::Token("foo".to_owned(), "bar".to_owned()));
+        let rotated = get_repository_key(repo_uri, &Credentials::Token("foo".to_owned(), "baz".to_owned()));
+
+        assert_eq!(anonymous, "https://github.com/shigedangao/gogo.git#anonymous");
+        assert_ne!(anonymous, token);
+        assert_ne!(token, rotated);
+        assert!(!token.contains("bar"));
+    }
+
+    #[test]
+    fn expect_to_parse_commit() {
+        let commit = parse_commit("a888f02\x00foo <foo@bar.com>\x002022-03-03T20:37:59+01:00\x00rotate the token\n").unwrap();
+        assert_eq!(commit.hash, "a888f02");
+        assert_eq!(commit.author, "foo <foo@bar.com>");
+        assert_eq!(commit.date, "2022-03-03T20:37:59+01:00");
+        assert_eq!(commit.subject, "rotate the token");
+
+        assert!(parse_commit("\n").is_none());
+    }
+
+    #[test]
+    fn expect_to_get_file_commit() {
+        let target = std::env::temp_dir().join("krapao/commit");
+        let _ = fs::remove_dir_all(&target);
+        helper::create_path(&target).unwrap();
+        fs::write(target.join("secret.enc.yaml"), "foo: bar").unwrap();
+
+        let git = |args: &[&str]| Command::new("git")
+            .arg("-C")
+            .arg(&target)
+            .args(["-c", "user.name=foo", "-c", "user.email=foo@bar.com"])
+            .args(args)
+            .status()
+            .unwrap();
+
+        assert!(git(&["init", "--quiet"]).success());
+        assert!(git(&["add", "secret.enc.yaml"]).success());
+        assert!(git(&["commit", "--quiet", "-m", "add the secret"]).success());
+        assert!(git(&["commit", "--quiet", "--allow-empty", "-m", "unrelated change"]).success());
+
+        let handle = GitConfig::new(Credentials::Empty, "https://github.com/shigedangao/gogo.git", target.clone()).unwrap();
+        let head = handle.get_commit_hash().unwrap();
+        assert_eq!(head.len(), 40);
+
+        let commit = handle.get_file_commit("HEAD", "secret.enc.yaml").unwrap().unwrap();
+        assert_ne!(commit.hash, head);
+        assert_eq!(commit.author, "foo <foo@bar.com>");
+        assert_eq!(commit.subject, "add the secret");
+    }
+
+    #[test]
+    fn expect_to_not_pass_revision_as_option() {
+        assert!(check_revision("main").is_ok());
+        assert!(check_revision("v1.0.0").is_ok());
+        assert!(check_revision("").is_err());
+        assert!(check_revision("main --output=/tmp/x").is_err());
+
+        let target = std::env::temp_dir().join("krapao/option");
+        let output = std::env::temp_dir().join("krapao/option-output");
+        let _ = fs::remove_dir_all(&target);
+        let _ = fs::remove_file(&output);
+        helper::create_path(&target).unwrap();
+        fs::write(target.join("secret.enc.yaml"), "foo: bar").unwrap();
+
+        let git = |args: &[&str]| Command::new("git")
+            .arg("-C")
+            .arg(&target)
+            .args(["-c", "user.name=foo", "-c", "user.email=foo@bar.com"])
+            .args(args)
+            .status()
+            .unwrap();
+
+        assert!(git(&["init", "--quiet"]).success());
+        assert!(git(&["add", "secret.enc.yaml"]).success());
+        assert!(git(&["commit", "--quiet", "-m", "add the secret"]).success());
+
+        let handle = GitConfig::new(Credentials::Empty, "https://github.com/shigedangao/gogo.git", target).unwrap();
+        let head = handle.get_commit_hash().unwrap();
+        assert_eq!(handle.resolve_revision("HEAD").unwrap(), head);
+
+        let option = format!("--output={}", output.display());
+        assert!(handle.resolve_revision(&option).is_err());
+        assert!(handle.get_revision_hash(&option).is_none());
+        assert!(handle.list_files(&option).is_err());
+        assert!(handle.export_file_at_revision(&option, "secret.enc.yaml").is_err());
+        assert!(!output.exists());
+    }
+
     #[test]
     fn expect_to_clone_private_repo() {
         // read the env as the token is stored in the env
@@ -20,14 +20,8 @@ pub mod service;
 pub async fn bootstrap_server(state: State) -> Result<(), Error> {
     info!("Gearing up the krapao server");
     let addr = match std::env::var_os("MODE") {
-        Some(res) => {
-            if res == "release" {
-                "0.0.0.0"
-            } else {
-                "127.0.0.1"
-            }
-        },
-        None => "127.0.0.1"
+        Some(res) if res == "release" => "0.0.0.0",
+        _ => "127.0.0.1"
     };
 
     let addr = format!("{addr}:50208").parse()
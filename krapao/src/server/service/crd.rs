@@ -3,19 +3,32 @@ use self::proto::{
     crd_service_server::CrdService,
     Response as ProtoResponse,
     Payload,
+    Commit
 };
 use crate::state;
 use crate::err::Error;
 use crate::sops;
 use crate::auth::Provider;
+use crate::repo::config::FileCommit;
 
 // Constant
-const REPO_NOT_EXIST_ERR_MSG: &str = "Repository does not exist";
+const HEAD: &str = "HEAD";
 
 pub mod proto {
     tonic::include_proto!("crd");
 }
 
+impl From<FileCommit> for Commit {
+    fn from(commit: FileCommit) -> Self {
+        Commit {
+            hash: commit.hash,
+            author: commit.author,
+            date: commit.date,
+            subject: commit.subject
+        }
+    }
+}
+
 #[derive(Debug, Default, Clone)]
 pub struct CrdHandler {
     pub state: state::State
@@ -32,20 +45,43 @@ impl CrdService for CrdHandler {
         let guard = self.state.lock()
             .map_err(|err| Error::Server(err.to_string()))?;
 
-        let config = guard.get(&input.repository)
-            .ok_or_else(|| Error::Server(REPO_NOT_EXIST_ERR_MSG.to_owned()))?;
+        // the owner may have been set up with another url. The repository is cloned again by miwen in that case
+        let config = state::get_owned_repository(&guard, &input.owner)
+            .ok()
+            .filter(|config| config.repo_uri == input.repository)
+            .ok_or_else(|| Error::MissingRepository(input.owner.to_owned()))?;
 
         let provider = Provider::new(&input);
         provider.authenticate()?;
 
-        let res = sops::decrypt_file(config, &input.file_to_decrypt, &input.sops_file_path)?;
-        let commit_hash = config.get_commit_hash();
+        let (res, commit_hash, revision) = match &input.revision {
+            Some(revision) => {
+                info!("Rendering the file at the revision {revision}");
+                let revision = config.resolve_revision(revision)?;
+                let res = sops::decrypt_file_at_revision(config, &revision, &input.file_to_decrypt, &input.sops_file_path)?;
+                (res, Some(revision.to_owned()), revision)
+            },
+            None => {
+                let res = sops::decrypt_file(config, &input.file_to_decrypt, &input.sops_file_path)?;
+                (res, config.get_commit_hash(), HEAD.to_owned())
+            }
+        };
+
+        // the commit metadata is informative. The render does not fail if it can't be retrieved
+        let commit = match config.get_file_commit(&revision, &input.file_to_decrypt) {
+            Ok(commit) => commit.map(Commit::from),
+            Err(err) => {
+                warn!("{err}");
+                None
+            }
+        };
 
         info!("✅ File has been decrypted. Sending back data to miwen");
 
         Ok(Response::new(ProtoResponse {
             resource: res,
-            commit_hash
+            commit_hash,
+            commit
         }))
     }
 }
\ No newline at end of file
@@ -3,16 +3,42 @@ use self::proto::{
     repo_service_server::RepoService,
     Payload,
     Response as ProtoResponse,
+    RepositoryStatus,
+    ListFilesPayload,
+    FileList,
+    ListRepositoriesPayload,
+    RepositoryList
 };
-use crate::repo;
+use glob::{Pattern, MatchOptions};
+use crate::repo::{self, config::{self, Credentials}};
 use crate::env::GitCredentials;
 use crate::state;
 use crate::err::Error;
 
+// Constant
+const DEFAULT_REVISION: &str = "HEAD";
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false
+};
+
 pub mod proto {
     tonic::include_proto!("repository");
 }
 
+/// Get the owner of the request. Repositories are referenced by their owner
+/// 
+/// # Arguments
+/// * `owner` - &str
+fn get_owner(owner: &str) -> Result<String, Error> {
+    if owner.is_empty() {
+        return Err(Error::Server("The owner of the repository is not specified".to_owned()));
+    }
+
+    Ok(owner.to_owned())
+}
+
 #[derive(Debug, Default, Clone)]
 pub struct RepoHandler {
     pub state: state::State
@@ -27,6 +53,10 @@ impl RepoService for RepoHandler {
     ///     - Clone the repository
     ///     - Store the state which can be used by an async task run in parallel with the gRPC server
     /// 
+    /// A repository is cloned per url and credentials and is referenced by the owner of the request. When the url
+    /// or the credentials of the owner changed (e.g: rotated token), the previous repository is released and
+    /// deleted if no other owner reference it. The repository is cloned again if it has been removed from the disk
+    /// 
     /// # Arguments
     /// * `&self` - Self
     /// * `request` - Request<Payload>
@@ -35,24 +65,35 @@ impl RepoService for RepoHandler {
         request: Request<Payload>
     ) -> Result<Response<ProtoResponse>, Status> {
         let input = request.into_inner();
+        let owner = get_owner(&input.owner)?;
         // retrieve the env from the request
         let env = GitCredentials::from(input);
+        let key = config::get_repository_key(&env.repository, &Credentials::new(&env));
         // retrieve the state
         let mut state = self.state.lock()
             .map_err(|err| Error::Server(err.to_string()))?;
 
+        // the owner may reference another repository if its url or its credentials changed
+        state::release_owner(&mut state, &owner, Some(&key))?;
+
         // if the state is already contain the repository then we don't need to clone it again
-        if state.contains_key(&env.repository) {
+        if let Some(config) = state.get_mut(&key) {
+            config.init_repository()?;
+            if config.owners.insert(owner) {
+                state::save_new_repo_in_persistent_state(config.clone())?;
+            }
+
             return Ok(Response::new(ProtoResponse {
                 done: true
             }))
         }
 
         // maybe do this async ?
-        let config = repo::initialize_git(&env)?;
+        let mut config = repo::initialize_git(&env)?;
+        config.owners.insert(owner);
         
         // add the new git config in the state
-        state.insert(env.repository, config.clone());
+        state.insert(key, config.clone());
         state::save_new_repo_in_persistent_state(config)?;
 
         Ok(Response::new(ProtoResponse {
@@ -61,10 +102,10 @@ impl RepoService for RepoHandler {
         // in this case we're going to trigger the creation of a new repo
     }
 
-    /// Delete a repository from the list of repository
-    ///     - Remove the repo from the list of repo
-    ///     - Remove the repo from the persistent state
-    ///     - Delete the repository
+    /// Release the repository referenced by the owner
+    ///     - Remove the owner from the repository
+    ///     - Remove the repo from the list of repo and from the persistent state if it has no owner
+    ///     - Delete the repository if it has no owner
     /// 
     /// # Arguments
     /// * `self` - Self
@@ -74,25 +115,94 @@ impl RepoService for RepoHandler {
         request: Request<Payload>
     ) -> Result<Response<ProtoResponse>, Status> {
         let input = request.into_inner();
-        // convert the input as an Env
-        let env = GitCredentials::from(input);
+        let owner = get_owner(&input.owner)?;
         // get the state
         let mut state = self.state.lock()
             .map_err(|err| Error::Server(err.to_string()))?;
 
-        if state.contains_key(&env.repository) {
-            let config = state.remove(&env.repository);
-            if let Some(git) = config {
-                // remove the value from the persistent state
-                state::remove_repo_from_persistent_state(&env.repository)?;
-                git.delete_repository()?;
-            }
-        } else {
-            info!("No repository to delete");
-        }
+        state::release_owner(&mut state, &owner, None)?;
 
         Ok(Response::new(ProtoResponse {
             done: true,
         }))
     }
+
+    /// Get the status of a repository
+    ///     - The commit hash of the revision (HEAD by default)
+    ///     - The time of the last fetch
+    /// 
+    /// # Arguments
+    /// * `self` - Self
+    /// * `request` - Request<Payload>
+    async fn get_repository_status(
+        &self,
+        request: Request<Payload>
+    ) -> Result<Response<RepositoryStatus>, Status> {
+        let input = request.into_inner();
+        let state = self.state.lock()
+            .map_err(|err| Error::Server(err.to_string()))?;
+
+        let config = state::get_owned_repository(&state, &input.owner)?;
+
+        let commit_hash = match &input.revision {
+            Some(revision) => config.resolve_revision(revision)?,
+            None => config.get_revision_hash(DEFAULT_REVISION)
+                .ok_or_else(|| Error::Revision(format!("Unable to resolve the revision {DEFAULT_REVISION}")))?
+        };
+
+        Ok(Response::new(RepositoryStatus {
+            commit_hash,
+            last_fetch_time: config.get_last_fetch_time()?
+        }))
+    }
+
+    /// List the files of a repository which match the glob pattern
+    /// 
+    /// # Arguments
+    /// * `self` - Self
+    /// * `request` - Request<ListFilesPayload>
+    async fn list_files(
+        &self,
+        request: Request<ListFilesPayload>
+    ) -> Result<Response<FileList>, Status> {
+        let input = request.into_inner();
+        let pattern = Pattern::new(&input.pattern)
+            .map_err(|err| Error::Server(err.to_string()))?;
+
+        let state = self.state.lock()
+            .map_err(|err| Error::Server(err.to_string()))?;
+
+        let config = state::get_owned_repository(&state, &input.owner)?;
+
+        let revision = match &input.revision {
+            Some(revision) => config.resolve_revision(revision)?,
+            None => DEFAULT_REVISION.to_owned()
+        };
+
+        let files = config.list_files(&revision)?
+            .into_iter()
+            .filter(|file| pattern.matches_with(file, MATCH_OPTIONS))
+            .collect();
+
+        Ok(Response::new(FileList { files }))
+    }
+
+    /// List the owners of the repositories handled by krapao
+    /// 
+    /// # Arguments
+    /// * `self` - Self
+    /// * `_` - Request<ListRepositoriesPayload>
+    async fn list_repositories(
+        &self,
+        _: Request<ListRepositoriesPayload>
+    ) -> Result<Response<RepositoryList>, Status> {
+        let state = self.state.lock()
+            .map_err(|err| Error::Server(err.to_string()))?;
+
+        let owners = state.values()
+            .flat_map(|config| config.owners.iter().cloned())
+            .collect();
+
+        Ok(Response::new(RepositoryList { owners }))
+    }
 }
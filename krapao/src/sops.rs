@@ -1,3 +1,5 @@
+use std::fs;
+use std::path::Path;
 use std::process::Command;
 use crate::repo::config::GitConfig;
 use crate::err::Error;
@@ -16,6 +18,41 @@ pub fn decrypt_file(config: &GitConfig, target_file_path: &str, sops_file_path:
     s_file_path.push(sops_file_path);
 
     info!("Trying to decrypt {target_file_path}...");
+    run_sops(&t_file_path, &s_file_path)
+}
+
+/// Decrypt the SOPS file as of the targeted revision. The file is exported from the git history
+/// and removed once it has been decrypted
+/// 
+/// # Arguments
+/// * `config` - &GitConfig
+/// * `revision` - &str
+/// * `target_file_path` - &str
+/// * `sops_file_path` - &str
+pub fn decrypt_file_at_revision(
+    config: &GitConfig,
+    revision: &str,
+    target_file_path: &str,
+    sops_file_path: &str
+) -> Result<String, Error> {
+    let t_file_path = config.export_file_at_revision(revision, target_file_path)?;
+
+    let mut s_file_path = config.target.clone();
+    s_file_path.push(sops_file_path);
+
+    info!("Trying to decrypt {target_file_path} at revision {revision}...");
+    let res = run_sops(&t_file_path, &s_file_path);
+    fs::remove_file(&t_file_path)?;
+
+    res
+}
+
+/// Run the SOPS command which decrypt the targeted file
+/// 
+/// # Arguments
+/// * `t_file_path` - &Path
+/// * `s_file_path` - &Path
+fn run_sops(t_file_path: &Path, s_file_path: &Path) -> Result<String, Error> {
     let cmd = Command::new("sops")
         .arg("-d")
         .arg(t_file_path)
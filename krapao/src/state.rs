@@ -76,13 +76,74 @@ pub fn create_state() -> Result<State, Error>  {
         }
     };
 
-    if let Some(existing_state) = saved_state.repositories {
+    if let Some(mut existing_state) = saved_state.repositories {
+        // repositories saved by a previous version are keyed by url and don't have owners. They're cloned
+        // again when they're referenced by a resource
+        existing_state.retain(|key, config| {
+            let keep = !config.owners.is_empty() && key == &config.get_key();
+            if !keep {
+                info!("Removing the repository {} which is not referenced", config.repo_uri);
+                if let Err(err) = config.delete_repository() {
+                    warn!("{err}");
+                }
+            }
+
+            keep
+        });
+
+        List { repositories: Some(existing_state.clone()) }.save_list_to_persistent_state()?;
+
         return Ok(Arc::new(Mutex::new(existing_state)));
     }
 
     Ok(Arc::new(Mutex::new(HashMap::new())))
 }
 
+/// Get the repository referenced by the owner
+/// 
+/// # Arguments
+/// * `repositories` - &HashMap<String, GitConfig>
+/// * `owner` - &str
+pub fn get_owned_repository<'a>(repositories: &'a HashMap<String, GitConfig>, owner: &str) -> Result<&'a GitConfig, Error> {
+    repositories.values()
+        .find(|config| config.owners.contains(owner))
+        .ok_or_else(|| Error::MissingRepository(owner.to_owned()))
+}
+
+/// Release the repositories referenced by the owner except the kept one. A repository which is no longer
+/// referenced by any owner is deleted
+/// 
+/// # Arguments
+/// * `repositories` - &mut HashMap<String, GitConfig>
+/// * `owner` - &str
+/// * `keep` - Option<&str>
+pub fn release_owner(repositories: &mut HashMap<String, GitConfig>, owner: &str, keep: Option<&str>) -> Result<(), Error> {
+    let mut unused = Vec::new();
+    for (key, config) in repositories.iter_mut() {
+        if Some(key.as_str()) == keep || !config.owners.remove(owner) {
+            continue;
+        }
+
+        info!("Releasing the repository {} referenced by {owner}", config.repo_uri);
+        if config.owners.is_empty() {
+            unused.push(key.to_owned());
+        } else {
+            save_new_repo_in_persistent_state(config.clone())?;
+        }
+    }
+
+    for key in unused {
+        if let Some(config) = repositories.remove(&key) {
+            remove_repo_from_persistent_state(&key)?;
+            if config.target.is_dir() {
+                config.delete_repository()?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
 /// Save the new repo config in the persistent state. 
 /// This enable us to not clone the repo again...
 /// 
@@ -92,10 +153,10 @@ pub fn save_new_repo_in_persistent_state(config: GitConfig) -> Result<(), Error>
     let mut list: List = List::read_persistent_state()?;
 
     if let Some(existing_state) = list.repositories.as_mut() {
-        existing_state.insert(config.repo_uri.clone(), config);
+        existing_state.insert(config.get_key(), config);
     } else {
         let mut map = HashMap::new();
-        map.insert(config.repo_uri.clone(), config);
+        map.insert(config.get_key(), config);
 
         list.repositories = Some(map);
     }
@@ -123,7 +184,8 @@ pub fn remove_repo_from_persistent_state(key: &str) -> Result<(), Error> {
 mod tests {
     use std::path::PathBuf;
 
-    use crate::repo::config::Credentials;
+    use std::collections::BTreeSet;
+    use crate::repo::config::{Credentials, get_repository_key};
 
     use super::*;
 
@@ -154,11 +216,43 @@ mod tests {
         let list = List::read_persistent_state().unwrap();
         let repos = list.repositories.unwrap();
 
-        let maskiedoc = repos.get(repo_uri);
+        let key = get_repository_key(repo_uri, &Credentials::Empty);
+        let maskiedoc = repos.get(&key);
         assert!(maskiedoc.is_some());
 
         // remove the maskiedoc from the state
-        let res = remove_repo_from_persistent_state(repo_uri);
+        let res = remove_repo_from_persistent_state(&key);
         assert!(res.is_ok());
     }
+
+    #[test]
+    fn expect_to_release_repository_wo_owner() {
+        create_state().unwrap();
+        let repo_uri = "https://github.com/shigedangao/release.git";
+        let mut shared = GitConfig::new(Credentials::Empty, repo_uri, PathBuf::new()).unwrap();
+        shared.owners = BTreeSet::from(["Decryptor/default/app".to_owned(), "Decryptor/prod/app".to_owned()]);
+
+        let token = Credentials::Token("foo".to_owned(), "bar".to_owned());
+        let mut private = GitConfig::new(token, repo_uri, PathBuf::new()).unwrap();
+        private.owners = BTreeSet::from(["Decryptor/prod/app".to_owned()]);
+
+        let mut repositories = HashMap::from([
+            (shared.get_key(), shared.clone()),
+            (private.get_key(), private.clone())
+        ]);
+
+        // prod/app now only reference the repository cloned with its credentials
+        release_owner(&mut repositories, "Decryptor/prod/app", Some(&private.get_key())).unwrap();
+        let config = get_owned_repository(&repositories, "Decryptor/prod/app").unwrap();
+        assert_eq!(config.get_key(), private.get_key());
+        assert!(!repositories[&shared.get_key()].owners.contains("Decryptor/prod/app"));
+
+        // the anonymous repository is deleted once default/app release it
+        release_owner(&mut repositories, "Decryptor/default/app", None).unwrap();
+        assert!(!repositories.contains_key(&shared.get_key()));
+        assert!(get_owned_repository(&repositories, "Decryptor/default/app").is_err());
+
+        release_owner(&mut repositories, "Decryptor/prod/app", None).unwrap();
+        assert!(repositories.is_empty());
+    }
 }
\ No newline at end of file
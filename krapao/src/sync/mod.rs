@@ -14,10 +14,12 @@ const THREAD_SLEEP: u64 = 180;
 /// * `state` - State
 pub async fn synchronize_repository(state: State) -> Result<(), Error> {
     loop {
-        let guard = state.lock()
-            .map_err(|err| Error::Sync(err.to_string()))?;
+        // cloning the configs and droping the mutex before sleeping the task to avoid creating deadlock
+        let configs = state.lock()
+            .map_err(|err| Error::Sync(err.to_string()))?
+            .clone();
 
-        for (_, config) in guard.clone().into_iter() {
+        for (_, config) in configs.into_iter() {
             // create an async task which will pull the repository
             // the pull method will exit if the timeout exceed
             // join them altogether...
@@ -28,8 +30,6 @@ pub async fn synchronize_repository(state: State) -> Result<(), Error> {
             });
         }
 
-        // droping the mutex before sleeping the task to avoid creating deadlock
-        drop(guard);
         sleep(Duration::from_secs(THREAD_SLEEP)).await;
     }
 }
\ No newline at end of file
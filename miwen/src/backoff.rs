@@ -0,0 +1,51 @@
+// This mod is used to delay the retries of the Decryptors which keep failing. A Decryptor which fails
+// because of a bad key or a wrong path is retried less and less often until its spec or the repository changed
+use gen::crd::Decryptor;
+use gen::crd::status::{DecryptorStatus, SyncStatus};
+use crate::err::Error;
+use crate::client::server;
+
+/// Get the commit of the repository used by the Decryptor from krapao. The file is not decrypted
+/// which allows to check whenever the repository changed w/o calling the provider
+///
+/// # Arguments
+/// * `decryptor` - &Decryptor
+/// * `ns` - &str
+pub async fn get_repository_commit(decryptor: &Decryptor, ns: &str) -> Result<String, Error> {
+    let (_, revision) = decryptor.spec.get_repository(ns).await?;
+    let status = server::get_repository_status(&server::get_owner(decryptor), revision).await?;
+
+    Ok(status.commit_hash)
+}
+
+/// Build the status of a failed synchronization. The reason of the error is reported and the
+/// next retry of the Decryptor is scheduled
+///
+/// # Arguments
+/// * `decryptor` - &Decryptor
+/// * `err` - &Error
+/// * `hash` - Option<String>
+/// * `ns` - &str
+pub async fn get_failed_status(decryptor: &Decryptor, err: &Error, hash: Option<String>, ns: &str) -> DecryptorStatus {
+    let mut status = DecryptorStatus::new(
+        SyncStatus::NotSync,
+        Some(err.to_string()),
+        hash
+    );
+
+    status.set_reason(err.reason());
+    if let Error::RolledBack(_) = err {
+        status.set_rolled_back();
+    }
+
+    let commit = match get_repository_commit(decryptor, ns).await {
+        Ok(commit) => Some(commit),
+        Err(err) => {
+            warn!("Unable to retrieve the commit of the repository: {err}");
+            None
+        }
+    };
+
+    status.set_failure(decryptor.status.as_ref(), decryptor.metadata.generation, commit);
+    status
+}
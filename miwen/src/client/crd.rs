@@ -1,8 +1,10 @@
 use std::time::Duration;
 use gen::crd::{
-    DecryptorSpec,
-    provider::{ProviderList}
+    Decryptor,
+    provider::{ProviderList},
+    status::{Reason, CommitInfo}
 };
+use kube::Client;
 use tonic::Request;
 use crate::err::Error;
 use self::proto::{
@@ -11,9 +13,10 @@ use self::proto::{
     Gcp,
     Aws,
     Pgp,
-    Vault
+    Vault,
+    Commit
 };
-use super::REQUEST_TIMEOUT;
+use super::{REQUEST_TIMEOUT, server};
 
 mod proto {
     tonic::include_proto!("crd");
@@ -23,20 +26,26 @@ impl Payload {
     /// Create a new Payload
     /// 
     /// # Arguments
-    /// * `spec` - &DecryptorSpec
+    /// * `decryptor` - &Decryptor
     /// * `ns` - &str
-    async fn new(spec: &DecryptorSpec, ns: &str) -> Result<Self, Error> {
-        let repository = spec.source.repository.url.to_owned();
+    /// * `revision` - Option<String>
+    async fn new(decryptor: &Decryptor, ns: &str, revision: Option<String>) -> Result<Self, Error> {
+        let spec = &decryptor.spec;
+        // the revision of the repository is used unless a revision is targeted (rollback)
+        let (repository, repository_revision) = spec.get_repository(ns).await?;
+        let repository = repository.url;
+        let revision = revision.or(repository_revision);
         let file_to_decrypt = spec.source.file_to_decrypt.to_owned();
         let sops_file_path = spec.source.sops_path.to_owned();
         
-        // get the auth provider from the crd
-        let provider = spec.provider.to_owned();
-        let credentials = provider.get_credentials(ns).await?;
+        // get the auth provider from the crd or from the referenced provider
+        let credentials = spec.get_credentials(ns).await?;
         let mut payload = Payload {
             file_to_decrypt,
             sops_file_path,
             repository,
+            revision,
+            owner: server::get_owner(decryptor),
             ..Default::default()
         };
 
@@ -64,30 +73,66 @@ impl Payload {
     }
 }
 
-/// Get the decrypted Kubernetes object from the RPC server
+impl From<Commit> for CommitInfo {
+    fn from(commit: Commit) -> Self {
+        CommitInfo {
+            hash: commit.hash,
+            author: commit.author,
+            date: commit.date,
+            subject: commit.subject
+        }
+    }
+}
+
+/// Build the request sent to the RPC server
 /// 
 /// # Arguments
-/// * `spec` - &DecryptorSpec
+/// * `payload` - Payload
+fn build_request(payload: Payload) -> Request<Payload> {
+    let mut req = Request::new(payload);
+    req.set_timeout(Duration::from_secs(REQUEST_TIMEOUT));
+
+    req
+}
+
+/// Get the decrypted Kubernetes object from the RPC server alongside the commit hash of the revision
+/// and the last commit which changed the file.
+/// krapao may have lost the repository (e.g: restarted on a fresh volume). In that case
+/// the repository is cloned again and the render is retried
+/// 
+/// # Arguments
+/// * `decryptor` - &Decryptor
+/// * `kube_client` - &Client
 /// * `ns` - &str
-pub async fn get_decrypted_kubernetes_object(spec: &DecryptorSpec, ns: &str) -> Result<(String, String), Error> {
+/// * `revision` - Option<String>
+pub async fn get_decrypted_kubernetes_object(
+    decryptor: &Decryptor,
+    kube_client: &Client,
+    ns: &str,
+    revision: Option<String>
+) -> Result<(String, String, Option<CommitInfo>), Error> {
     info!("Rpc call to retrieve the decrypted kubernetes file...");
     let mut client = CrdServiceClient::connect(super::get_rpc_addr()).await?;
 
     // create the payload
-    let payload = Payload::new(spec, ns).await?;
-
-    // create a request and call the rpc server
-    let mut req = Request::new(payload);
-    req.set_timeout(Duration::from_secs(REQUEST_TIMEOUT));
+    let payload = Payload::new(decryptor, ns, revision).await?;
 
-    let res = client.render(req).await
-        .map_err(|err| Error::Rpc(err.to_string()))?;
+    // call the rpc server
+    let res = match client.render(build_request(payload.clone())).await {
+        Err(status) if super::get_reason(&status) == Reason::RepoNotFound => {
+            warn!("Repository does not exist on the rpc server. Cloning the repository again...");
+            server::dispatch_clone_repository(decryptor, kube_client, ns).await?;
+            client.render(build_request(payload)).await
+        },
+        res => res
+    }?;
 
-    let resp = res.get_ref();
-    let tmpl = resp.resource.clone();
-    let hash = resp.commit_hash.clone().unwrap_or_default();
+    let resp = res.into_inner();
+    let tmpl = resp.resource;
+    let hash = resp.commit_hash.unwrap_or_default();
+    let commit = resp.commit.map(CommitInfo::from);
     
     info!("✅ Template has been rendered.");
 
-    Ok((tmpl, hash))
+    Ok((tmpl, hash, commit))
 }
\ No newline at end of file
@@ -1,6 +1,15 @@
+use tonic::{Code, Status};
+use prost::Message;
+use gen::crd::status::Reason;
+use self::proto::{ErrorDetails, Reason as ProtoReason};
+
 pub mod server;
 pub mod crd;
 
+mod proto {
+    tonic::include_proto!("error");
+}
+
 // Constant
 const REQUEST_TIMEOUT: u64 = 30;
 
@@ -14,4 +23,56 @@ fn get_rpc_addr() -> String {
 
     // use on local dev
     "http://127.0.0.1:50208".to_owned()
-}
\ No newline at end of file
+}
+
+/// Get the reason of a failed rpc call from the details of the status. The code of the status
+/// is used when the status does not have details (e.g: the rpc server can't be reached)
+/// 
+/// # Arguments
+/// * `status` - &Status
+pub fn get_reason(status: &Status) -> Reason {
+    if let Ok(details) = ErrorDetails::decode(status.details()) {
+        match details.reason() {
+            ProtoReason::GitAuthFailed => return Reason::GitAuthFailed,
+            ProtoReason::RepoNotFound => return Reason::RepoNotFound,
+            ProtoReason::CloneFailed => return Reason::CloneFailed,
+            ProtoReason::PullFailed => return Reason::PullFailed,
+            ProtoReason::InvalidRepository => return Reason::InvalidRepository,
+            ProtoReason::RevisionNotFound => return Reason::RevisionNotFound,
+            ProtoReason::DecryptFailed => return Reason::DecryptFailed,
+            ProtoReason::ProviderAuthFailed => return Reason::ProviderAuthFailed,
+            ProtoReason::Internal => return Reason::Internal,
+            ProtoReason::Unknown => {}
+        }
+    }
+
+    match status.code() {
+        Code::NotFound => Reason::RepoNotFound,
+        Code::Unauthenticated => Reason::GitAuthFailed,
+        Code::Unavailable | Code::DeadlineExceeded | Code::Cancelled => Reason::Unavailable,
+        _ => Reason::Internal
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expect_to_get_reason_from_details() {
+        let details = ErrorDetails {
+            reason: ProtoReason::DecryptFailed as i32,
+            message: "Error with SOPS: no key could decrypt the data key".to_owned()
+        };
+
+        let status = Status::with_details(Code::FailedPrecondition, "sops", details.encode_to_vec().into());
+        assert_eq!(get_reason(&status), Reason::DecryptFailed);
+    }
+
+    #[test]
+    fn expect_to_get_reason_from_code() {
+        assert_eq!(get_reason(&Status::unavailable("connection refused")), Reason::Unavailable);
+        assert_eq!(get_reason(&Status::not_found("missing")), Reason::RepoNotFound);
+        assert_eq!(get_reason(&Status::unknown("unknown")), Reason::Internal);
+    }
+}
@@ -1,13 +1,21 @@
 use std::time::Duration;
-use gen::crd::{DecryptorSpec, repo::RepositoryCredentials};
-use kube::Client;
+use gen::crd::{
+    Decryptor,
+    repo::{Repository, RepositoryCredentials}
+};
+use kube::{Client, Resource, ResourceExt};
 use tonic::Request;
 use crate::err::Error;
+use crate::state::get_key;
 use self::proto::{
     repo_service_client::RepoServiceClient,
     Payload,
-    Credentials
+    Credentials,
+    ListFilesPayload,
+    ListRepositoriesPayload
 };
+
+pub use self::proto::RepositoryStatus;
 use super::REQUEST_TIMEOUT;
 
 mod proto {
@@ -39,34 +47,154 @@ impl Credentials {
     }
 }
 
+/// Get the owner of the repository of a resource in krapao. The repositories are referenced by the kind,
+/// the namespace and the name of the resource. Hence resources of different namespaces using the same
+/// repository are tracked separately
+/// 
+/// # Arguments
+/// * `resource` - &K
+pub fn get_owner<K>(resource: &K) -> String
+where
+    K: Resource<DynamicType = ()>
+{
+    let key = get_key(&resource.namespace().unwrap_or_default(), &resource.name());
+    format!("{}/{key}", K::kind(&()))
+}
+
+/// Dispatch to krapao rpc server the repository of the Decryptor to clone
+/// 
+/// # Arguments
+/// * `decryptor` - &Decryptor
+/// * `kube_client` - &Client
+/// * `ns` - &str
+pub async fn dispatch_clone_repository(decryptor: &Decryptor, kube_client: &Client, ns: &str) -> Result<(), Error> {
+    let (repository, _) = decryptor.spec.get_repository(ns).await?;
+
+    clone_repository(&repository, kube_client, ns, &get_owner(decryptor)).await
+}
+
 /// Dispatch to krapao rpc server the repository to clone
 ///     - Build credentials needed for krapao to clone the repository if needed
+///     - Reference the repository by the owner
 /// 
 /// # Arguments
-/// * `spec` - &DecryptorSpec
+/// * `repository` - &Repository
 /// * `kube_client` - &Client
 /// * `ns` - &str
-pub async fn dispatch_clone_repository(spec: &DecryptorSpec, kube_client: &Client, ns: &str) -> Result<(), Error> {
+/// * `owner` - &str
+pub async fn clone_repository(repository: &Repository, kube_client: &Client, ns: &str, owner: &str) -> Result<(), Error> {
     info!("Rpc call to clone the target repository...");
     let mut client = RepoServiceClient::connect(super::get_rpc_addr()).await?;
     // request to grpc server
     // build credentials
-    let spec_creds = spec.source.repository.credentials.clone();
-    let cred = match spec_creds {
+    let cred = match repository.credentials.clone() {
         Some(res) => Some(Credentials::build(res, kube_client, ns).await?),
         None => None
     };
 
     let mut req = Request::new(Payload {
-        url: spec.source.repository.url.clone(),
-        cred
+        url: repository.url.clone(),
+        cred,
+        revision: None,
+        owner: owner.to_owned()
     });
     req.set_timeout(Duration::from_secs(REQUEST_TIMEOUT));
     
-    client.set_repository(req).await
-        .map_err(|err| Error::Rpc(err.to_string()))?;
+    client.set_repository(req).await?;
     
     info!("Repository has been setted up");
 
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Get the status of the repository referenced by the owner from the krapao rpc server
+/// 
+/// # Arguments
+/// * `owner` - &str
+/// * `revision` - Option<String>
+pub async fn get_repository_status(owner: &str, revision: Option<String>) -> Result<RepositoryStatus, Error> {
+    let mut client = RepoServiceClient::connect(super::get_rpc_addr()).await?;
+    let mut req = Request::new(Payload {
+        revision,
+        owner: owner.to_owned(),
+        ..Default::default()
+    });
+    req.set_timeout(Duration::from_secs(REQUEST_TIMEOUT));
+
+    let res = client.get_repository_status(req).await?;
+
+    Ok(res.into_inner())
+}
+
+/// List the files of the repository referenced by the owner matching the glob pattern from the krapao rpc server
+/// 
+/// # Arguments
+/// * `owner` - &str
+/// * `pattern` - &str
+/// * `revision` - Option<String>
+pub async fn list_files(owner: &str, pattern: &str, revision: Option<String>) -> Result<Vec<String>, Error> {
+    let mut client = RepoServiceClient::connect(super::get_rpc_addr()).await?;
+    let mut req = Request::new(ListFilesPayload {
+        pattern: pattern.to_owned(),
+        revision,
+        owner: owner.to_owned(),
+        ..Default::default()
+    });
+    req.set_timeout(Duration::from_secs(REQUEST_TIMEOUT));
+
+    let res = client.list_files(req).await?;
+
+    Ok(res.into_inner().files)
+}
+
+/// List the owners of the repositories handled by the krapao rpc server
+pub async fn list_repositories() -> Result<Vec<String>, Error> {
+    let mut client = RepoServiceClient::connect(super::get_rpc_addr()).await?;
+    let mut req = Request::new(ListRepositoriesPayload {});
+    req.set_timeout(Duration::from_secs(REQUEST_TIMEOUT));
+
+    let res = client.list_repositories(req).await?;
+
+    Ok(res.into_inner().owners)
+}
+
+/// Dispatch to krapao rpc server the release of the repository referenced by the owner. The repository
+/// is deleted by krapao once no owner reference it
+/// 
+/// # Arguments
+/// * `owner` - &str
+pub async fn delete_repository(owner: &str) -> Result<(), Error> {
+    let mut client = RepoServiceClient::connect(super::get_rpc_addr()).await?;
+    let mut req = Request::new(Payload {
+        owner: owner.to_owned(),
+        ..Default::default()
+    });
+    req.set_timeout(Duration::from_secs(REQUEST_TIMEOUT));
+
+    client.delete_repository(req).await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gen::crd::git_repository::{GitRepository, GitRepositorySpec};
+
+    fn get_repository(ns: &str) -> GitRepository {
+        let mut repository = GitRepository::new("app", GitRepositorySpec {
+            url: "https://github.com/foo/bar.git".to_owned(),
+            revision: None,
+            credentials: None
+        });
+        repository.metadata.namespace = Some(ns.to_owned());
+
+        repository
+    }
+
+    #[test]
+    fn expect_owner_to_be_namespaced() {
+        assert_eq!(get_owner(&get_repository("default")), "GitRepository/default/app");
+        assert_ne!(get_owner(&get_repository("default")), get_owner(&get_repository("prod")));
+    }
+}
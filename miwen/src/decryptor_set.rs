@@ -0,0 +1,269 @@
+// This mod is used to reconcile the DecryptorSet resources. A Decryptor is generated for each encrypted file
+// of the repository matching the path glob. Decryptors of files which no longer match are deleted
+use std::collections::BTreeMap;
+use kube::{
+    Api,
+    Client,
+    Resource,
+    ResourceExt,
+    Error as KubeError,
+    api::{ListParams, Patch, PatchParams, DeleteParams}
+};
+use kube::runtime::watcher::Event;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::OwnerReference;
+use gen::crd::Decryptor;
+use gen::crd::decryptor_set::{DecryptorSet, DecryptorSetStatus};
+use gen::util::get_label_value;
+use crate::err::Error;
+use crate::client::server;
+use crate::scope::{WatchScope, ScopedWatcher};
+use crate::state::get_key;
+
+// Constant
+const DECRYPTOR_SET_LABEL: &str = "jiemi.cr/decryptor-set";
+const FIELD_MANAGER: &str = "miwen";
+const NOT_FOUND_STATUS_CODE: u16 = 404;
+
+/// Get the owner reference of the DecryptorSet set on the generated Decryptors. This allows
+/// the Decryptors to be garbage collected when the DecryptorSet is deleted
+///
+/// # Arguments
+/// * `set` - &DecryptorSet
+/// * `name` - &str
+fn get_owner_reference(set: &DecryptorSet, name: &str) -> Result<OwnerReference, Error> {
+    let uid = set.metadata.uid.to_owned()
+        .ok_or_else(|| Error::Generator("DecryptorSet does not have an uid".to_owned()))?;
+
+    Ok(OwnerReference {
+        api_version: DecryptorSet::api_version(&()).to_string(),
+        kind: DecryptorSet::kind(&()).to_string(),
+        name: name.to_owned(),
+        uid,
+        controller: Some(true),
+        block_owner_deletion: Some(true)
+    })
+}
+
+/// Check whenever the Decryptor is owned by the DecryptorSet
+///
+/// # Arguments
+/// * `decryptor` - &Decryptor
+/// * `owner` - &OwnerReference
+fn is_owned_by(decryptor: &Decryptor, owner: &OwnerReference) -> bool {
+    decryptor.owner_references()
+        .iter()
+        .any(|reference| reference.uid == owner.uid)
+}
+
+/// Check whenever the Decryptor of a file can be applied by the DecryptorSet. A Decryptor which already
+/// exists and is not owned by the DecryptorSet (e.g: created by a user) is never taken over
+///
+/// # Arguments
+/// * `existing` - Option<&Decryptor>
+/// * `owner` - &OwnerReference
+fn can_apply_child(existing: Option<&Decryptor>, owner: &OwnerReference) -> bool {
+    existing
+        .map(|decryptor| is_owned_by(decryptor, owner))
+        .unwrap_or(true)
+}
+
+/// Apply the Decryptor of a file. The Decryptor is skipped if it exists and is not owned by the DecryptorSet
+///
+/// # Arguments
+/// * `api` - &Api<Decryptor>
+/// * `decryptor` - &Decryptor
+/// * `owner` - &OwnerReference
+async fn apply_child(api: &Api<Decryptor>, decryptor: &Decryptor, owner: &OwnerReference) -> Result<(), Error> {
+    let name = decryptor.name();
+    let existing = match api.get(&name).await {
+        Ok(existing) => Some(existing),
+        Err(KubeError::Api(err)) if err.code == NOT_FOUND_STATUS_CODE => None,
+        Err(err) => return Err(Error::from(err))
+    };
+
+    if !can_apply_child(existing.as_ref(), owner) {
+        return Err(Error::Apply(format!("Decryptor {name} already exists and is not owned by the DecryptorSet")));
+    }
+
+    api.patch(&name, &PatchParams::apply(FIELD_MANAGER).force(), &Patch::Apply(decryptor)).await?;
+
+    Ok(())
+}
+
+/// Generate the Decryptors of the DecryptorSet
+///     - Clone the repository with krapao if it's not already cloned
+///     - List the files of the repository matching the path glob
+///     - Apply a Decryptor for each file
+///     - Delete the Decryptors of the files which no longer match
+///
+/// A Decryptor which fails to be applied does not prevent the others to be applied. The generated Decryptors
+/// are returned alongside the failures
+///
+/// # Arguments
+/// * `set` - &DecryptorSet
+/// * `client` - &Client
+async fn generate_decryptors(set: &DecryptorSet, client: &Client) -> Result<(Vec<String>, Vec<String>), Error> {
+    let (name, ns) = set.get_metadata_info()?;
+    let (repository, revision) = set.get_repository(&ns).await?;
+
+    let owner = server::get_owner(set);
+    server::clone_repository(&repository, client, &ns, &owner).await?;
+    let files = server::list_files(&owner, &set.spec.generator.path, revision).await?;
+
+    let owner = get_owner_reference(set, &name)?;
+    let label = get_label_value(&name);
+    let api: Api<Decryptor> = Api::namespaced(client.clone(), &ns);
+
+    let mut expected = Vec::new();
+    let mut generated = Vec::new();
+    let mut failures = Vec::new();
+    for (child_name, spec) in set.get_children(&files)? {
+        let mut decryptor = Decryptor::new(&child_name, spec);
+        decryptor.metadata.namespace = Some(ns.to_owned());
+        decryptor.metadata.labels = Some(BTreeMap::from([(DECRYPTOR_SET_LABEL.to_owned(), label.to_owned())]));
+        decryptor.metadata.owner_references = Some(vec![owner.clone()]);
+
+        match apply_child(&api, &decryptor, &owner).await {
+            Ok(_) => generated.push(child_name.to_owned()),
+            Err(err) => {
+                error!("Unable to apply the Decryptor {}: {err}", get_key(&ns, &child_name));
+                failures.push(format!("{child_name}: {err}"));
+            }
+        }
+
+        expected.push(child_name);
+    }
+
+    // delete the Decryptors of the files which have been removed from the repository. The label can be set by anyone
+    // hence only the Decryptors owned by the DecryptorSet are deleted
+    let selector = format!("{DECRYPTOR_SET_LABEL}={label}");
+    for decryptor in api.list(&ListParams::default().labels(&selector)).await? {
+        let child_name = decryptor.name();
+        if !expected.contains(&child_name) && is_owned_by(&decryptor, &owner) {
+            info!("Deleting the Decryptor {} as the file no longer match the DecryptorSet {}", get_key(&ns, &child_name), get_key(&ns, &name));
+            if let Err(err) = api.delete(&child_name, &DeleteParams::default()).await {
+                failures.push(format!("{child_name}: {err}"));
+            }
+        }
+    }
+
+    Ok((generated, failures))
+}
+
+/// Reconcile the DecryptorSet. An error is reported in the status if the Decryptors can't be generated
+///
+/// # Arguments
+/// * `set` - &mut DecryptorSet
+/// * `client` - &Client
+pub async fn reconcile(set: &mut DecryptorSet, client: &Client) -> Result<(), Error> {
+    let status = match generate_decryptors(set, client).await {
+        Ok((generated, failures)) => DecryptorSetStatus::synced(generated, failures),
+        Err(err) => {
+            error!("Unable to reconcile the DecryptorSet {}: {err}", get_key(&set.namespace().unwrap_or_default(), &set.name()));
+            DecryptorSetStatus::failed(set.status.as_ref(), err.to_string())
+        }
+    };
+
+    set.update_status(status).await?;
+
+    Ok(())
+}
+
+/// Refresh every DecryptorSet of the watched namespaces. This is used by the sync process
+/// as files may be added or removed from the repositories pulled by krapao
+///
+/// # Arguments
+/// * `client` - &Client
+/// * `scope` - &WatchScope
+pub async fn refresh_decryptor_sets(client: &Client, scope: &WatchScope) -> Result<(), Error> {
+    for api in scope.get_apis::<DecryptorSet>(client).await? {
+        for mut set in api.list(&ListParams::default()).await? {
+            if let Err(err) = reconcile(&mut set, client).await {
+                error!("Unable to refresh the DecryptorSet {}: {err}", set.name());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Create a watcher which will watch the DecryptorSet resources of the watched namespaces.
+/// A DecryptorSet is reconciled when a new generation of the resource is observed
+///
+/// # Arguments
+/// * `scope` - WatchScope
+pub async fn bootstrap_decryptor_set_watcher(scope: WatchScope) -> Result<(), Error> {
+    info!("Starting up the DecryptorSet watcher...");
+    let client = Client::try_default().await?;
+
+    let mut watcher = ScopedWatcher::<DecryptorSet>::new(scope, &client).await?;
+
+    while let Some(event) = watcher.try_next().await? {
+        if let Event::Applied(mut set) = event {
+            // updating the status trigger a new event. The generation is used to skip it
+            if set.is_reconciled() {
+                continue;
+            }
+
+            let client = client.clone();
+            tokio::spawn(async move {
+                if let Err(err) = reconcile(&mut set, &client).await {
+                    error!("{err}");
+                }
+            });
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_decryptor(owner_uid: &str) -> Decryptor {
+        serde_json::from_value(serde_json::json!({
+            "apiVersion": "jiemi.cr/v1alpha1",
+            "kind": "Decryptor",
+            "metadata": {
+                "name": "services-foo",
+                "ownerReferences": [{
+                    "apiVersion": "jiemi.cr/v1alpha1",
+                    "kind": "DecryptorSet",
+                    "name": "services",
+                    "uid": owner_uid
+                }]
+            },
+            "spec": {
+                "provider": {},
+                "source": {
+                    "fileToDecrypt": "foo.enc.yaml",
+                    "sopsPath": ".sops.yaml"
+                }
+            }
+        })).unwrap()
+    }
+
+    #[test]
+    fn expect_to_only_prune_owned_decryptors() {
+        let owner = OwnerReference {
+            uid: "1234".to_owned(),
+            ..Default::default()
+        };
+
+        assert!(is_owned_by(&get_decryptor("1234"), &owner));
+        assert!(!is_owned_by(&get_decryptor("5678"), &owner));
+    }
+
+    #[test]
+    fn expect_to_not_take_over_existing_decryptor() {
+        let owner = OwnerReference {
+            uid: "1234".to_owned(),
+            ..Default::default()
+        };
+
+        assert!(can_apply_child(None, &owner));
+        assert!(can_apply_child(Some(&get_decryptor("1234")), &owner));
+        assert!(!can_apply_child(Some(&get_decryptor("5678")), &owner));
+    }
+}
@@ -0,0 +1,213 @@
+// This mod is used to order the Decryptors. A Decryptor which depends on other Decryptors is held
+// in a DependencyNotReady condition until every dependency is Ready at its current generation
+use std::collections::{HashMap, HashSet, VecDeque};
+use kube::{
+    Api,
+    Client,
+    Error as KubeError
+};
+use gen::crd::Decryptor;
+use gen::crd::status::{Condition, DecryptorStatus, SyncStatus, READY_CONDITION};
+use crate::err::Error;
+use crate::state::get_key;
+
+// Constant
+const DEPENDENCY_NOT_READY_REASON: &str = "DependencyNotReady";
+const DEPENDENCY_CYCLE_REASON: &str = "DependencyCycle";
+const NOT_FOUND_CODE: u16 = 404;
+
+/// Graph of the dependencies. Decryptors are identified by their namespace/name key
+type Graph = HashMap<String, Vec<String>>;
+
+/// Get the namespace and the name of the dependencies of a Decryptor
+///
+/// # Arguments
+/// * `decryptor` - &Decryptor
+/// * `ns` - &str
+fn get_dependencies(decryptor: &Decryptor, ns: &str) -> Vec<(String, String)> {
+    decryptor.spec.depends_on.to_owned()
+        .unwrap_or_default()
+        .into_iter()
+        .map(|dep| (dep.get_namespace(ns), dep.name))
+        .collect()
+}
+
+/// Visit the graph depth first. A cycle is found when a node which is on the current path is visited again
+///
+/// # Arguments
+/// * `graph` - &Graph
+/// * `node` - &str
+/// * `path` - &mut Vec<String>
+/// * `visited` - &mut HashSet<String>
+fn visit(graph: &Graph, node: &str, path: &mut Vec<String>, visited: &mut HashSet<String>) -> Option<Vec<String>> {
+    if let Some(pos) = path.iter().position(|n| n == node) {
+        let mut cycle = path[pos..].to_vec();
+        cycle.push(node.to_owned());
+
+        return Some(cycle);
+    }
+
+    if !visited.insert(node.to_owned()) {
+        return None;
+    }
+
+    path.push(node.to_owned());
+    for next in graph.get(node).into_iter().flatten() {
+        if let Some(cycle) = visit(graph, next, path, visited) {
+            return Some(cycle);
+        }
+    }
+    path.pop();
+
+    None
+}
+
+/// Find a cycle in the dependencies reachable from the start node
+///
+/// # Arguments
+/// * `graph` - &Graph
+/// * `start` - &str
+fn find_cycle(graph: &Graph, start: &str) -> Option<Vec<String>> {
+    visit(graph, start, &mut Vec::new(), &mut HashSet::new())
+}
+
+/// Get a Decryptor. None is returned when the Decryptor does not exist
+///
+/// # Arguments
+/// * `client` - &Client
+/// * `ns` - &str
+/// * `name` - &str
+async fn get_decryptor(client: &Client, ns: &str, name: &str) -> Result<Option<Decryptor>, Error> {
+    let api: Api<Decryptor> = Api::namespaced(client.clone(), ns);
+    match api.get(name).await {
+        Ok(decryptor) => Ok(Some(decryptor)),
+        Err(KubeError::Api(err)) if err.code == NOT_FOUND_CODE => Ok(None),
+        Err(err) => Err(Error::from(err))
+    }
+}
+
+/// Check the dependencies of the Decryptor. The dependencies are resolved transitively in order to detect cycles.
+/// A Ready condition which hold the Decryptor is returned if any dependency is missing, not Ready or if a cycle is found
+///
+/// # Arguments
+/// * `decryptor` - &Decryptor
+/// * `client` - &Client
+pub async fn check_dependencies(decryptor: &Decryptor, client: &Client) -> Result<Option<Condition>, Error> {
+    let (name, _, ns) = decryptor.get_metadata_info()?;
+    let dependencies = get_dependencies(decryptor, &ns);
+    if dependencies.is_empty() {
+        return Ok(None);
+    }
+
+    let root = get_key(&ns, &name);
+    let mut graph = Graph::new();
+    let mut fetched = HashMap::new();
+    let mut queue: VecDeque<(String, String)> = dependencies.iter().cloned().collect();
+    graph.insert(root.to_owned(), dependencies.iter().map(|(ns, name)| get_key(ns, name)).collect());
+
+    while let Some((dep_ns, dep_name)) = queue.pop_front() {
+        let key = get_key(&dep_ns, &dep_name);
+        if key == root || fetched.contains_key(&key) {
+            continue;
+        }
+
+        let dep = get_decryptor(client, &dep_ns, &dep_name).await?;
+        if let Some(dep) = &dep {
+            let next = get_dependencies(dep, &dep_ns);
+            graph.insert(key.to_owned(), next.iter().map(|(ns, name)| get_key(ns, name)).collect());
+            queue.extend(next);
+        }
+
+        fetched.insert(key, dep);
+    }
+
+    if let Some(cycle) = find_cycle(&graph, &root) {
+        let msg = format!("dependency cycle detected: {}", cycle.join(" -> "));
+        return Ok(Some(Condition::new(READY_CONDITION, false, DEPENDENCY_CYCLE_REASON, Some(msg))));
+    }
+
+    let not_ready: Vec<String> = dependencies.iter()
+        .map(|(ns, name)| get_key(ns, name))
+        .filter(|key| !matches!(fetched.get(key), Some(Some(dep)) if dep.is_ready()))
+        .collect();
+
+    if not_ready.is_empty() {
+        return Ok(None);
+    }
+
+    let msg = format!("waiting for the dependencies {} to be Ready", not_ready.join(", "));
+    Ok(Some(Condition::new(READY_CONDITION, false, DEPENDENCY_NOT_READY_REASON, Some(msg))))
+}
+
+/// Hold the Decryptor by setting the Ready condition returned by the dependency check.
+/// The status is only updated when the condition changed
+///
+/// # Arguments
+/// * `decryptor` - &mut Decryptor
+/// * `condition` - Condition
+pub async fn hold(decryptor: &mut Decryptor, condition: Condition) -> Result<(), Error> {
+    let (name, _, ns) = decryptor.get_metadata_info()?;
+    info!("{} is held: {}", get_key(&ns, &name), condition.message.as_deref().unwrap_or_default());
+
+    let mut status = decryptor.status.to_owned()
+        .unwrap_or_else(|| DecryptorStatus::new(SyncStatus::NotSync, condition.message.to_owned(), None));
+
+    let changed = match status.get_condition(READY_CONDITION) {
+        Some(existing) => existing.status != condition.status
+            || existing.reason != condition.reason
+            || existing.message != condition.message,
+        None => true
+    };
+
+    if changed {
+        status.set_condition(condition);
+        decryptor.status = Some(status);
+        decryptor.update_status().await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_graph(edges: &[(&str, &[&str])]) -> Graph {
+        edges.iter()
+            .map(|(node, next)| (node.to_string(), next.iter().map(|n| n.to_string()).collect()))
+            .collect()
+    }
+
+    #[test]
+    fn expect_to_not_find_cycle() {
+        let graph = get_graph(&[
+            ("default/app", &["default/sa", "default/ns"]),
+            ("default/sa", &["default/ns"]),
+            ("default/ns", &[])
+        ]);
+
+        assert!(find_cycle(&graph, "default/app").is_none());
+    }
+
+    #[test]
+    fn expect_to_find_cycle() {
+        let graph = get_graph(&[
+            ("default/app", &["default/sa"]),
+            ("default/sa", &["jiemi/ns"]),
+            ("jiemi/ns", &["default/app"])
+        ]);
+
+        let cycle = find_cycle(&graph, "default/app").unwrap();
+        assert_eq!(cycle, vec!["default/app", "default/sa", "jiemi/ns", "default/app"]);
+    }
+
+    #[test]
+    fn expect_to_find_self_dependency() {
+        let graph = get_graph(&[
+            ("default/app", &["default/app"])
+        ]);
+
+        let cycle = find_cycle(&graph, "default/app").unwrap();
+        assert_eq!(cycle, vec!["default/app", "default/app"]);
+    }
+}
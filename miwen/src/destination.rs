@@ -0,0 +1,513 @@
+// This mod is used to get the cluster where the decrypted file of a Decryptor is applied
+use std::sync::{Arc, RwLock};
+use std::collections::{HashMap, HashSet};
+use http::{Request, HeaderValue, header::HeaderName};
+use hyper::Body;
+use hyper_timeout::TimeoutConnector;
+use tokio::sync::OnceCell;
+use tower::ServiceBuilder;
+use kube::{
+    Api,
+    Client,
+    Config,
+    client::ConfigExt,
+    config::{Kubeconfig, KubeConfigOptions}
+};
+use k8s_openapi::api::core::v1::Secret;
+use gen::crd::{Decryptor, destination::SecretRef};
+use crate::err::Error;
+use crate::discovery;
+use crate::policy::Policy;
+
+// Constant
+const LOCK_ERR_MSG: &str = "Unable to acquired lock on the destination cache";
+const IMPERSONATE_USER_HEADER: &str = "impersonate-user";
+const IMPERSONATE_GROUP_HEADER: &str = "impersonate-group";
+const SERVICE_ACCOUNT_GROUP: &str = "system:serviceaccounts";
+
+type Impersonated = Arc<RwLock<HashMap<String, Client>>>;
+
+/// Destination contains the client of a cluster and the discovery cache of this cluster.
+/// The config is only set for remote clusters. The clients impersonating the ServiceAccounts
+/// are built once per ServiceAccount and are released alongside the destination
+#[derive(Clone)]
+pub struct Destination {
+    pub client: Client,
+    pub discovery: discovery::Cache,
+    config: Option<Config>,
+    impersonated: Impersonated
+}
+
+/// Cache of the destinations. Clients of the remote clusters are stored by kubeconfig Secret alongside
+/// the resourceVersion of the Secret. A client is rebuilt when the Secret has been updated.
+/// The config of the cluster where jiemi is running is inferred once
+#[derive(Clone)]
+pub struct Cache {
+    local: discovery::Cache,
+    local_config: Arc<OnceCell<Config>>,
+    local_impersonated: Impersonated,
+    remote: Arc<RwLock<HashMap<String, (String, Destination)>>>
+}
+
+/// Generate a new destination Cache
+pub fn generate_new_cache() -> Cache {
+    Cache {
+        local: discovery::generate_new_cache(),
+        local_config: Arc::new(OnceCell::new()),
+        local_impersonated: Arc::new(RwLock::new(HashMap::new())),
+        remote: Arc::new(RwLock::new(HashMap::new()))
+    }
+}
+
+/// Get the key of a kubeconfig Secret in the cache
+///
+/// # Arguments
+/// * `ns` - &str
+/// * `secret_ref` - &SecretRef
+fn get_key(ns: &str, secret_ref: &SecretRef) -> String {
+    format!("{ns}/{}/{}", secret_ref.name, secret_ref.get_key())
+}
+
+/// Get the kubeconfig and the resourceVersion of the Secret referenced by the Decryptor
+///
+/// # Arguments
+/// * `client` - &Client
+/// * `ns` - &str
+/// * `secret_ref` - &SecretRef
+async fn get_kubeconfig(client: &Client, ns: &str, secret_ref: &SecretRef) -> Result<(String, String), Error> {
+    let api: Api<Secret> = Api::namespaced(client.clone(), ns);
+    let secret = api.get(&secret_ref.name).await?;
+
+    let key = secret_ref.get_key();
+    let resource_version = secret.metadata.resource_version.unwrap_or_default();
+    let value = secret.data
+        .and_then(|mut data| data.remove(&key))
+        .ok_or_else(|| Error::Destination(format!("Key {key} could not be founded in the Secret {}", secret_ref.name)))?;
+
+    let kubeconfig = String::from_utf8(value.0)
+        .map_err(|err| Error::Destination(err.to_string()))?;
+
+    Ok((kubeconfig, resource_version))
+}
+
+/// Check that the kubeconfig only contains inline credentials. The kubeconfig is provided by the tenant
+/// and is loaded within the miwen pod. Hence the following fields are rejected
+///     - exec and auth-provider which run a command or load the credentials of a provider
+///     - tokenFile, client-certificate, client-key and certificate-authority which read a file of the pod
+///
+/// # Arguments
+/// * `kubeconfig` - &Kubeconfig
+fn check_kubeconfig(kubeconfig: &Kubeconfig) -> Result<(), Error> {
+    let mut forbidden = Vec::new();
+    for named in &kubeconfig.auth_infos {
+        let auth = &named.auth_info;
+        let fields = [
+            ("exec", auth.exec.is_some()),
+            ("auth-provider", auth.auth_provider.is_some()),
+            ("tokenFile", auth.token_file.is_some()),
+            ("client-certificate", auth.client_certificate.is_some()),
+            ("client-key", auth.client_key.is_some())
+        ];
+
+        for (field, set) in fields {
+            if set {
+                forbidden.push(format!("users.{}.{field}", named.name));
+            }
+        }
+    }
+
+    for named in &kubeconfig.clusters {
+        if named.cluster.certificate_authority.is_some() {
+            forbidden.push(format!("clusters.{}.certificate-authority", named.name));
+        }
+    }
+
+    if !forbidden.is_empty() {
+        return Err(Error::Destination(format!(
+            "Only inline token and certificate data are supported in the kubeconfig. Forbidden fields: {}",
+            forbidden.join(", ")
+        )));
+    }
+
+    Ok(())
+}
+
+/// Check that the clusters of the kubeconfig are allowed by the policy of the controller for the namespace
+/// of the Decryptor. Remote clusters can't be used when no policy is loaded
+///
+/// # Arguments
+/// * `kubeconfig` - &Kubeconfig
+/// * `ns` - &str
+/// * `policy` - &Policy
+fn check_servers(kubeconfig: &Kubeconfig, ns: &str, policy: &Policy) -> Result<(), Error> {
+    let rule = policy.get_rule(ns)
+        .ok_or_else(|| Error::Destination("Remote clusters needs to be allowed by the policy of the controller".to_owned()))?;
+
+    let denied: Vec<&str> = kubeconfig.clusters.iter()
+        .map(|named| named.cluster.server.as_str())
+        .filter(|server| !rule.is_server_allowed(server))
+        .collect();
+
+    if !denied.is_empty() {
+        return Err(Error::Destination(format!("Servers {} are not allowed for the namespace {ns}", denied.join(", "))));
+    }
+
+    Ok(())
+}
+
+/// Build a client from the kubeconfig. The kubeconfig needs to only use inline credentials
+/// and target servers which are allowed by the policy
+///
+/// # Arguments
+/// * `kubeconfig` - &str
+/// * `ns` - &str
+/// * `policy` - &Policy
+async fn build_config(kubeconfig: &str, ns: &str, policy: &Policy) -> Result<Config, Error> {
+    let kubeconfig = Kubeconfig::from_yaml(kubeconfig)
+        .map_err(|err| Error::Destination(err.to_string()))?;
+
+    check_kubeconfig(&kubeconfig)?;
+    check_servers(&kubeconfig, ns, policy)?;
+
+    let config = Config::from_custom_kubeconfig(kubeconfig, &KubeConfigOptions::default()).await
+        .map_err(|err| Error::Destination(err.to_string()))?;
+
+    Ok(config)
+}
+
+/// Get the headers used to impersonate the ServiceAccount
+/// 
+/// # Arguments
+/// * `service_account` - &str
+/// * `ns` - &str
+fn get_impersonate_headers(service_account: &str, ns: &str) -> Result<Vec<(HeaderName, HeaderValue)>, Error> {
+    let values = [
+        (IMPERSONATE_USER_HEADER, format!("system:serviceaccount:{ns}:{service_account}")),
+        (IMPERSONATE_GROUP_HEADER, SERVICE_ACCOUNT_GROUP.to_owned()),
+        (IMPERSONATE_GROUP_HEADER, format!("{SERVICE_ACCOUNT_GROUP}:{ns}"))
+    ];
+
+    let mut headers = Vec::new();
+    for (name, value) in values {
+        let value = HeaderValue::from_str(&value)
+            .map_err(|err| Error::Destination(err.to_string()))?;
+
+        headers.push((HeaderName::from_static(name), value));
+    }
+
+    Ok(headers)
+}
+
+/// Build a client which impersonate the ServiceAccount of the Decryptor. The service stack mirrors
+/// the one built by kube (timeouts, base uri, auth and extra headers) with the impersonation headers on top
+/// 
+/// # Arguments
+/// * `config` - &Config
+/// * `service_account` - &str
+/// * `ns` - &str
+fn build_impersonated_client(config: &Config, service_account: &str, ns: &str) -> Result<Client, Error> {
+    let headers = get_impersonate_headers(service_account, ns)?;
+    let mut connector = TimeoutConnector::new(config.native_tls_https_connector()?);
+    connector.set_connect_timeout(config.timeout);
+    connector.set_read_timeout(config.timeout);
+
+    let service = ServiceBuilder::new()
+        .layer(config.base_uri_layer())
+        .option_layer(config.auth_layer()?)
+        .layer(config.extra_headers_layer()?)
+        .map_request(move |mut req: Request<Body>| {
+            for (name, value) in &headers {
+                req.headers_mut().append(name.clone(), value.clone());
+            }
+            req
+        })
+        .service(hyper::Client::builder().build(connector));
+
+    Ok(Client::new(service, config.default_namespace.to_owned()))
+}
+
+/// Get the client used to apply the objects of the Decryptor. When the Decryptor specify a
+/// ServiceAccount, the client impersonate the ServiceAccount. Otherwise the client of the destination is used
+/// unless the policy requires a ServiceAccount
+/// 
+/// # Arguments
+/// * `destination` - &Destination
+/// * `service_account` - Option<&str>
+/// * `ns` - &str
+/// * `cache` - &Cache
+/// * `policy` - &Policy
+pub async fn get_apply_client(
+    destination: &Destination,
+    service_account: Option<&str>,
+    ns: &str,
+    cache: &Cache,
+    policy: &Policy
+) -> Result<Client, Error> {
+    let service_account = match service_account {
+        Some(sa) => sa,
+        None if policy.is_service_account_required() => return Err(Error::Policy(
+            "The policy of the controller requires the Decryptor to specify a serviceAccountName".to_owned()
+        )),
+        None => return Ok(destination.client.clone())
+    };
+
+    let key = format!("{ns}/{service_account}");
+    {
+        let impersonated = destination.impersonated.read()
+            .map_err(|_| Error::Destination(LOCK_ERR_MSG.to_owned()))?;
+
+        if let Some(client) = impersonated.get(&key) {
+            return Ok(client.clone());
+        }
+    }
+
+    let config = match &destination.config {
+        Some(config) => config,
+        None => cache.local_config
+            .get_or_try_init(Config::infer).await
+            .map_err(|err| Error::Destination(err.to_string()))?
+    };
+
+    info!("🔌 Building the client impersonating the ServiceAccount {key}");
+    let client = build_impersonated_client(config, service_account, ns)?;
+    let mut impersonated = destination.impersonated.write()
+        .map_err(|_| Error::Destination(LOCK_ERR_MSG.to_owned()))?;
+
+    impersonated.insert(key, client.clone());
+
+    Ok(client)
+}
+
+/// Get the SecretRef of the kubeconfig of the Decryptor if any
+///
+/// # Arguments
+/// * `decryptor` - &Decryptor
+fn get_secret_ref(decryptor: &Decryptor) -> Option<&SecretRef> {
+    decryptor.spec.destination.as_ref()
+        .and_then(|dest| dest.kube_config.as_ref())
+        .map(|kube_config| &kube_config.secret_ref)
+}
+
+/// Get the key of the destination of the Decryptor in the cache. None is returned when the
+/// Decryptor is applied on the cluster where jiemi is running
+///
+/// # Arguments
+/// * `decryptor` - &Decryptor
+/// * `ns` - &str
+pub fn get_destination_key(decryptor: &Decryptor, ns: &str) -> Option<String> {
+    get_secret_ref(decryptor).map(|secret_ref| get_key(ns, secret_ref))
+}
+
+/// Remove the clients of the remote clusters which are no longer referenced by any Decryptor
+///
+/// # Arguments
+/// * `cache` - &Cache
+/// * `used` - &HashSet<String>
+pub fn release_unused_destinations(cache: &Cache, used: &HashSet<String>) -> Result<(), Error> {
+    let mut remote = cache.remote.write()
+        .map_err(|_| Error::Destination(LOCK_ERR_MSG.to_owned()))?;
+
+    remote.retain(|key, _| {
+        let keep = used.contains(key);
+        if !keep {
+            info!("🔌 Releasing the client of the destination {key}");
+        }
+
+        keep
+    });
+
+    Ok(())
+}
+
+/// Get the destination of the Decryptor. The client of the cluster where jiemi is running
+/// is used when the Decryptor does not specify a kubeconfig. The client of a remote cluster
+/// which has been built from a previous resourceVersion of the Secret is evicted from the cache
+///
+/// # Arguments
+/// * `decryptor` - &Decryptor
+/// * `client` - &Client
+/// * `ns` - &str
+/// * `cache` - &Cache
+/// * `policy` - &Policy
+pub async fn get_destination(
+    decryptor: &Decryptor,
+    client: &Client,
+    ns: &str,
+    cache: &Cache,
+    policy: &Policy
+) -> Result<Destination, Error> {
+    let secret_ref = match get_secret_ref(decryptor) {
+        Some(secret_ref) => secret_ref,
+        None => return Ok(Destination {
+            client: client.clone(),
+            discovery: cache.local.clone(),
+            config: None,
+            impersonated: cache.local_impersonated.clone()
+        })
+    };
+
+    let key = get_key(ns, secret_ref);
+    let (kubeconfig, resource_version) = get_kubeconfig(client, ns, secret_ref).await?;
+    {
+        let mut remote = cache.remote.write()
+            .map_err(|_| Error::Destination(LOCK_ERR_MSG.to_owned()))?;
+
+        match remote.get(&key) {
+            Some((version, destination)) if version == &resource_version => return Ok(destination.clone()),
+            Some((version, _)) => {
+                info!("🔌 Evicting the client of the destination {key} built from the resourceVersion {version}");
+                remote.remove(&key);
+            },
+            None => {}
+        }
+    }
+
+    info!("🔌 Building the client of the destination {key}");
+    let config = build_config(&kubeconfig, ns, policy).await?;
+    let destination = Destination {
+        client: Client::try_from(config.clone())?,
+        discovery: discovery::generate_new_cache(),
+        config: Some(config),
+        impersonated: Arc::new(RwLock::new(HashMap::new()))
+    };
+
+    let mut remote = cache.remote.write()
+        .map_err(|_| Error::Destination(LOCK_ERR_MSG.to_owned()))?;
+
+    remote.insert(key, (resource_version, destination.clone()));
+
+    Ok(destination)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expect_to_get_key() {
+        let secret_ref = SecretRef {
+            name: "prod-kubeconfig".to_owned(),
+            key: None
+        };
+
+        assert_eq!(get_key("default", &secret_ref), "default/prod-kubeconfig/value");
+    }
+
+    fn get_kubeconfig(user: &str) -> String {
+        format!(r#"
+apiVersion: v1
+kind: Config
+clusters:
+  - name: prod
+    cluster:
+      server: https://prod.example.com
+      certificate-authority-data: Zm9v
+users:
+  - name: prod
+    user:
+{user}
+contexts:
+  - name: prod
+    context:
+      cluster: prod
+      user: prod
+current-context: prod
+"#)
+    }
+
+    fn get_policy() -> Policy {
+        serde_yaml::from_str(r#"
+rules:
+  - sourceNamespace: team-a
+    allowedKinds: ["Secret"]
+    allowedServers: ["https://prod.example.com"]
+"#).unwrap()
+    }
+
+    #[tokio::test]
+    async fn expect_invalid_kubeconfig_to_return_err() {
+        let res = build_config("foo: [", "team-a", &get_policy()).await;
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn expect_inline_credentials_to_be_allowed() {
+        let kubeconfig = Kubeconfig::from_yaml(&get_kubeconfig("      token: foo")).unwrap();
+        assert!(check_kubeconfig(&kubeconfig).is_ok());
+    }
+
+    #[test]
+    fn expect_exec_and_file_credentials_to_be_rejected() {
+        let exec = get_kubeconfig("      exec:\n        apiVersion: client.authentication.k8s.io/v1beta1\n        command: sh");
+        let kubeconfig = Kubeconfig::from_yaml(&exec).unwrap();
+        assert!(check_kubeconfig(&kubeconfig).is_err());
+
+        let token_file = get_kubeconfig("      tokenFile: /var/run/secrets/kubernetes.io/serviceaccount/token");
+        let kubeconfig = Kubeconfig::from_yaml(&token_file).unwrap();
+        assert!(check_kubeconfig(&kubeconfig).is_err());
+
+        let client_key = get_kubeconfig("      client-certificate: /tmp/cert\n      client-key: /tmp/key");
+        let kubeconfig = Kubeconfig::from_yaml(&client_key).unwrap();
+        assert!(check_kubeconfig(&kubeconfig).is_err());
+    }
+
+    #[test]
+    fn expect_servers_to_be_allowed_by_the_policy() {
+        let kubeconfig = Kubeconfig::from_yaml(&get_kubeconfig("      token: foo")).unwrap();
+
+        assert!(check_servers(&kubeconfig, "team-a", &get_policy()).is_ok());
+        assert!(check_servers(&kubeconfig, "team-b", &get_policy()).is_err());
+        assert!(check_servers(&kubeconfig, "team-a", &Policy::default()).is_err());
+    }
+
+    #[tokio::test]
+    async fn expect_to_release_unused_destinations() {
+        let cache = generate_new_cache();
+        let destination = Destination {
+            client: Client::try_from(Config::new("https://prod.example.com".parse().unwrap())).unwrap(),
+            discovery: discovery::generate_new_cache(),
+            config: None,
+            impersonated: Arc::new(RwLock::new(HashMap::new()))
+        };
+
+        {
+            let mut remote = cache.remote.write().unwrap();
+            remote.insert("team-a/prod/value".to_owned(), ("1".to_owned(), destination.clone()));
+            remote.insert("team-a/old/value".to_owned(), ("1".to_owned(), destination));
+        }
+
+        let used = HashSet::from(["team-a/prod/value".to_owned()]);
+        release_unused_destinations(&cache, &used).unwrap();
+
+        let remote = cache.remote.read().unwrap();
+        assert!(remote.contains_key("team-a/prod/value"));
+        assert!(!remote.contains_key("team-a/old/value"));
+    }
+
+    #[tokio::test]
+    async fn expect_service_account_to_be_required_by_the_policy() {
+        let cache = generate_new_cache();
+        let destination = Destination {
+            client: Client::try_from(Config::new("https://prod.example.com".parse().unwrap())).unwrap(),
+            discovery: discovery::generate_new_cache(),
+            config: Some(Config::new("https://prod.example.com".parse().unwrap())),
+            impersonated: Arc::new(RwLock::new(HashMap::new()))
+        };
+
+        let policy: Policy = serde_yaml::from_str("requireServiceAccount: true").unwrap();
+        assert!(get_apply_client(&destination, None, "team-a", &cache, &policy).await.is_err());
+        assert!(get_apply_client(&destination, None, "team-a", &cache, &Policy::default()).await.is_ok());
+
+        get_apply_client(&destination, Some("deployer"), "team-a", &cache, &policy).await.unwrap();
+        assert!(destination.impersonated.read().unwrap().contains_key("team-a/deployer"));
+    }
+
+    #[test]
+    fn expect_to_get_impersonate_headers() {
+        let headers = get_impersonate_headers("deployer", "team-a").unwrap();
+
+        assert_eq!(headers[0].0, "impersonate-user");
+        assert_eq!(headers[0].1, "system:serviceaccount:team-a:deployer");
+        assert_eq!(headers[2].1, "system:serviceaccounts:team-a");
+    }
+}
@@ -0,0 +1,168 @@
+use std::sync::{Arc, RwLock};
+use std::collections::HashMap;
+use kube::{
+    Client,
+    Error as KubeError,
+    core::{GroupVersion, GroupVersionKind},
+    discovery::{self, ApiResource, ApiCapabilities, verbs}
+};
+use crate::err::Error;
+
+// Constant
+const LOCK_ERR_MSG: &str = "Unable to acquired lock on the discovery cache";
+const NOT_FOUND_STATUS_CODE: u16 = 404;
+
+pub type Cache = Arc<RwLock<HashMap<String, (ApiResource, ApiCapabilities)>>>;
+
+/// Generate a new discovery Cache
+/// 
+/// The cache is used to resolve a GVK to the api resource served by the cluster. This allows to
+/// use the real plural, scope and verbs of a kind instead of guessing them
+pub fn generate_new_cache() -> Cache {
+    Arc::new(RwLock::new(HashMap::new()))
+}
+
+/// Get the key of a GVK in the cache
+/// 
+/// # Arguments
+/// * `group` - &str
+/// * `version` - &str
+/// * `kind` - &str
+fn get_key(group: &str, version: &str, kind: &str) -> String {
+    format!("{group}/{version}/{kind}")
+}
+
+/// Get the api resource of the GVK from the cache
+/// 
+/// # Arguments
+/// * `cache` - &Cache
+/// * `gvk` - &GroupVersionKind
+fn get_cached(cache: &Cache, gvk: &GroupVersionKind) -> Result<Option<(ApiResource, ApiCapabilities)>, Error> {
+    let cache = cache.read()
+        .map_err(|_| Error::Watch(LOCK_ERR_MSG.to_owned()))?;
+
+    let key = get_key(&gvk.group, &gvk.version, &gvk.kind);
+
+    Ok(cache.get(&key).cloned())
+}
+
+/// Refresh the api resources of the group version targeted by the GVK. A group version which
+/// is not served by the cluster does not return an error as the kind is unknown
+/// 
+/// # Arguments
+/// * `cache` - &Cache
+/// * `client` - &Client
+/// * `gvk` - &GroupVersionKind
+async fn refresh(cache: &Cache, client: &Client, gvk: &GroupVersionKind) -> Result<(), Error> {
+    info!("🔎 Refreshing the api resources of {}/{}", gvk.group, gvk.version);
+    let gv = GroupVersion::gv(&gvk.group, &gvk.version);
+    let group = match discovery::pinned_group(client, &gv).await {
+        Ok(group) => group,
+        Err(KubeError::Api(err)) if err.code == NOT_FOUND_STATUS_CODE => return Ok(()),
+        Err(err) => return Err(Error::from(err))
+    };
+
+    let mut cache = cache.write()
+        .map_err(|_| Error::Watch(LOCK_ERR_MSG.to_owned()))?;
+
+    for (resource, capabilities) in group.versioned_resources(&gvk.version) {
+        let key = get_key(&resource.group, &resource.version, &resource.kind);
+        cache.insert(key, (resource, capabilities));
+    }
+
+    Ok(())
+}
+
+/// Resolve the GVK to the api resource served by the cluster. The cache is refreshed
+/// when the kind is unknown. This handle kinds which have been created since the last refresh (CRD...)
+/// 
+/// # Arguments
+/// * `cache` - &Cache
+/// * `client` - &Client
+/// * `gvk` - &GroupVersionKind
+pub async fn resolve(cache: &Cache, client: &Client, gvk: &GroupVersionKind) -> Result<(ApiResource, ApiCapabilities), Error> {
+    if let Some(res) = get_cached(cache, gvk)? {
+        return Ok(res);
+    }
+
+    refresh(cache, client, gvk).await?;
+
+    get_cached(cache, gvk)?
+        .ok_or_else(|| Error::Apply(format!("Unknown kind {} for the apiVersion {}", gvk.kind, get_api_version(gvk))))
+}
+
+/// Resolve the GVK and check that the kind can be applied by miwen
+/// 
+/// # Arguments
+/// * `cache` - &Cache
+/// * `client` - &Client
+/// * `gvk` - &GroupVersionKind
+pub async fn resolve_appliable(cache: &Cache, client: &Client, gvk: &GroupVersionKind) -> Result<(ApiResource, ApiCapabilities), Error> {
+    let (resource, capabilities) = resolve(cache, client, gvk).await?;
+    for verb in [verbs::GET, verbs::CREATE, verbs::PATCH] {
+        if !capabilities.supports_operation(verb) {
+            return Err(Error::Apply(format!("Kind {} does not support the {verb} operation", gvk.kind)));
+        }
+    }
+
+    Ok((resource, capabilities))
+}
+
+/// Get the apiVersion of the GVK
+/// 
+/// # Arguments
+/// * `gvk` - &GroupVersionKind
+fn get_api_version(gvk: &GroupVersionKind) -> String {
+    GroupVersion::gv(&gvk.group, &gvk.version).api_version()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kube::discovery::Scope;
+
+    #[test]
+    fn expect_to_get_cached_resource() {
+        let cache = generate_new_cache();
+        let gvk = GroupVersionKind::gvk("networking.k8s.io", "v1", "NetworkPolicy");
+        let resource = ApiResource {
+            group: "networking.k8s.io".to_owned(),
+            version: "v1".to_owned(),
+            api_version: "networking.k8s.io/v1".to_owned(),
+            kind: "NetworkPolicy".to_owned(),
+            plural: "networkpolicies".to_owned()
+        };
+
+        let capabilities = ApiCapabilities {
+            scope: Scope::Namespaced,
+            subresources: Vec::new(),
+            operations: Vec::new()
+        };
+
+        cache.write().unwrap().insert(
+            get_key(&gvk.group, &gvk.version, &gvk.kind),
+            (resource, capabilities)
+        );
+
+        let (resource, _) = get_cached(&cache, &gvk).unwrap().unwrap();
+        assert_eq!(resource.plural, "networkpolicies");
+    }
+
+    #[test]
+    fn expect_unknown_kind_to_not_be_cached() {
+        let cache = generate_new_cache();
+        let gvk = GroupVersionKind::gvk("", "v1", "Foo");
+
+        let res = get_cached(&cache, &gvk).unwrap();
+        assert!(res.is_none());
+    }
+
+    #[test]
+    fn expect_to_get_api_version() {
+        let core = GroupVersionKind::gvk("", "v1", "Secret");
+        assert_eq!(get_api_version(&core), "v1");
+
+        let apps = GroupVersionKind::gvk("apps", "v1", "Deployment");
+        assert_eq!(get_api_version(&apps), "apps/v1");
+    }
+}
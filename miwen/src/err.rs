@@ -1,6 +1,8 @@
 use core::fmt;
 use kube::Error as KubeError;
 use gen::err::Error as GenError;
+use gen::crd::status::Reason;
+use crate::client;
 
 #[derive(Debug)]
 pub enum Error {
@@ -10,7 +12,18 @@ pub enum Error {
     Watch(String),
     Serialize,
     Rpc(String),
-    Apply(String)
+    Apply(String),
+    Wave(i64, String),
+    Conflict(String, String),
+    Output(String),
+    Substitute(String),
+    Destination(String),
+    Policy(String),
+    Immutable(String, String),
+    DeletionBlocked(String, Vec<String>),
+    RolledBack(String),
+    Snapshot(String),
+    Remote(Reason, String)
 }
 
 impl fmt::Display for Error {
@@ -22,13 +35,57 @@ impl fmt::Display for Error {
             Error::Watch(msg) => write!(f, "Error while watching the decryptor resource {msg}"),
             Error::Serialize => write!(f, "Error while serializing the Status"),
             Error::Rpc(msg) => write!(f, "Error while communicating with rpc server {msg}"),
-            Error::Apply(msg) => write!(f, "Error while applying rendered resource from repo: {msg}")
+            Error::Apply(msg) => write!(f, "Error while applying rendered resource from repo: {msg}"),
+            Error::Wave(wave, msg) => write!(f, "Error while applying the sync wave {wave}: {msg}"),
+            Error::Conflict(managers, msg) => write!(f, "Field ownership conflict with the manager {managers}: {msg}"),
+            Error::Output(msg) => write!(f, "Error while generating the output of the decrypted file: {msg}"),
+            Error::Substitute(msg) => write!(f, "Error while substituting the variables of the decrypted file: {msg}"),
+            Error::Destination(msg) => write!(f, "Error while connecting to the destination cluster: {msg}"),
+            Error::Policy(msg) => write!(f, "Policy violation: {msg}"),
+            Error::Immutable(name, msg) => write!(f, "Unable to change the immutable fields of {name}: {msg}"),
+            Error::DeletionBlocked(name, finalizers) => write!(
+                f,
+                "{name} has been deleted in order to be replaced but its deletion is blocked by the finalizers {}",
+                finalizers.join(", ")
+            ),
+            Error::RolledBack(msg) => write!(f, "Objects have been restored to their previous state as the apply failed: {msg}"),
+            Error::Snapshot(msg) => write!(f, "Error while saving the snapshot of the objects: {msg}"),
+            Error::Remote(_, msg) => write!(f, "The rpc server returned an error: {msg}")
         }
     }
 }
 
 impl std::error::Error for Error {}
 
+impl Error {
+    /// Get the reason of the error which is reported in the status of the Decryptor
+    /// 
+    /// # Arguments
+    /// * `&self` - &Self
+    pub fn reason(&self) -> Reason {
+        match self {
+            Error::Remote(reason, _) => *reason,
+            Error::Generator(_) => Reason::InvalidSpec,
+            Error::Conflict(..) => Reason::Conflict,
+            Error::Policy(_) => Reason::PolicyViolation,
+            Error::DeletionBlocked(..) => Reason::DeletionBlocked,
+            Error::Apply(_)
+            | Error::Wave(..)
+            | Error::Output(_)
+            | Error::Substitute(_)
+            | Error::Immutable(..)
+            | Error::RolledBack(_)
+            | Error::Snapshot(_) => Reason::ApplyFailed,
+            Error::Rpc(_)
+            | Error::KubeRuntime(_)
+            | Error::Destination(_) => Reason::Unavailable,
+            Error::KubeAuthentication
+            | Error::Watch(_)
+            | Error::Serialize => Reason::Internal
+        }
+    }
+}
+
 impl From<KubeError> for Error {
     fn from(err: KubeError) -> Self {
         match err {
@@ -40,7 +97,11 @@ impl From<KubeError> for Error {
 
 impl From<GenError> for Error {
     fn from(err: GenError) -> Self {
-        Error::Generator(err.to_string())
+        match err {
+            // failures of the kube api are transient and are not caused by the spec
+            GenError::Api(_) => Error::KubeRuntime(err.to_string()),
+            _ => Error::Generator(err.to_string())
+        }
     }
 }
 
@@ -56,6 +117,12 @@ impl From<serde_json::Error> for Error {
     }
 }
 
+impl From<tonic::Status> for Error {
+    fn from(status: tonic::Status) -> Self {
+        Error::Remote(client::get_reason(&status), status.message().to_owned())
+    }
+}
+
 impl From<tonic::transport::Error> for Error {
     fn from(err: tonic::transport::Error) -> Self {
         Error::Rpc(err.to_string())
@@ -67,3 +134,17 @@ impl From<serde_yaml::Error> for Error {
         Error::Apply("Unable to decrypt the resource from the repository".to_owned())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expect_kube_api_failure_to_be_transient() {
+        let err = Error::from(GenError::Api("connection refused".to_owned()));
+        assert_eq!(err.reason(), Reason::Unavailable);
+
+        let err = Error::from(GenError::MissingMetadata("name".to_owned()));
+        assert_eq!(err.reason(), Reason::InvalidSpec);
+    }
+}
@@ -6,6 +6,15 @@ mod err;
 mod state;
 mod client;
 mod sync;
+mod discovery;
+mod destination;
+mod policy;
+mod scope;
+mod repository;
+mod decryptor_set;
+mod dependency;
+mod backoff;
+mod revision;
 
 /// Setup different logging & debugging services
 fn setup() -> color_eyre::Result<()> {
@@ -27,11 +36,25 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     setup()?;
 
     let state = state::generate_new_state();
+    let locks = state::generate_new_locks();
+    let cache = destination::generate_new_cache();
+    let policy = policy::load_policy()?;
+    let scope = scope::load_watch_scope();
+
+    // Restore the objects left half-updated by an apply which has been interrupted
+    if let Err(err) = watcher::snapshot::recover_snapshots(&cache, &policy).await {
+        error!("Unable to restore the snapshots: {err}");
+    }
+
     tokio::try_join!(
         // Start the watcher which will react to any changes on the crd
-        watcher::boostrap_watcher(state),
+        watcher::boostrap_watcher(state, locks.clone(), cache.clone(), policy.clone(), scope.clone()),
+        // Start the watcher which will reconcile the GitRepository
+        repository::bootstrap_repository_watcher(scope.clone()),
+        // Start the watcher which will generate the Decryptors of the DecryptorSet
+        decryptor_set::bootstrap_decryptor_set_watcher(scope.clone()),
         // Start a sync loop which will sync the repo with the cluster
-        sync::bootstrap_repo_sync()
+        sync::bootstrap_repo_sync(cache, policy, locks, scope)
     )?;
 
     Ok(())
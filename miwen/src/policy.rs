@@ -0,0 +1,212 @@
+// This mod is used to restrict the objects that a Decryptor can apply. The policy is defined
+// by the administrator of the controller and is loaded from the file specified by POLICY_PATH
+use std::fs;
+use std::sync::Arc;
+use serde::Deserialize;
+use crate::err::Error;
+
+// Constant
+const POLICY_PATH_ENV: &str = "POLICY_PATH";
+const WILDCARD: &str = "*";
+
+pub type SharedPolicy = Arc<Policy>;
+
+/// Policy contains a rule per source namespace. When no policy file is specified, a Decryptor
+/// can apply any objects. When requireServiceAccount is set, the Decryptors need to specify a ServiceAccount
+/// which is impersonated to apply the objects
+///
+/// # Example
+/// requireServiceAccount: true
+/// rules:
+///   - sourceNamespace: team-a
+///     allowedKinds: ["Secret", "ConfigMap"]
+///     allowedNamespaces: ["team-a", "team-a-staging"]
+///     allowedServers: ["https://prod.example.com"]
+///   - sourceNamespace: "*"
+///     allowedKinds: ["Secret"]
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct Policy {
+    #[serde(rename = "requireServiceAccount", default)]
+    require_service_account: bool,
+    rules: Option<Vec<Rule>>
+}
+
+/// Rule list the kinds, the target namespaces and the remote clusters allowed for the Decryptors of the source namespace.
+/// The Decryptor can only target its own namespace when the allowed namespaces are not specified and
+/// can't target a remote cluster when the allowed servers are not specified
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct Rule {
+    #[serde(rename = "sourceNamespace")]
+    source_namespace: String,
+    #[serde(rename = "allowedKinds", default)]
+    allowed_kinds: Vec<String>,
+    #[serde(rename = "allowedNamespaces")]
+    allowed_namespaces: Option<Vec<String>>,
+    #[serde(rename = "allowedServers", default)]
+    allowed_servers: Vec<String>
+}
+
+/// Check whenever the value is contained in the list. The list may contains a wildcard
+///
+/// # Arguments
+/// * `list` - &[String]
+/// * `value` - &str
+fn is_allowed(list: &[String], value: &str) -> bool {
+    list.iter().any(|item| item == WILDCARD || item == value)
+}
+
+/// Load the policy from the file specified by the POLICY_PATH env
+pub fn load_policy() -> Result<SharedPolicy, Error> {
+    let path = match std::env::var(POLICY_PATH_ENV) {
+        Ok(path) => path,
+        Err(_) => return Ok(Arc::new(Policy::default()))
+    };
+
+    info!("📜 Loading the policy from {path}");
+    let content = fs::read_to_string(&path)
+        .map_err(|err| Error::Policy(format!("Unable to read the policy file {path}: {err}")))?;
+
+    let policy = serde_yaml::from_str(&content)
+        .map_err(|err| Error::Policy(format!("Unable to parse the policy file {path}: {err}")))?;
+
+    Ok(Arc::new(policy))
+}
+
+impl Policy {
+    /// Check whenever the Decryptors need to specify a ServiceAccount to apply the objects
+    ///
+    /// # Arguments
+    /// * `&self` - &Self
+    pub fn is_service_account_required(&self) -> bool {
+        self.require_service_account
+    }
+
+    /// Get the rule of the source namespace. The rule which matches the namespace takes precedence
+    /// over the wildcard rule. When a policy is defined and no rule matches, nothing can be applied
+    ///
+    /// # Arguments
+    /// * `&self` - &Self
+    /// * `ns` - &str
+    pub fn get_rule(&self, ns: &str) -> Option<Rule> {
+        let rules = self.rules.as_ref()?;
+        let rule = rules.iter()
+            .find(|rule| rule.source_namespace == ns)
+            .or_else(|| rules.iter().find(|rule| rule.source_namespace == WILDCARD))
+            .cloned()
+            .unwrap_or_else(|| Rule {
+                source_namespace: ns.to_owned(),
+                ..Default::default()
+            });
+
+        Some(rule)
+    }
+}
+
+impl Rule {
+    /// Check whenever the rule explicitly allows the Decryptors to target the namespace. Unlike the check of
+    /// the objects, the namespace of the Decryptor is not allowed implicitly
+    ///
+    /// # Arguments
+    /// * `&self` - &Self
+    /// * `target` - &str
+    pub fn is_namespace_allowed(&self, target: &str) -> bool {
+        self.allowed_namespaces.as_ref()
+            .map(|namespaces| is_allowed(namespaces, target))
+            .unwrap_or_default()
+    }
+
+    /// Check whenever the rule allows the Decryptors to apply objects on the remote cluster
+    ///
+    /// # Arguments
+    /// * `&self` - &Self
+    /// * `server` - &str
+    pub fn is_server_allowed(&self, server: &str) -> bool {
+        is_allowed(&self.allowed_servers, server.trim_end_matches('/'))
+    }
+
+    /// Check that the object is allowed by the rule. A violation is returned otherwise
+    ///
+    /// # Arguments
+    /// * `&self` - &Self
+    /// * `kind` - &str
+    /// * `name` - &str
+    /// * `ns` - &str
+    /// * `target` - Option<&str>
+    pub fn check(&self, kind: &str, name: &str, ns: &str, target: Option<&str>) -> Result<(), String> {
+        if !is_allowed(&self.allowed_kinds, kind) {
+            return Err(format!("kind {kind} of {name} is not allowed"));
+        }
+
+        if let Some(target) = target {
+            let allowed = match &self.allowed_namespaces {
+                Some(namespaces) => is_allowed(namespaces, target),
+                None => target == ns
+            };
+
+            if !allowed {
+                return Err(format!("{kind} {name} can not target the namespace {target}"));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_policy() -> Policy {
+        let policy = r#"
+rules:
+  - sourceNamespace: team-a
+    allowedKinds: ["Secret", "ConfigMap"]
+    allowedNamespaces: ["team-a", "team-a-staging"]
+  - sourceNamespace: "*"
+    allowedKinds: ["Secret"]
+"#;
+
+        serde_yaml::from_str(policy).unwrap()
+    }
+
+    #[test]
+    fn expect_to_allow_everything_wo_policy() {
+        let policy = Policy::default();
+        assert!(policy.get_rule("team-a").is_none());
+    }
+
+    #[test]
+    fn expect_to_check_kind_and_namespace() {
+        let rule = get_policy().get_rule("team-a").unwrap();
+
+        assert!(rule.check("ConfigMap", "foo", "team-a", Some("team-a-staging")).is_ok());
+        assert!(rule.check("Deployment", "foo", "team-a", Some("team-a")).is_err());
+        assert!(rule.check("Secret", "foo", "team-a", Some("kube-system")).is_err());
+    }
+
+    #[test]
+    fn expect_to_use_wildcard_rule() {
+        let rule = get_policy().get_rule("team-b").unwrap();
+
+        assert!(rule.check("Secret", "foo", "team-b", Some("team-b")).is_ok());
+        assert!(rule.check("Secret", "foo", "team-b", Some("team-a")).is_err());
+        assert!(rule.check("ConfigMap", "foo", "team-b", Some("team-b")).is_err());
+    }
+
+    #[test]
+    fn expect_namespace_to_be_allowed_explicitly() {
+        let policy = get_policy();
+
+        assert!(policy.get_rule("team-a").unwrap().is_namespace_allowed("team-a-staging"));
+        assert!(!policy.get_rule("team-a").unwrap().is_namespace_allowed("kube-system"));
+        assert!(!policy.get_rule("team-b").unwrap().is_namespace_allowed("team-a"));
+    }
+
+    #[test]
+    fn expect_to_deny_wo_matching_rule() {
+        let policy: Policy = serde_yaml::from_str("rules: []").unwrap();
+        let rule = policy.get_rule("team-a").unwrap();
+
+        assert!(rule.check("Secret", "foo", "team-a", Some("team-a")).is_err());
+    }
+}
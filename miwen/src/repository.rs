@@ -0,0 +1,147 @@
+// This mod is used to reconcile the GitRepository resources. The repository is cloned by krapao
+// and the status of the GitRepository reflects the last fetch of the repository
+use std::collections::HashSet;
+use kube::{
+    Client,
+    ResourceExt,
+    api::ListParams
+};
+use kube::runtime::watcher::Event;
+use gen::crd::Decryptor;
+use gen::crd::git_repository::{GitRepository, GitRepositoryStatus};
+use gen::crd::decryptor_set::DecryptorSet;
+use crate::err::Error;
+use crate::client::server;
+use crate::scope::{WatchScope, ScopedWatcher};
+use crate::state::get_key;
+
+/// Reconcile the GitRepository
+///     - Clone the repository with krapao if it's not already cloned
+///     - Update the status with the commit hash of the revision and the time of the last fetch
+///
+/// An error is reported in the status if any of these steps fail
+///
+/// # Arguments
+/// * `repository` - &mut GitRepository
+/// * `client` - &Client
+pub async fn reconcile(repository: &mut GitRepository, client: &Client) -> Result<(), Error> {
+    let (name, ns) = repository.get_metadata_info()?;
+    let spec = repository.spec.clone();
+
+    let owner = server::get_owner(repository);
+    let res = match spec.get_revision() {
+        Ok(revision) => match server::clone_repository(&spec.get_repository(), client, &ns, &owner).await {
+            Ok(_) => server::get_repository_status(&owner, revision).await,
+            Err(err) => Err(err)
+        },
+        Err(err) => Err(Error::from(err))
+    };
+
+    let status = match res {
+        Ok(res) => GitRepositoryStatus::fetched(res.commit_hash, res.last_fetch_time),
+        Err(err) => {
+            error!("Unable to reconcile the GitRepository {}: {err}", get_key(&ns, &name));
+            GitRepositoryStatus::failed(repository.status.as_ref(), err.to_string())
+        }
+    };
+
+    repository.update_status(status).await?;
+
+    Ok(())
+}
+
+/// Refresh the status of every GitRepository of the watched namespaces. This is used by the sync process
+/// as the repositories are pulled by krapao periodically. A GitRepository which fails to be refreshed
+/// does not prevent the others to be refreshed
+///
+/// # Arguments
+/// * `client` - &Client
+/// * `scope` - &WatchScope
+pub async fn refresh_repositories(client: &Client, scope: &WatchScope) -> Result<(), Error> {
+    for api in scope.get_apis::<GitRepository>(client).await? {
+        for mut repository in api.list(&ListParams::default()).await? {
+            if let Err(err) = reconcile(&mut repository, client).await {
+                error!("Unable to refresh the GitRepository {}: {err}", repository.name());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Get the owners of the repositories in krapao. Every Decryptor, GitRepository and DecryptorSet
+/// of the watched namespaces reference a repository
+///
+/// # Arguments
+/// * `client` - &Client
+/// * `scope` - &WatchScope
+async fn get_used_repositories(client: &Client, scope: &WatchScope) -> Result<HashSet<String>, Error> {
+    let mut used = HashSet::new();
+    for api in scope.get_apis::<Decryptor>(client).await? {
+        for decryptor in api.list(&ListParams::default()).await? {
+            used.insert(server::get_owner(&decryptor));
+        }
+    }
+
+    for api in scope.get_apis::<GitRepository>(client).await? {
+        for repository in api.list(&ListParams::default()).await? {
+            used.insert(server::get_owner(&repository));
+        }
+    }
+
+    for api in scope.get_apis::<DecryptorSet>(client).await? {
+        for set in api.list(&ListParams::default()).await? {
+            used.insert(server::get_owner(&set));
+        }
+    }
+
+    Ok(used)
+}
+
+/// Release the repositories of krapao referenced by resources which no longer exist. krapao deletes
+/// a repository once it's not referenced anymore
+///
+/// # Arguments
+/// * `client` - &Client
+/// * `scope` - &WatchScope
+pub async fn release_unused_repositories(client: &Client, scope: &WatchScope) -> Result<(), Error> {
+    let used = get_used_repositories(client, scope).await?;
+    for owner in server::list_repositories().await? {
+        if !used.contains(&owner) {
+            info!("🗑️ Releasing the repository of {owner} as it no longer exists");
+            server::delete_repository(&owner).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Create a watcher which will watch the GitRepository resources of the watched namespaces.
+/// A GitRepository is reconciled when a new generation of the resource is observed
+///
+/// # Arguments
+/// * `scope` - WatchScope
+pub async fn bootstrap_repository_watcher(scope: WatchScope) -> Result<(), Error> {
+    info!("Starting up the GitRepository watcher...");
+    let client = Client::try_default().await?;
+
+    let mut watcher = ScopedWatcher::<GitRepository>::new(scope, &client).await?;
+
+    while let Some(event) = watcher.try_next().await? {
+        if let Event::Applied(mut repository) = event {
+            // updating the status trigger a new event. The generation is used to skip it
+            if repository.is_reconciled() {
+                continue;
+            }
+
+            let client = client.clone();
+            tokio::spawn(async move {
+                if let Err(err) = reconcile(&mut repository, &client).await {
+                    error!("{err}");
+                }
+            });
+        }
+    }
+
+    Ok(())
+}
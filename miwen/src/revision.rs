@@ -0,0 +1,310 @@
+// This mod is used to record the synchronizations of the Decryptors in DecryptorRevision objects. The revisions
+// are owned by the Decryptor and the oldest ones are removed once the revisionHistoryLimit is exceeded
+use std::collections::BTreeMap;
+use kube::{
+    Api,
+    Client,
+    Resource,
+    Error as KubeError,
+    api::{ListParams, Patch, PatchParams, DeleteParams}
+};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::OwnerReference;
+use gen::crd::Decryptor;
+use gen::crd::status::{DecryptorStatus, Status};
+use gen::util::get_label_value;
+use gen::crd::revision::{
+    DecryptorRevision,
+    DecryptorRevisionSpec,
+    Trigger,
+    DECRYPTOR_LABEL,
+    DEFAULT_REVISION_HISTORY_LIMIT,
+    get_revision_name,
+    is_owned_by
+};
+use crate::err::Error;
+use crate::state::get_key;
+
+// Constant
+const NOT_FOUND_STATUS_CODE: u16 = 404;
+const FIELD_MANAGER: &str = "miwen";
+const STATUS_SUBRESOURCE: &str = "status";
+
+/// Get the uid of the Decryptor
+///
+/// # Arguments
+/// * `decryptor` - &Decryptor
+fn get_uid(decryptor: &Decryptor) -> Result<String, Error> {
+    decryptor.metadata.uid.to_owned()
+        .ok_or_else(|| Error::Generator("Decryptor does not have an uid".to_owned()))
+}
+
+/// Get the owner reference of the Decryptor set on the DecryptorRevision. This allows
+/// the revisions to be garbage collected when the Decryptor is deleted
+///
+/// # Arguments
+/// * `decryptor` - &Decryptor
+/// * `name` - &str
+fn get_owner_reference(decryptor: &Decryptor, name: &str) -> Result<OwnerReference, Error> {
+    let uid = get_uid(decryptor)?;
+
+    Ok(OwnerReference {
+        api_version: Decryptor::api_version(&()).to_string(),
+        kind: Decryptor::kind(&()).to_string(),
+        name: name.to_owned(),
+        uid,
+        controller: Some(true),
+        block_owner_deletion: Some(true)
+    })
+}
+
+/// Get the field manager which changed the spec of the Decryptor the most recently
+///
+/// # Arguments
+/// * `decryptor` - &Decryptor
+fn get_spec_manager(decryptor: &Decryptor) -> Option<String> {
+    decryptor.metadata.managed_fields.iter()
+        .flatten()
+        .filter(|entry| entry.subresource.as_deref() != Some(STATUS_SUBRESOURCE))
+        .max_by_key(|entry| entry.time.as_ref().map(|time| time.0))
+        .and_then(|entry| entry.manager.to_owned())
+}
+
+/// Get what triggered the synchronization of the Decryptor alongside who triggered it.
+/// This needs to be called before the status of the synchronization is set
+///
+/// # Arguments
+/// * `decryptor` - &Decryptor
+fn get_trigger(decryptor: &Decryptor) -> (Trigger, Option<String>) {
+    if !decryptor.is_observed() {
+        return (Trigger::Spec, get_spec_manager(decryptor));
+    }
+
+    if decryptor.has_failed() {
+        return (Trigger::Retry, None);
+    }
+
+    (Trigger::Commit, None)
+}
+
+/// Get the ids of the oldest DecryptorRevision which exceed the revisionHistoryLimit. The revision
+/// targeted by the rollback of the Decryptor is never pruned as the Decryptor is pinned to it
+///
+/// # Arguments
+/// * `ids` - Vec<u64>
+/// * `limit` - u32
+/// * `pinned` - Option<u64>
+fn get_pruned_ids(mut ids: Vec<u64>, limit: u32, pinned: Option<u64>) -> Vec<u64> {
+    ids.sort_unstable();
+
+    let exceeding = ids.len().saturating_sub(limit as usize);
+    ids.into_iter()
+        .take(exceeding)
+        .filter(|id| Some(*id) != pinned)
+        .collect()
+}
+
+/// Remove the oldest DecryptorRevision of the Decryptor which exceed the revisionHistoryLimit. Only the
+/// revisions owned by the Decryptor are considered as a previous Decryptor with the same name may still own some
+///
+/// # Arguments
+/// * `decryptor` - &Decryptor
+/// * `api` - &Api<DecryptorRevision>
+/// * `name` - &str
+async fn prune_revisions(decryptor: &Decryptor, api: &Api<DecryptorRevision>, name: &str) -> Result<(), Error> {
+    let limit = decryptor.spec.revision_history_limit.unwrap_or(DEFAULT_REVISION_HISTORY_LIMIT);
+    let pinned = decryptor.spec.rollback.as_ref().and_then(|rollback| rollback.to_id);
+
+    let uid = get_uid(decryptor)?;
+
+    let selector = format!("{DECRYPTOR_LABEL}={}", get_label_value(name));
+    let ids = api.list(&ListParams::default().labels(&selector)).await?
+        .into_iter()
+        .filter(|revision| is_owned_by(revision, &uid))
+        .map(|revision| revision.spec.id)
+        .collect();
+
+    for id in get_pruned_ids(ids, limit, pinned) {
+        api.delete(&get_revision_name(name, &uid, id), &DeleteParams::default()).await?;
+    }
+
+    Ok(())
+}
+
+/// Apply a DecryptorRevision recording a status of the Decryptor. A revision with the same name
+/// which is not owned by the Decryptor is never overwritten
+///
+/// # Arguments
+/// * `decryptor` - &Decryptor
+/// * `api` - &Api<DecryptorRevision>
+/// * `status` - &Status
+/// * `trigger` - Trigger
+/// * `triggered_by` - Option<String>
+async fn apply_revision(
+    decryptor: &Decryptor,
+    api: &Api<DecryptorRevision>,
+    status: &Status,
+    trigger: Trigger,
+    triggered_by: Option<String>
+) -> Result<(), Error> {
+    let (name, _, ns) = decryptor.get_metadata_info()?;
+    let uid = get_uid(decryptor)?;
+    let revision_name = get_revision_name(&name, &uid, status.id);
+    match api.get(&revision_name).await {
+        Ok(existing) if !is_owned_by(&existing, &uid) => {
+            return Err(Error::KubeRuntime(format!("DecryptorRevision {revision_name} is not owned by the Decryptor {name}")));
+        },
+        Ok(_) => {},
+        Err(KubeError::Api(err)) if err.code == NOT_FOUND_STATUS_CODE => {},
+        Err(err) => return Err(Error::from(err))
+    }
+
+    let mut revision = DecryptorRevision::new(
+        &revision_name,
+        DecryptorRevisionSpec::new(&name, status, trigger, triggered_by)
+    );
+    revision.metadata.namespace = Some(ns);
+    revision.metadata.labels = Some(BTreeMap::from([(DECRYPTOR_LABEL.to_owned(), get_label_value(&name))]));
+    revision.metadata.owner_references = Some(vec![get_owner_reference(decryptor, &name)?]);
+
+    api.patch(&revision_name, &PatchParams::apply(FIELD_MANAGER).force(), &Patch::Apply(&revision)).await?;
+
+    Ok(())
+}
+
+/// Record the current status of the Decryptor in a DecryptorRevision
+///
+/// # Arguments
+/// * `decryptor` - &Decryptor
+/// * `client` - &Client
+/// * `trigger` - Trigger
+/// * `triggered_by` - Option<String>
+async fn record_revision(
+    decryptor: &Decryptor,
+    client: &Client,
+    trigger: Trigger,
+    triggered_by: Option<String>
+) -> Result<(), Error> {
+    let (name, _, ns) = decryptor.get_metadata_info()?;
+    let status = match &decryptor.status {
+        Some(status) => &status.current,
+        None => return Ok(())
+    };
+
+    let api: Api<DecryptorRevision> = Api::namespaced(client.clone(), &ns);
+    apply_revision(decryptor, &api, status, trigger, triggered_by).await?;
+
+    prune_revisions(decryptor, &api, &name).await
+}
+
+/// Migrate the statuses of the history of the status to DecryptorRevision objects. The history was
+/// used to record the synchronizations before the DecryptorRevision existed. True is returned when
+/// a history has been migrated and can be removed from the status
+///
+/// # Arguments
+/// * `decryptor` - &Decryptor
+/// * `client` - &Client
+async fn migrate_history(decryptor: &Decryptor, client: &Client) -> Result<bool, Error> {
+    let history = match decryptor.status.as_ref().and_then(|status| status.history.as_ref()) {
+        Some(history) => history,
+        None => return Ok(false)
+    };
+
+    let (name, _, ns) = decryptor.get_metadata_info()?;
+    info!("Migrating the history of the status of {} to DecryptorRevision", get_key(&ns, &name));
+
+    let api: Api<DecryptorRevision> = Api::namespaced(client.clone(), &ns);
+    for status in history {
+        apply_revision(decryptor, &api, status, Trigger::Migration, None).await?;
+    }
+
+    Ok(true)
+}
+
+/// Set the status of a synchronization on the Decryptor and record it in a DecryptorRevision.
+/// The history of the status is migrated beforehand. A failure to record the revision does not
+/// fail the synchronization
+///
+/// # Arguments
+/// * `decryptor` - &mut Decryptor
+/// * `status` - DecryptorStatus
+/// * `client` - &Client
+pub async fn update_status(decryptor: &mut Decryptor, status: DecryptorStatus, client: &Client) -> Result<(), Error> {
+    let (trigger, triggered_by) = get_trigger(decryptor);
+    let migrated = match migrate_history(decryptor, client).await {
+        Ok(migrated) => migrated,
+        Err(err) => {
+            error!("Unable to migrate the history of the status: {err}");
+            false
+        }
+    };
+
+    decryptor.set_status(status);
+    // the history is removed from the status once every entries are recorded by a DecryptorRevision
+    if let Some(status) = decryptor.status.as_mut().filter(|_| migrated) {
+        status.history = None;
+    }
+
+    decryptor.update_status().await?;
+
+    if let Err(err) = record_revision(decryptor, client, trigger, triggered_by).await {
+        let (name, _, ns) = decryptor.get_metadata_info()?;
+        error!("Unable to record the revision of {}: {err}", get_key(&ns, &name));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::{ManagedFieldsEntry, Time};
+    use k8s_openapi::chrono::{TimeZone, Utc};
+
+    fn get_entry(manager: &str, subresource: Option<&str>, secs: i64) -> ManagedFieldsEntry {
+        ManagedFieldsEntry {
+            manager: Some(manager.to_owned()),
+            subresource: subresource.map(|s| s.to_owned()),
+            time: Some(Time(Utc.timestamp(secs, 0))),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn expect_to_get_spec_manager() {
+        let mut decryptor: Decryptor = serde_json::from_value(serde_json::json!({
+            "apiVersion": "jiemi.cr/v1alpha1",
+            "kind": "Decryptor",
+            "metadata": { "name": "app" },
+            "spec": {
+                "source": {
+                    "fileToDecrypt": "foo",
+                    "sopsPath": "bar"
+                }
+            }
+        })).unwrap();
+
+        decryptor.metadata.managed_fields = Some(vec![
+            get_entry("kubectl-client-side-apply", None, 10),
+            get_entry("miwen", Some("status"), 20),
+            get_entry("argocd", None, 15)
+        ]);
+
+        assert_eq!(get_spec_manager(&decryptor).unwrap(), "argocd");
+
+        let (trigger, triggered_by) = get_trigger(&decryptor);
+        assert_eq!(trigger, Trigger::Spec);
+        assert_eq!(triggered_by.unwrap(), "argocd");
+    }
+
+    #[test]
+    fn expect_to_prune_oldest_revisions() {
+        assert_eq!(get_pruned_ids(vec![4, 1, 3, 2, 5], 3, None), vec![1, 2]);
+        assert!(get_pruned_ids(vec![1, 2], 3, None).is_empty());
+    }
+
+    #[test]
+    fn expect_to_not_prune_pinned_revision() {
+        assert_eq!(get_pruned_ids(vec![4, 1, 3, 2, 5], 3, Some(1)), vec![2]);
+        assert_eq!(get_pruned_ids(vec![4, 1, 3, 2, 5], 3, Some(5)), vec![1, 2]);
+    }
+}
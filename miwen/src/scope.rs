@@ -0,0 +1,217 @@
+// This mod is used to restrict the namespaces watched by miwen. By default every namespaces are watched
+// which requires a ClusterRole. Restricting the namespaces with a list allows to run miwen with namespace
+// scoped Roles. The label selector still requires a ClusterRole to list the namespaces
+use std::fmt::Debug;
+use std::time::Duration;
+use kube::{
+    Api,
+    Client,
+    Resource,
+    ResourceExt,
+    api::ListParams
+};
+use kube::runtime::{
+    watcher,
+    watcher::Event
+};
+use k8s_openapi::api::core::v1::Namespace;
+use futures::{TryStreamExt, StreamExt, stream, stream::{BoxStream, SelectAll}};
+use serde::de::DeserializeOwned;
+use tokio::time::{interval, Interval};
+use crate::err::Error;
+
+// Constant
+const WATCH_NAMESPACES_ENV: &str = "WATCH_NAMESPACES";
+const WATCH_NAMESPACE_SELECTOR_ENV: &str = "WATCH_NAMESPACE_SELECTOR";
+const NAMESPACE_SEPARATOR: char = ',';
+const RESOLVE_INTERVAL: u64 = 60;
+
+type Watchers<K> = SelectAll<BoxStream<'static, Result<Event<K>, watcher::Error>>>;
+
+/// WatchScope define the namespaces where the Decryptors are watched
+///     - All: every namespaces of the cluster
+///     - Namespaces: a list of namespaces (WATCH_NAMESPACES=team-a,team-b)
+///     - Selector: the namespaces matching the label selector (WATCH_NAMESPACE_SELECTOR=jiemi.cr/enabled=true)
+#[derive(Debug, Clone, PartialEq)]
+pub enum WatchScope {
+    All,
+    Namespaces(Vec<String>),
+    Selector(String)
+}
+
+/// Parse the list of namespaces
+///
+/// # Arguments
+/// * `value` - &str
+fn parse_namespaces(value: &str) -> Vec<String> {
+    value.split(NAMESPACE_SEPARATOR)
+        .map(str::trim)
+        .filter(|ns| !ns.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
+/// Load the WatchScope from the WATCH_NAMESPACES or the WATCH_NAMESPACE_SELECTOR env.
+/// The list of namespaces takes precedence over the selector
+pub fn load_watch_scope() -> WatchScope {
+    if let Ok(value) = std::env::var(WATCH_NAMESPACES_ENV) {
+        let namespaces = parse_namespaces(&value);
+        if !namespaces.is_empty() {
+            return WatchScope::Namespaces(namespaces);
+        }
+    }
+
+    match std::env::var(WATCH_NAMESPACE_SELECTOR_ENV) {
+        Ok(selector) if !selector.trim().is_empty() => WatchScope::Selector(selector.trim().to_owned()),
+        _ => WatchScope::All
+    }
+}
+
+impl WatchScope {
+    /// Get the namespaces targeted by the scope. None is returned when every namespaces are watched
+    ///
+    /// # Arguments
+    /// * `&self` - &Self
+    /// * `client` - &Client
+    async fn get_namespaces(&self, client: &Client) -> Result<Option<Vec<String>>, Error> {
+        match self {
+            WatchScope::All => Ok(None),
+            WatchScope::Namespaces(namespaces) => Ok(Some(namespaces.to_owned())),
+            WatchScope::Selector(selector) => {
+                let api: Api<Namespace> = Api::all(client.clone());
+                let mut namespaces: Vec<String> = api.list(&ListParams::default().labels(selector)).await?
+                    .into_iter()
+                    .map(|ns| ns.name())
+                    .collect();
+
+                namespaces.sort();
+
+                Ok(Some(namespaces))
+            }
+        }
+    }
+
+    /// Get the apis of the resource (Decryptor, GitRepository...) for the watched namespaces
+    ///
+    /// # Arguments
+    /// * `&self` - &Self
+    /// * `client` - &Client
+    pub async fn get_apis<K>(&self, client: &Client) -> Result<Vec<Api<K>>, Error>
+    where
+        K: Resource<DynamicType = ()>
+    {
+        let apis = match self.get_namespaces(client).await? {
+            Some(namespaces) => namespaces.iter()
+                .map(|ns| Api::namespaced(client.clone(), ns))
+                .collect(),
+            None => vec![Api::all(client.clone())]
+        };
+
+        Ok(apis)
+    }
+}
+
+/// ScopedWatcher watch a resource in the namespaces of the WatchScope. The namespaces matching the label
+/// selector are resolved periodically and the watchers are recreated when the matching namespaces changed
+pub struct ScopedWatcher<K> {
+    scope: WatchScope,
+    client: Client,
+    namespaces: Option<Vec<String>>,
+    watchers: Watchers<K>,
+    interval: Interval
+}
+
+/// Create a watcher per namespace. A single watcher is created when every namespaces are watched
+///
+/// # Arguments
+/// * `client` - &Client
+/// * `namespaces` - &Option<Vec<String>>
+fn create_watchers<K>(client: &Client, namespaces: &Option<Vec<String>>) -> Watchers<K>
+where
+    K: Resource<DynamicType = ()> + Clone + DeserializeOwned + Debug + Send + 'static
+{
+    let apis: Vec<Api<K>> = match namespaces {
+        Some(namespaces) => namespaces.iter()
+            .map(|ns| Api::namespaced(client.clone(), ns))
+            .collect(),
+        None => vec![Api::all(client.clone())]
+    };
+
+    stream::select_all(
+        apis.into_iter().map(|api| watcher(api, ListParams::default()).boxed())
+    )
+}
+
+impl<K> ScopedWatcher<K>
+where
+    K: Resource<DynamicType = ()> + Clone + DeserializeOwned + Debug + Send + 'static
+{
+    /// Create a new ScopedWatcher
+    ///
+    /// # Arguments
+    /// * `scope` - WatchScope
+    /// * `client` - &Client
+    pub async fn new(scope: WatchScope, client: &Client) -> Result<Self, Error> {
+        let namespaces = scope.get_namespaces(client).await?;
+        let watchers = create_watchers(client, &namespaces);
+
+        Ok(ScopedWatcher {
+            scope,
+            client: client.clone(),
+            namespaces,
+            watchers,
+            interval: interval(Duration::from_secs(RESOLVE_INTERVAL))
+        })
+    }
+
+    /// Resolve the namespaces of the scope and recreate the watchers if the namespaces changed
+    ///
+    /// # Arguments
+    /// * `&mut self` - &mut Self
+    async fn resolve(&mut self) -> Result<(), Error> {
+        if !matches!(self.scope, WatchScope::Selector(_)) {
+            return Ok(());
+        }
+
+        let mut namespaces = self.scope.get_namespaces(&self.client).await?;
+        if let Some(list) = namespaces.as_mut() {
+            list.sort();
+        }
+
+        if namespaces != self.namespaces {
+            info!("👀 Watched namespaces changed to {:?}", namespaces.as_deref().unwrap_or_default());
+            self.watchers = create_watchers(&self.client, &namespaces);
+            self.namespaces = namespaces;
+        }
+
+        Ok(())
+    }
+
+    /// Get the next event of the watchers. The namespaces are resolved while waiting for the event
+    ///
+    /// # Arguments
+    /// * `&mut self` - &mut Self
+    pub async fn try_next(&mut self) -> Result<Option<Event<K>>, Error> {
+        loop {
+            tokio::select! {
+                event = self.watchers.try_next() => return Ok(event?),
+                _ = self.interval.tick() => {
+                    if let Err(err) = self.resolve().await {
+                        error!("Unable to resolve the watched namespaces: {err}");
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expect_to_parse_namespaces() {
+        let namespaces = parse_namespaces(" team-a,team-b ,,");
+        assert_eq!(namespaces, vec!["team-a", "team-b"]);
+    }
+}
@@ -1,11 +1,24 @@
 use std::sync::{Arc, Mutex};
 use std::collections::HashMap;
+use tokio::sync::Mutex as AsyncMutex;
 use crate::err::Error;
 
 // Constant
 const LOCK_ERR_MSG: &str = "Unable to acquired lock";
 
-pub type State = Arc<Mutex<HashMap<String, i64>>>;
+/// Entry of the state. The uid allows to differentiate a Decryptor from a Decryptor
+/// which has been re-created with the same name
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Entry {
+    pub uid: Option<String>,
+    pub generation: i64
+}
+
+pub type State = Arc<Mutex<HashMap<String, Entry>>>;
+
+/// Locks serialize the applies of a Decryptor. The watcher and the sync process may apply the same
+/// Decryptor concurrently while an apply relies on a single snapshot of the Decryptor. The locks are keyed by uid
+pub type Locks = Arc<Mutex<HashMap<String, Arc<AsyncMutex<()>>>>>;
 
 /// Generate a new State
 /// 
@@ -16,44 +29,66 @@ pub fn generate_new_state() -> State {
     Arc::new(Mutex::new(HashMap::new()))
 }
 
-/// Check whenever the generation id exist in the state
+/// Generate new Locks
+pub fn generate_new_locks() -> Locks {
+    Arc::new(Mutex::new(HashMap::new()))
+}
+
+/// Get the lock of a Decryptor. The lock is created if it does not exist
+/// 
+/// # Arguments
+/// * `locks` - &Locks
+/// * `uid` - &str
+pub fn get_lock(locks: &Locks, uid: &str) -> Result<Arc<AsyncMutex<()>>, Error> {
+    let mut locks = locks.lock()
+        .map_err(|_| Error::Watch(LOCK_ERR_MSG.to_owned()))?;
+
+    Ok(locks.entry(uid.to_owned()).or_default().clone())
+}
+
+/// Remove the lock of a Decryptor which has been deleted. An apply which is still running keeps its lock
+/// 
+/// # Arguments
+/// * `locks` - &Locks
+/// * `uid` - &str
+pub fn remove_lock(locks: &Locks, uid: &str) -> Result<(), Error> {
+    let mut locks = locks.lock()
+        .map_err(|_| Error::Watch(LOCK_ERR_MSG.to_owned()))?;
+    locks.remove(uid);
+
+    Ok(())
+}
+
+/// Get the key identifying a Decryptor in the state. Decryptors with the same name
+/// may exist in different namespaces hence the key is namespace/name
+/// 
+/// # Arguments
+/// * `ns` - &str
+/// * `name` - &str
+pub fn get_key(ns: &str, name: &str) -> String {
+    format!("{ns}/{name}")
+}
+
+/// Check whenever the entry exist in the state
 /// If it does not exist / different, then adding / updating the value in the state
 /// 
 /// # Arguments
 /// * `state` - State
 /// * `key` - &str
-/// * `value` - i64
-pub fn upsert_state(state: State, key: &str, value: i64) -> Result<bool, Error> {
+/// * `entry` - Entry
+pub fn upsert_state(state: State, key: &str, entry: Entry) -> Result<bool, Error> {
     let mut state = state
         .lock()
         .map_err(|_| Error::Watch(LOCK_ERR_MSG.to_owned()))?;
     
     if let Some(inner) = state.get(key) {
-        if inner == &value {
+        if inner == &entry {
             return Ok(true)
         }
     }
 
     // otherwise update the state...
-    state.insert(String::from(key), value);
-
-    Ok(false)
-}
-
-/// Check if the CRD is registered in the state
-/// This is gonna be used to trigger a grpc call to pull the repository if a new CRD is founded
-/// Why ? If a new CRD is pushed we want to clone it's associated repository only once.
-/// 
-/// # Arguments
-/// * `state` - State
-/// * `key` - &str
-pub fn is_registered(state: State, key: &str) -> Result<bool, Error> {
-    let state = state.lock()
-        .map_err(|_| Error::Watch(LOCK_ERR_MSG.to_owned()))?;
-
-    if state.contains_key(key) {
-        return Ok(true);
-    }
+    state.insert(String::from(key), entry);
 
     Ok(false)
 }
@@ -61,14 +96,23 @@ pub fn is_registered(state: State, key: &str) -> Result<bool, Error> {
 /// Delete an item in the state. This case is used when a CRD is delete. w/o removing the item, when a crd containing the sma
 /// name is applied. The crd might not take into account the update
 /// 
+/// The item is kept if it belongs to a Decryptor which has been re-created with the same name
+/// 
 /// # Arguments
 /// * `state` - State
 /// * `key` - &str
-pub fn delete_item_in_state(state: State, key: &str) -> Result<(), Error> {
+/// * `uid` - Option<&str>
+pub fn delete_item_in_state(state: State, key: &str, uid: Option<&str>) -> Result<(), Error> {
     let mut state = state.lock()
         .map_err(|_| Error::Watch(LOCK_ERR_MSG.to_owned()))?;
 
-    state.remove(key);
+    let recreated = state.get(key)
+        .map(|entry| uid.is_some() && entry.uid.as_deref() != uid)
+        .unwrap_or_default();
+
+    if !recreated {
+        state.remove(key);
+    }
 
     Ok(())
 }
@@ -77,65 +121,109 @@ pub fn delete_item_in_state(state: State, key: &str) -> Result<(), Error> {
 mod tests {
     use super::*;
 
+    fn get_entry(uid: &str, generation: i64) -> Entry {
+        Entry {
+            uid: Some(uid.to_owned()),
+            generation
+        }
+    }
+
     #[test]
     fn expect_to_add_new_element_in_state() {
         let state = generate_new_state();
-        let res = upsert_state(state, "foo", 1).unwrap();
+        let res = upsert_state(state, "default/foo", get_entry("a", 1)).unwrap();
         
-        assert_eq!(res, false);
+        assert!(!res);
     }
 
     #[test]
     fn expect_upsert_to_return_false() {
         let state = generate_new_state();
 
-        let res = upsert_state(state.clone(), "foo", 1).unwrap();
-        assert_eq!(res, false);
+        let res = upsert_state(state.clone(), "default/foo", get_entry("a", 1)).unwrap();
+        assert!(!res);
 
-        let res = upsert_state(state, "foo", 1).unwrap();
-        assert_eq!(res, true);
+        let res = upsert_state(state, "default/foo", get_entry("a", 1)).unwrap();
+        assert!(res);
     }
 
     #[test]
     fn expect_to_update_existing_element_in_state() {
         let state = generate_new_state();
-        upsert_state(state.clone(), "foo", 1).unwrap();
-        upsert_state(state.clone(), "foo", 2).unwrap();
+        upsert_state(state.clone(), "default/foo", get_entry("a", 1)).unwrap();
+        upsert_state(state.clone(), "default/foo", get_entry("a", 2)).unwrap();
 
         // get the value and check it
         let map = state.lock().unwrap();
-        let item = map.get("foo");
+        let item = map.get("default/foo");
 
-        assert_eq!(item.unwrap().to_owned(), 2);
+        assert_eq!(item.unwrap().generation, 2);
     }
 
     #[test]
-    fn expect_state_to_be_registered() {
+    fn expect_to_delete_item_in_state() {
         let state = generate_new_state();
-        upsert_state(state.clone(), "foo", 1).unwrap();
-        
-        let res = is_registered(state, "foo").unwrap();
-        assert_eq!(res, true);
+        upsert_state(state.clone(), "default/foo", get_entry("a", 1)).unwrap();
+
+        let res = delete_item_in_state(state.clone(), "default/foo", Some("a"));
+        assert!(res.is_ok());
+
+        let map = state.lock().unwrap();
+        let item = map.get("default/foo");
+        assert!(item.is_none());
     }
 
     #[test]
-    fn expect_state_to_not_be_registered() {
+    fn expect_same_name_in_different_namespaces_to_not_collide() {
         let state = generate_new_state();
-        let res = is_registered(state, "foo").unwrap();
-        
-        assert_eq!(res, false);
+        let default = get_key("default", "app");
+        let prod = get_key("prod", "app");
+
+        assert!(!upsert_state(state.clone(), &default, get_entry("a", 1)).unwrap());
+        assert!(!upsert_state(state.clone(), &prod, get_entry("b", 1)).unwrap());
+
+        // an update of one Decryptor is not blocked by the other one
+        assert!(!upsert_state(state.clone(), &prod, get_entry("b", 2)).unwrap());
+        assert!(upsert_state(state.clone(), &default, get_entry("a", 1)).unwrap());
+
+        // deleting one Decryptor keeps the other one registered
+        delete_item_in_state(state.clone(), &default, Some("a")).unwrap();
+
+        let map = state.lock().unwrap();
+        assert!(map.get(&default).is_none());
+        assert_eq!(map.get(&prod).unwrap().generation, 2);
     }
 
     #[test]
-    fn expect_to_delete_item_in_state() {
+    fn expect_recreated_decryptor_to_be_processed() {
         let state = generate_new_state();
-        upsert_state(state.clone(), "foo", 1).unwrap();
+        upsert_state(state.clone(), "default/foo", get_entry("a", 1)).unwrap();
 
-        let res = delete_item_in_state(state.clone(), "foo");
-        assert!(res.is_ok());
+        let res = upsert_state(state, "default/foo", get_entry("b", 1)).unwrap();
+        assert!(!res);
+    }
+
+    #[test]
+    fn expect_delete_of_previous_decryptor_to_keep_recreated_one() {
+        let state = generate_new_state();
+        upsert_state(state.clone(), "default/foo", get_entry("b", 1)).unwrap();
+
+        delete_item_in_state(state.clone(), "default/foo", Some("a")).unwrap();
 
         let map = state.lock().unwrap();
-        let item = map.get("foo");
-        assert!(item.is_none());
+        assert!(map.get("default/foo").is_some());
+    }
+
+    #[tokio::test]
+    async fn expect_applies_of_same_decryptor_to_be_serialized() {
+        let locks = generate_new_locks();
+        let lock = get_lock(&locks, "a").unwrap();
+        let _guard = lock.lock().await;
+
+        assert!(get_lock(&locks, "a").unwrap().try_lock().is_err());
+        assert!(get_lock(&locks, "b").unwrap().try_lock().is_ok());
+
+        remove_lock(&locks, "a").unwrap();
+        assert!(get_lock(&locks, "a").unwrap().try_lock().is_ok());
     }
-}
\ No newline at end of file
+}
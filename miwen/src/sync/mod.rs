@@ -1,29 +1,48 @@
 // This mod is used to pull changes from the repository
 // from time to time and check whenever we need to update the resoruces
-use kube::{Api, Client};
+use kube::Client;
 use kube::api::ListParams;
 use gen::crd::status::{DecryptorStatus, SyncStatus};
 use gen::crd::Decryptor;
 use tokio::time::sleep;
 use std::time::Duration;
-use futures::future::try_join_all;
+use futures::future::join_all;
 use crate::err::Error;
-use crate::client::crd;
-use crate::watcher::apply;
+use crate::client::{crd, server};
+use crate::destination::{self, Cache};
+use crate::policy::SharedPolicy;
+use crate::scope::WatchScope;
+use crate::repository;
+use crate::decryptor_set;
+use crate::dependency;
+use crate::backoff;
+use crate::revision;
+use crate::state;
+use crate::watcher::{self, health};
 
 // constant
 const THREAD_SLEEP: u64 = 180;
 
 /// Bootstrap the repo sync process
 /// It runs every 180s / 3min
-pub async fn bootstrap_repo_sync() -> Result<(), Error> {
+/// 
+/// # Arguments
+/// * `cache` - Cache
+/// * `policy` - SharedPolicy
+/// * `locks` - Locks
+/// * `scope` - WatchScope
+pub async fn bootstrap_repo_sync(cache: Cache, policy: SharedPolicy, locks: state::Locks, scope: WatchScope) -> Result<(), Error> {
     info!("Starting up sync process");
     loop {
         sleep(Duration::from_secs(THREAD_SLEEP)).await;
 
+        let cache = cache.clone();
+        let policy = policy.clone();
+        let locks = locks.clone();
+        let scope = scope.clone();
         tokio::spawn(async move {
             info!("Sync process is running...");
-            if let Err(err) = sync_encrypted_file_with_git().await {
+            if let Err(err) = sync_encrypted_file_with_git(cache, policy, locks, scope).await {
                 error!("Error while syncing repository with cluster: {}", err.to_string());
             }
         });
@@ -34,32 +53,109 @@ pub async fn bootstrap_repo_sync() -> Result<(), Error> {
 /// Basically, we're comparing the commit hash of the last sync with the current hash in the
 /// repository.
 /// If the hash is different, then we may synchronize the file with the cluster
-async fn sync_encrypted_file_with_git() -> Result<(), Error> {
+/// 
+/// # Arguments
+/// * `cache` - Cache
+/// * `policy` - SharedPolicy
+/// * `locks` - Locks
+/// * `scope` - WatchScope
+async fn sync_encrypted_file_with_git(cache: Cache, policy: SharedPolicy, locks: state::Locks, scope: WatchScope) -> Result<(), Error> {
     let client = Client::try_default().await?;
-    let crds = list_crd(client.clone()).await?;
+    // krapao pull the repositories periodically. Refresh the status of the GitRepository accordingly
+    if let Err(err) = repository::refresh_repositories(&client, &scope).await {
+        error!("Error while refreshing the GitRepository: {err}");
+    }
+
+    // files may have been added or removed from the repositories. Regenerate the Decryptors of the DecryptorSet
+    if let Err(err) = decryptor_set::refresh_decryptor_sets(&client, &scope).await {
+        error!("Error while refreshing the DecryptorSet: {err}");
+    }
+
+    // repositories which are no longer used (e.g: url of a Decryptor changed) are removed from krapao
+    if let Err(err) = repository::release_unused_repositories(&client, &scope).await {
+        error!("Error while releasing the unused repositories: {err}");
+    }
+
+    let crds = list_crd(client.clone(), &scope).await?;
+
+    // the clients of the remote clusters which are no longer referenced (e.g: Decryptor deleted) are released
+    let used = crds.iter()
+        .filter_map(|crd| {
+            let ns = crd.metadata.namespace.as_deref()?;
+            destination::get_destination_key(crd, ns)
+        })
+        .collect();
+
+    if let Err(err) = destination::release_unused_destinations(&cache, &used) {
+        error!("Error while releasing the unused destinations: {err}");
+    }
 
     // for each crd we're going to check whenever the crd is synced with the latest
     let mut fut = Vec::new();
     for crd in crds {
-        fut.push(get_and_apply_template(crd));
+        fut.push(get_and_apply_template(crd, cache.clone(), policy.clone(), locks.clone()));
     }
 
-    // Joining the futures better than spawning a thread for each crd
-    let res = try_join_all(fut).await;
-    if let Err(err) = res {
-        return Err(err);
-    }
-    
+    // Joining the futures better than spawning a thread for each crd. A Decryptor which fails
+    // does not prevent the others to be synchronized
+    join_all(fut).await;
+
     Ok(())
 }
 
-/// Get and apply the rendered template from the rpc server
+/// Get and apply the rendered template from the rpc server. An error which prevents the Decryptor
+/// to be synchronized is logged and recorded in the status of the Decryptor
 /// 
 /// # Arguments
 /// * `mut decryptor` - Decryptor
-async fn get_and_apply_template(mut decryptor: Decryptor) -> Result<(), Error> {
+/// * `cache` - Cache
+/// * `policy` - SharedPolicy
+/// * `locks` - Locks
+async fn get_and_apply_template(mut decryptor: Decryptor, cache: Cache, policy: SharedPolicy, locks: state::Locks) {
+    let key = match decryptor.get_metadata_info() {
+        Ok((name, _, ns)) => state::get_key(&ns, &name),
+        Err(err) => {
+            error!("Unable to sync the Decryptor: {err}");
+            return;
+        }
+    };
+
+    if let Err(err) = sync_decryptor(&mut decryptor, &cache, &policy, &locks).await {
+        error!("Unable to sync {key}: {err}");
+        if let Err(err) = record_failure(&mut decryptor, &err).await {
+            error!("Unable to update the status of {key}: {err}");
+        }
+    }
+}
+
+/// Record the error which prevented the Decryptor to be synchronized in its status. The revision
+/// of the previous synchronization is kept
+/// 
+/// # Arguments
+/// * `decryptor` - &mut Decryptor
+/// * `err` - &Error
+async fn record_failure(decryptor: &mut Decryptor, err: &Error) -> Result<(), Error> {
     let client = Client::try_default().await?;
     let (_, _, ns) = decryptor.get_metadata_info()?;
+    let hash = decryptor.status.as_ref()
+        .map(|st| st.current.revision.to_owned())
+        .filter(|hash| !hash.is_empty());
+
+    let status = backoff::get_failed_status(decryptor, err, hash, &ns).await;
+    revision::update_status(decryptor, status, &client).await
+}
+
+/// Synchronize the Decryptor with the file of the repository
+/// 
+/// # Arguments
+/// * `decryptor` - &mut Decryptor
+/// * `cache` - &Cache
+/// * `policy` - &SharedPolicy
+/// * `locks` - &Locks
+async fn sync_decryptor(decryptor: &mut Decryptor, cache: &Cache, policy: &SharedPolicy, locks: &state::Locks) -> Result<(), Error> {
+    let client = Client::try_default().await?;
+    let (name, _, ns) = decryptor.get_metadata_info()?;
+    let key = state::get_key(&ns, &name);
     // get the existing hash...
     let current_hash = match &decryptor.status {
         Some(st) => st.current.revision.clone(),
@@ -67,41 +163,80 @@ async fn get_and_apply_template(mut decryptor: Decryptor) -> Result<(), Error> {
     };
 
     // get file and commit hash from the repo
+    // a decryptor which is pinned to a rollback revision will keep the same hash
     let spec = decryptor.spec.clone();
+    // the credentials of the repository may have been rotated. krapao update them if they changed
+    server::dispatch_clone_repository(decryptor, &client, &ns).await?;
     let filename = &spec.source.file_to_decrypt;
-    let (tmpl, hash) = crd::get_decrypted_kubernetes_object(&spec, &ns).await?;
+    // a Decryptor which keeps failing is not retried until the backoff delay expired. Pushing a new
+    // commit or fixing the spec of the Decryptor reset the delay
+    let commit = backoff::get_repository_commit(decryptor, &ns).await.ok();
+    if decryptor.is_backing_off(commit.as_deref()) {
+        info!("Skipping {key} which is backing off after consecutive failures");
+        return Ok(());
+    }
+
+    // a rollback which can't be resolved is reported in the status like any failed synchronization
+    let revision = match decryptor.get_rollback_revision().await {
+        Ok(res) => res,
+        Err(err) => {
+            let err = Error::from(err);
+            error!("Unable to resolve the rollback of {key}: {err}");
+            let status = backoff::get_failed_status(decryptor, &err, None, &ns).await;
+            return revision::update_status(decryptor, status, &client).await;
+        }
+    };
+
+    let (tmpl, hash, commit) = match crd::get_decrypted_kubernetes_object(decryptor, &client, &ns, revision).await {
+        Ok(res) => res,
+        Err(err) => {
+            error!("Unable to render {filename} of {key}: {err}");
+            let status = backoff::get_failed_status(decryptor, &err, None, &ns).await;
+            return revision::update_status(decryptor, status, &client).await;
+        }
+    };
+
+    // a Decryptor which has been held by its dependencies has not processed its current generation
+    // a failed synchronization is retried once the backoff delay expired
+    if current_hash != hash || !decryptor.is_observed() || decryptor.has_failed() {
+        if let Some(condition) = dependency::check_dependencies(decryptor, &client).await? {
+            return dependency::hold(decryptor, condition).await;
+        }
 
-    if current_hash != hash {
         // Apply the decrypted file in the kubernetes cluster
-        info!("Found changes in repository. Apply changes for file {filename}");
-        let apply_res = apply::apply_rendered_object(tmpl, &client, &ns).await;
+        info!("Found changes in repository. Apply changes for file {filename} of {key}");
+        // the watcher may apply the Decryptor concurrently
+        let lock = state::get_lock(locks, decryptor.metadata.uid.as_deref().unwrap_or(&key))?;
+        let _guard = lock.lock().await;
+        let apply_res = watcher::apply_decrypted_file(decryptor, tmpl, &hash, &client, &ns, cache, policy).await;
         return match apply_res {
-            Ok(_) => {
-                decryptor.set_status(DecryptorStatus::new(
+            Ok((ctx, replaced)) => {
+                let mut status = DecryptorStatus::new(
                     SyncStatus::Sync, 
                     None, 
                     Some(hash), 
-                ));
-                decryptor
-                    .update_status()
-                    .await
-                    .map_err(Error::from)
+                );
+                status.set_replaced_objects(replaced);
+                status.set_commit(commit);
+
+                if let Some(checks) = &spec.health_checks {
+                    status.set_condition(health::assess_health(checks, &ctx, &ns).await);
+                }
+
+                revision::update_status(decryptor, status, &client).await
             },
             Err(err) => {
-                decryptor.set_status(DecryptorStatus::new(
-                    SyncStatus::NotSync,  
-                    Some(err.to_string()),  
-                    Some(hash),  
-                ));
-                decryptor
-                    .update_status()
-                    .await
-                    .map_err(Error::from)
+                let mut status = backoff::get_failed_status(decryptor, &err, Some(hash), &ns).await;
+                status.set_commit(commit);
+                revision::update_status(decryptor, status, &client).await
             }
         }
     }
 
-    info!("No change detected for {filename}");
+    info!("No change detected for {filename} of {key}");
+    // the applied resources may have been degraded since the last synchronization
+    let ctx = watcher::get_apply_context(decryptor, &hash, &client, &ns, cache, policy).await?;
+    health::refresh_health(decryptor, &ctx, &ns).await?;
 
     Ok(())
 }
@@ -109,14 +244,17 @@ async fn get_and_apply_template(mut decryptor: Decryptor) -> Result<(), Error> {
 /// Get a list of Crd. The list is used to get the file to apply on the cluster
 /// We're assuming that the repo is being already pulled...
 /// 
+/// Only the Decryptors of the watched namespaces are listed
+/// 
 /// # Arguments
 /// * `client` - Client
-async fn list_crd(client: Client) -> Result<Vec<Decryptor>, Error> {
-    let api: Api<Decryptor> = Api::all(client);
+/// * `scope` - &WatchScope
+async fn list_crd(client: Client, scope: &WatchScope) -> Result<Vec<Decryptor>, Error> {
     let mut list = Vec::new();
-
-    for crd in api.list(&ListParams::default()).await? {
-        list.push(crd);
+    for api in scope.get_apis::<Decryptor>(&client).await? {
+        for crd in api.list(&ListParams::default()).await? {
+            list.push(crd);
+        }
     }
 
     Ok(list)
@@ -129,7 +267,7 @@ mod tests {
     #[tokio::test]
     async fn expect_list_crd_to_not_fail() {
         let client = Client::try_default().await.unwrap();
-        let res = list_crd(client).await;
+        let res = list_crd(client, &WatchScope::All).await;
 
         assert!(res.is_ok());
     }
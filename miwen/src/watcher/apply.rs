@@ -1,23 +1,48 @@
 use kube::{
     Api,
     Client,
+    Error as KubeError,
     core::{
         DynamicObject,
         GroupVersionKind,
-        ApiResource, ObjectMeta,
+        ObjectMeta,
     },
+    discovery::Scope,
     api::{
         PatchParams,
         Patch,
-        PostParams
+        PostParams,
+        DeleteParams
     },
 };
+use std::time::Duration;
+use tokio::time::sleep;
 use serde::Deserialize;
+use gen::crd::{
+    Decryptor,
+    ConflictPolicy,
+    output::SecretOutput,
+    post_render::Substitute
+};
 use crate::err::Error;
+use crate::discovery::{self, Cache};
+use crate::policy::{Policy, Rule};
+use super::wave;
+use super::snapshot::{Snapshot, Store};
+use super::tracking::Tracking;
 
 // Constant
 const API_GROUP_SPLIT: &str = "/";
 const MISSING_NAME_ERR: &str = "❌ Provided resource does not have a name";
+const DEFAULT_FIELD_MANAGER: &str = "miwen";
+const CONFLICT_MANAGER_PREFIX: &str = "conflict with \"";
+const CONFLICT_STATUS_CODE: u16 = 409;
+const INVALID_STATUS_CODE: u16 = 422;
+const NOT_FOUND_STATUS_CODE: u16 = 404;
+const IMMUTABLE_ERR_MSG: &str = "immutable";
+const DELETE_MAX_RETRY: u64 = 30;
+const DELETE_RETRY_INTERVAL: u64 = 1;
+pub const REPLACE_ANNOTATION: &str = "jiemi.cr/replace-on-immutable-change";
 
 #[derive(Deserialize, Debug)]
 struct GvkWrapper {
@@ -27,28 +52,37 @@ struct GvkWrapper {
     metadata: ObjectMeta
 }
 
+/// Build a GVK from the apiVersion and the kind of a resource
+/// 
+/// # Arguments
+/// * `api_version` - &str
+/// * `kind` - &str
+pub fn get_gvk(api_version: &str, kind: &str) -> GroupVersionKind {
+    // version is defined like so v1/deployment
+    // if we have no slash, then we use group as "". "" represent the core api
+    // version -> group
+    let splitted_group = api_version.split_once(API_GROUP_SPLIT);
+    match splitted_group {
+        Some((group, version)) => GroupVersionKind {
+            group: group.to_owned(),
+            version: version.to_owned(),
+            kind: kind.to_owned()
+        },
+        None => GroupVersionKind {
+            group: "".to_owned(),
+            version: api_version.to_owned(),
+            kind: kind.to_owned()
+        }
+    }
+}
+
 impl GvkWrapper {
     /// Retrieve the GVK from the wrapper
     /// 
     /// # Arguments
     /// * `&self` - &Self
     fn get_gkv(&self) -> GroupVersionKind {
-        // version is defined like so v1/deployment
-        // if we have no slash, then we use group as "". "" represent the core api
-        // version -> group
-        let splitted_group = self.api_version.split_once(API_GROUP_SPLIT);
-        match splitted_group {
-            Some((group, version)) => GroupVersionKind {
-                group: group.to_owned(),
-                version: version.to_owned(),
-                kind: self.kind.clone()
-            },
-            None => GroupVersionKind {
-                group: "".to_owned(),
-                version: self.api_version.clone(),
-                kind: self.kind.clone()
-            }
-        }
+        get_gvk(&self.api_version, &self.kind)
     }
 
     /// Get the name of the kubernetes resource
@@ -57,28 +91,184 @@ impl GvkWrapper {
     }
 }
 
-/// Create a resource based on the DynamicObject
+/// ApplyConfig contains the options used to apply the rendered objects of a Decryptor
+#[derive(Debug, Clone)]
+pub struct ApplyConfig {
+    pub tracking: Tracking,
+    pub field_manager: String,
+    pub conflict_policy: ConflictPolicy,
+    pub allow_cross_namespace: bool,
+    pub discovery: Cache,
+    pub output: Option<SecretOutput>,
+    pub substitute: Option<Substitute>,
+    pub policy: Option<Rule>,
+    pub replace_on_immutable_change: bool
+}
+
+impl Default for ApplyConfig {
+    fn default() -> Self {
+        ApplyConfig {
+            tracking: Tracking::default(),
+            field_manager: DEFAULT_FIELD_MANAGER.to_owned(),
+            conflict_policy: ConflictPolicy::default(),
+            allow_cross_namespace: false,
+            discovery: discovery::generate_new_cache(),
+            output: None,
+            substitute: None,
+            policy: None,
+            replace_on_immutable_change: false
+        }
+    }
+}
+
+impl ApplyConfig {
+    /// Create a new ApplyConfig from the spec of the Decryptor
+    /// 
+    /// # Arguments
+    /// * `decryptor` - &Decryptor
+    /// * `revision` - &str
+    /// * `discovery` - Cache
+    /// * `policy` - &Policy
+    pub fn new(decryptor: &Decryptor, revision: &str, discovery: Cache, policy: &Policy) -> Result<Self, Error> {
+        let spec = &decryptor.spec;
+        let tracking = Tracking::new(decryptor, revision)?;
+
+        Ok(ApplyConfig {
+            policy: policy.get_rule(&tracking.namespace),
+            tracking,
+            field_manager: spec.field_manager.to_owned()
+                .unwrap_or_else(|| DEFAULT_FIELD_MANAGER.to_owned()),
+            conflict_policy: spec.conflict_policy.to_owned().unwrap_or_default(),
+            allow_cross_namespace: spec.allow_cross_namespace.unwrap_or_default(),
+            discovery,
+            output: spec.output.to_owned().and_then(|output| output.secret),
+            substitute: spec.post_render.to_owned().and_then(|post_render| post_render.substitute),
+            replace_on_immutable_change: spec.replace_on_immutable_change.unwrap_or_default()
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct RenderedObject {
+    pub gvk: GroupVersionKind,
+    pub name: String,
+    pub object: DynamicObject
+}
+
+/// Parse the rendered template which may contain multiple YAML documents into a list of RenderedObject.
+/// Empty documents are skipped
+/// 
+/// # Arguments
+/// * `tmpl` - &str
+pub fn parse_rendered_objects(tmpl: &str) -> Result<Vec<RenderedObject>, Error> {
+    let mut objects = Vec::new();
+    for document in serde_yaml::Deserializer::from_str(tmpl) {
+        let value = serde_yaml::Value::deserialize(document)?;
+        if value.is_null() {
+            continue;
+        }
+
+        let gvk_wrapper: GvkWrapper = serde_yaml::from_value(value.clone())?;
+        let gvk = gvk_wrapper.get_gkv();
+        let name = gvk_wrapper.get_name()
+            .ok_or_else(|| Error::Watch(MISSING_NAME_ERR.to_owned()))?;
+
+        objects.push(RenderedObject {
+            gvk,
+            name,
+            object: serde_yaml::from_value(value)?
+        });
+    }
+
+    Ok(objects)
+}
+
+/// Create a resource based on the DynamicObject. The resource is created with the field manager of the Decryptor
+/// in order to own the fields on the next updates
 /// 
 /// # Arguments
 /// * `api` - Api<DynamicObject>
 /// * `patch` - DynamicObject
-async fn create_resource(api: Api<DynamicObject>, patch: DynamicObject) -> Result<(), Error> {
-    api.create(&PostParams::default(), &patch).await?;
+/// * `config` - &ApplyConfig
+async fn create_resource(api: Api<DynamicObject>, patch: DynamicObject, config: &ApplyConfig) -> Result<(), Error> {
+    let params = PostParams {
+        field_manager: Some(config.field_manager.to_owned()),
+        ..Default::default()
+    };
+
+    api.create(&params, &patch).await?;
     info!("📝 Resource has been created");
 
     Ok(())
 }
 
+/// Retrieve the name of the managers which own the conflicting fields from the message of the api server
+/// 
+/// # Example
+/// Apply failed with 1 conflict: conflict with "helm" using v1: .data.foo
+/// 
+/// # Arguments
+/// * `msg` - &str
+fn get_conflicting_managers(msg: &str) -> Vec<String> {
+    let mut managers: Vec<String> = msg.split(CONFLICT_MANAGER_PREFIX)
+        .skip(1)
+        .filter_map(|part| part.split_once('"'))
+        .map(|(manager, _)| manager.to_owned())
+        .collect();
+
+    managers.sort();
+    managers.dedup();
+    managers
+}
+
+/// Check whenever the error returned by the api server is caused by a change of an immutable field
+/// (e.g: data of an immutable Secret, selector of a Deployment...)
+/// 
+/// # Arguments
+/// * `code` - u16
+/// * `msg` - &str
+fn is_immutable_error(code: u16, msg: &str) -> bool {
+    code == INVALID_STATUS_CODE && msg.contains(IMMUTABLE_ERR_MSG)
+}
+
+/// Check whenever the object can be replaced when an immutable field changed. The annotation
+/// of the object takes precedence over the replaceOnImmutableChange field of the Decryptor
+/// 
+/// # Arguments
+/// * `rendered` - &RenderedObject
+/// * `config` - &ApplyConfig
+fn is_replace_allowed(rendered: &RenderedObject, config: &ApplyConfig) -> bool {
+    let annotation = rendered.object.metadata.annotations.as_ref()
+        .and_then(|annotations| annotations.get(REPLACE_ANNOTATION));
+
+    match annotation {
+        Some(value) => value == "true",
+        None => config.replace_on_immutable_change
+    }
+}
+
 /// Patch a Kubernetes resource with the dynamic object
+/// Field ownership conflicts are handled depending on the conflict policy
+///     - Force: fields owned by other managers are taken over
+///     - Fail: an error containing the name of the other managers is returned
+///     - Skip: the object is not applied
+/// 
+/// A change of an immutable field is reported with a dedicated error which allows the object to be replaced
 /// 
 /// # Arguments
 /// * `api` - Api<DynamicObject>
 /// * `name` - &str
 /// * `patch` - DynamicObject
-async fn patch_resource(api: Api<DynamicObject>, name: &str, patch: DynamicObject) -> Result<(), Error> {
+/// * `config` - &ApplyConfig
+async fn patch_resource(api: Api<DynamicObject>, name: &str, patch: DynamicObject, config: &ApplyConfig) -> Result<(), Error> {
+    let mut params = PatchParams::apply(&config.field_manager);
+    if config.conflict_policy == ConflictPolicy::Force {
+        params = params.force();
+    }
+
     let res = api.patch(
         name, 
-        &PatchParams::apply("miwen").force(),
+        &params,
          &Patch::Apply(&patch)
     ).await;
 
@@ -87,6 +277,20 @@ async fn patch_resource(api: Api<DynamicObject>, name: &str, patch: DynamicObjec
             info!("🖌️ Resource {name} has been successfully synchronized");
             Ok(())
         },
+        Err(KubeError::Api(err)) if err.code == CONFLICT_STATUS_CODE => {
+            let managers = get_conflicting_managers(&err.message).join(", ");
+            if config.conflict_policy == ConflictPolicy::Skip {
+                warn!("⏭️ Resource {name} is skipped as fields are owned by {managers}");
+                return Ok(());
+            }
+
+            error!("❌ Resource {name} has a conflict with {managers}");
+            Err(Error::Conflict(managers, err.message))
+        },
+        Err(KubeError::Api(err)) if is_immutable_error(err.code, &err.message) => {
+            error!("❌ Resource {name} has immutable fields which have changed");
+            Err(Error::Immutable(name.to_owned(), err.message))
+        },
         Err(err) => {
             error!("❌ Resource could not be synchronize: {err:?}");
             Err(Error::from(err))
@@ -94,37 +298,288 @@ async fn patch_resource(api: Api<DynamicObject>, name: &str, patch: DynamicObjec
     }
 }
 
-/// Apply the rendered template in the Kubernetes cluster
+/// Replace a Kubernetes resource by deleting and re-creating it. The resource is re-created
+/// once the api server has completed the deletion. A resource which is still terminating because of its
+/// finalizers is reported as such
+/// 
+/// # Arguments
+/// * `api` - Api<DynamicObject>
+/// * `name` - &str
+/// * `patch` - DynamicObject
+/// * `config` - &ApplyConfig
+async fn replace_resource(api: Api<DynamicObject>, name: &str, patch: DynamicObject, config: &ApplyConfig) -> Result<(), Error> {
+    info!("♻️ Replacing the resource {name} as immutable fields have changed");
+    api.delete(name, &DeleteParams::default()).await?;
+
+    let mut finalizers = Vec::new();
+    for _ in 0..DELETE_MAX_RETRY {
+        match api.get(name).await {
+            Ok(object) => {
+                finalizers = object.metadata.finalizers.unwrap_or_default();
+                sleep(Duration::from_secs(DELETE_RETRY_INTERVAL)).await
+            },
+            Err(KubeError::Api(err)) if err.code == NOT_FOUND_STATUS_CODE => {
+                return patch_resource(api, name, patch, config).await;
+            },
+            Err(err) => return Err(Error::from(err))
+        }
+    }
+
+    if !finalizers.is_empty() {
+        error!("❌ Resource {name} can't be re-created as its deletion is blocked by its finalizers");
+        return Err(Error::DeletionBlocked(name.to_owned(), finalizers));
+    }
+
+    Err(Error::Apply(format!("{name} has not been deleted in time to be replaced")))
+}
+
+/// Apply an object captured by a snapshot in order to restore its previous state. The object is replaced
+/// when the immutable fields have been changed by the failed apply
+/// 
+/// # Arguments
+/// * `api` - Api<DynamicObject>
+/// * `name` - &str
+/// * `object` - DynamicObject
+/// * `config` - &ApplyConfig
+pub async fn restore_object(api: Api<DynamicObject>, name: &str, object: DynamicObject, config: &ApplyConfig) -> Result<(), Error> {
+    match patch_resource(api.clone(), name, object.clone(), config).await {
+        Err(Error::Immutable(..)) => replace_resource(api, name, object, config).await,
+        res => res
+    }
+}
+
+/// Check whenever an object can target an other namespace than the namespace of the Decryptor. The Decryptor
+/// needs to allow it and the policy of the controller needs to list the target namespace. Nothing
+/// can target an other namespace when no policy is loaded
+/// 
+/// # Arguments
+/// * `target` - &str
+/// * `config` - &ApplyConfig
+fn is_cross_namespace_allowed(target: &str, config: &ApplyConfig) -> bool {
+    if !config.allow_cross_namespace {
+        return false;
+    }
+
+    config.policy.as_ref()
+        .map(|rule| rule.is_namespace_allowed(target))
+        .unwrap_or_default()
+}
+
+/// Get the namespace where a namespaced object is applied. The namespace of the Decryptor is used unless
+/// the object specify it's own namespace. An object can only target an other namespace if the
+/// Decryptor and the policy allow it
+/// 
+/// # Arguments
+/// * `rendered` - &RenderedObject
+/// * `ns` - &str
+/// * `config` - &ApplyConfig
+fn get_target_namespace(rendered: &RenderedObject, ns: &str, config: &ApplyConfig) -> Result<String, Error> {
+    match rendered.object.metadata.namespace.as_deref() {
+        Some(target) if target != ns && !is_cross_namespace_allowed(target, config) => Err(Error::Apply(format!(
+            "{} {} target the namespace {target} which is not allowed for a Decryptor in the namespace {ns}",
+            rendered.gvk.kind,
+            rendered.name
+        ))),
+        Some(target) => Ok(target.to_owned()),
+        None => Ok(ns.to_owned())
+    }
+}
+
+/// Get the Api used to manage the rendered object alongside the namespace of the object.
+/// The plural and the scope of the kind are retrieved with the discovery cache
+///     - Cluster scoped objects are managed w/o namespace
+///     - Namespaced objects are managed in the target namespace
+/// 
+/// # Arguments
+/// * `rendered` - &RenderedObject
+/// * `client` - &Client
+/// * `ns` - &str
+/// * `config` - &ApplyConfig
+pub async fn get_object_api(
+    rendered: &RenderedObject,
+    client: &Client,
+    ns: &str,
+    config: &ApplyConfig
+) -> Result<(Api<DynamicObject>, Option<String>), Error> {
+    let (api_resource, capabilities) = discovery::resolve_appliable(&config.discovery, client, &rendered.gvk).await?;
+    if capabilities.scope == Scope::Cluster {
+        return Ok((Api::all_with(client.clone(), &api_resource), None));
+    }
+
+    let target = get_target_namespace(rendered, ns, config)?;
+    let api = Api::namespaced_with(client.clone(), &target, &api_resource);
+
+    Ok((api, Some(target)))
+}
+
+/// Check that every rendered objects are allowed by the policy of the controller. Nothing is applied
+/// when an object violates the policy
+/// 
+/// # Arguments
+/// * `objects` - &[RenderedObject]
+/// * `client` - &Client
+/// * `ns` - &str
+/// * `config` - &ApplyConfig
+pub async fn check_policy(objects: &[RenderedObject], client: &Client, ns: &str, config: &ApplyConfig) -> Result<(), Error> {
+    let rule = match &config.policy {
+        Some(rule) => rule,
+        None => return Ok(())
+    };
+
+    let mut violations = Vec::new();
+    for rendered in objects {
+        let target = match rendered.object.metadata.namespace.as_deref() {
+            Some(target) => Some(target.to_owned()),
+            None => get_object_api(rendered, client, ns, config).await?.1
+        };
+
+        if let Err(violation) = rule.check(&rendered.gvk.kind, &rendered.name, ns, target.as_deref()) {
+            violations.push(violation);
+        }
+    }
+
+    if !violations.is_empty() {
+        return Err(Error::Policy(violations.join(", ")));
+    }
+
+    Ok(())
+}
+
+/// Apply a single rendered object in the Kubernetes cluster
 /// Because we couldn't applied YAML straight away. We need to retrieve the resource type
 /// to create the DynamicObject. We need to retrieve the:
 ///     - GVK
-///     - Create an apiResource from the GVK
+///     - Resolve the apiResource and the scope of the GVK
 ///     - name of the resource
 /// 
-/// If the resource already exist, then we're going to patch it. Otherwise we'll create the resource
+/// If the resource already exist, then we're going to patch it. Otherwise we'll create the resource.
+/// A resource which has immutable fields changed is replaced if allowed. Return whenever the resource has been replaced
 /// 
 /// # Arguments
-/// * `tmpl` - String
+/// * `rendered` - &RenderedObject
 /// * `client` - &Client
 /// * `ns` - &str
-pub async fn apply_rendered_object(tmpl: String, client: &Client, ns: &str) -> Result<(), Error> {
-    // Get the gvk struct from the rendered yaml
-    let gvk_wrapper: GvkWrapper = serde_yaml::from_str(&tmpl)?;
-    let gvk = gvk_wrapper.get_gkv();
-    // create an api_resource from the gvk
-    let api_resource = ApiResource::from_gvk(&gvk);
-    // get a dynamic object
-    let api: Api<DynamicObject> = Api::namespaced_with(client.clone(), ns, &api_resource);
-
-    let res_name = gvk_wrapper.get_name()
-        .ok_or_else(|| Error::Watch(MISSING_NAME_ERR.to_owned()))?;
+/// * `config` - &ApplyConfig
+async fn apply_object(rendered: &RenderedObject, client: &Client, ns: &str, config: &ApplyConfig) -> Result<bool, Error> {
+    let (api, target) = get_object_api(rendered, client, ns, config).await?;
 
     // get a dynamic object to retrieve the metadata...
-    let res = api.get(&res_name).await;
-    let patch: DynamicObject = serde_yaml::from_str(&tmpl)?;
-    match res {
-        Ok(_) => patch_resource(api, &res_name, patch).await,
-        Err(_) => create_resource(api, patch).await
+    let res = api.get(&rendered.name).await;
+    let mut patch = rendered.object.clone();
+    patch.metadata.namespace = target;
+    config.tracking.inject(&mut patch);
+    if res.is_err() {
+        return create_resource(api, patch, config).await.map(|_| false);
+    }
+
+    match patch_resource(api.clone(), &rendered.name, patch.clone(), config).await {
+        Err(Error::Immutable(..)) if is_replace_allowed(rendered, config) => {
+            replace_resource(api, &rendered.name, patch, config).await?;
+            Ok(true)
+        },
+        res => res.map(|_| false)
+    }
+}
+
+/// Apply the waves of objects. The live state of each object is captured by the snapshot before it's changed
+/// 
+/// # Arguments
+/// * `waves` - Vec<wave::Wave>
+/// * `snapshot` - &mut Snapshot
+/// * `client` - &Client
+/// * `ns` - &str
+/// * `config` - &ApplyConfig
+async fn apply_waves(
+    waves: Vec<wave::Wave>,
+    snapshot: &mut Snapshot,
+    client: &Client,
+    ns: &str,
+    config: &ApplyConfig
+) -> Result<Vec<String>, Error> {
+    let mut replaced = Vec::new();
+    for wave in waves {
+        for rendered in &wave.objects {
+            snapshot.capture(rendered, client, ns, config).await
+                .map_err(|err| Error::Wave(wave.id, err.to_string()))?;
+
+            // an object stuck in deletion is reported as it is as it needs an action on the cluster
+            let res = apply_object(rendered, client, ns, config).await
+                .map_err(|err| match err {
+                    Error::DeletionBlocked(..) => err,
+                    err => Error::Wave(wave.id, err.to_string())
+                })?;
+
+            if res {
+                replaced.push(format!("{} {}", rendered.gvk.kind, rendered.name));
+            }
+        }
+
+        wave::wait_for_wave(&wave, client, ns, config).await?;
+    }
+
+    Ok(replaced)
+}
+
+/// Apply the rendered template in the Kubernetes cluster
+/// The template may contain several objects. These objects are applied by sync wave
+/// (see the jiemi.cr/sync-wave annotation). Within a wave, objects are ordered by kind
+///     - Namespace
+///     - CustomResourceDefinition
+///     - RBAC
+///     - Everything else
+/// 
+/// Each wave needs to be established before the next wave is applied. Every objects are labeled
+/// with the Decryptor which owns them as well as the source file and the revision
+/// 
+/// The objects are checked against the policy of the controller before anything is applied
+/// 
+/// The objects are applied as a whole. If any object fails, the objects which have been changed are
+/// restored from the snapshot saved in the store. The snapshot is discarded unless the restore fails.
+/// The list of the objects which have been replaced is returned
+/// 
+/// # Arguments
+/// * `tmpl` - String
+/// * `client` - &Client
+/// * `ns` - &str
+/// * `config` - &ApplyConfig
+/// * `store` - Option<Store>
+pub async fn apply_rendered_object(
+    tmpl: String,
+    client: &Client,
+    ns: &str,
+    config: &ApplyConfig,
+    store: Option<Store>
+) -> Result<Vec<String>, Error> {
+    let objects = parse_rendered_objects(&tmpl)?;
+    check_policy(&objects, client, ns, config).await?;
+    let waves = wave::group_by_wave(objects)?;
+
+    let mut snapshot = Snapshot::new(store);
+    match apply_waves(waves, &mut snapshot, client, ns, config).await {
+        Ok(replaced) => {
+            if let Err(err) = snapshot.discard().await {
+                warn!("Unable to discard the snapshot of the applied objects: {err}");
+            }
+
+            Ok(replaced)
+        },
+        Err(err) => {
+            error!("❌ Restoring the objects to their previous state: {err}");
+            let restored = snapshot.restore(config).await;
+            // the snapshot is kept when the restore failed in order to restore it again when miwen starts
+            if restored.is_ok() {
+                if let Err(err) = snapshot.discard().await {
+                    warn!("Unable to discard the snapshot of the restored objects: {err}");
+                }
+            }
+
+            match (err, restored) {
+                // the reason is kept as the object can't be re-created until its finalizers are removed
+                (err @ Error::DeletionBlocked(..), _) => Err(err),
+                (err, Ok(_)) => Err(Error::RolledBack(err.to_string())),
+                (err, Err(restore_err)) => Err(Error::Apply(format!("{err}. {restore_err}")))
+            }
+        }
     }
 }
 
@@ -165,8 +620,10 @@ mod tests {
 
     #[test]
     fn expect_to_get_name() {
-        let mut meta = ObjectMeta::default();
-        meta.name = Some("foo".to_owned());
+        let meta = ObjectMeta {
+            name: Some("foo".to_owned()),
+            ..Default::default()
+        };
 
         let wrapper = GvkWrapper {
             api_version: "v1".to_owned(),
@@ -178,6 +635,130 @@ mod tests {
         assert_eq!(name, "foo");
     }
 
+    #[test]
+    fn expect_to_parse_multiple_documents() {
+        let tmpl = r#"
+---
+apiVersion: v1
+kind: Namespace
+metadata:
+  name: foo
+---
+apiVersion: v1
+kind: Secret
+metadata:
+  name: bar
+data:
+  foo: YmFy
+---
+"#;
+
+        let objects = parse_rendered_objects(tmpl).unwrap();
+        assert_eq!(objects.len(), 2);
+        assert_eq!(objects[0].gvk.kind, "Namespace");
+        assert_eq!(objects[1].name, "bar");
+        assert_eq!(objects[1].object.data["data"]["foo"], "YmFy");
+    }
+
+    #[test]
+    fn expect_to_not_parse_object_wo_name() {
+        let tmpl = r#"
+apiVersion: v1
+kind: Secret
+metadata: {}
+"#;
+
+        let res = parse_rendered_objects(tmpl);
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn expect_to_get_conflicting_managers() {
+        let msg = r#"Apply failed with 2 conflicts: conflict with "helm" using v1: .data.foo, conflict with "kubectl-edit" using v1: .data.bar"#;
+        let managers = get_conflicting_managers(msg);
+
+        assert_eq!(managers, vec!["helm", "kubectl-edit"]);
+
+        let msg = r#"Apply failed with 3 conflicts: conflict with "helm" using v1: .data.foo, conflict with "kubectl-edit" using v1: .data.bar, conflict with "helm" using v1: .data.baz"#;
+        let managers = get_conflicting_managers(msg);
+
+        assert_eq!(managers, vec!["helm", "kubectl-edit"]);
+    }
+
+    #[test]
+    fn expect_to_get_target_namespace() {
+        let tmpl = r#"
+apiVersion: v1
+kind: Secret
+metadata:
+  name: foo
+---
+apiVersion: v1
+kind: Secret
+metadata:
+  name: bar
+  namespace: prod
+"#;
+
+        let objects = parse_rendered_objects(tmpl).unwrap();
+        let mut config = ApplyConfig::default();
+
+        let ns = get_target_namespace(&objects[0], "default", &config).unwrap();
+        assert_eq!(ns, "default");
+
+        let res = get_target_namespace(&objects[1], "default", &config);
+        assert!(res.is_err());
+
+        // the flag of the Decryptor is not honored w/o a policy
+        config.allow_cross_namespace = true;
+        let res = get_target_namespace(&objects[1], "default", &config);
+        assert!(res.is_err());
+
+        let policy: Policy = serde_yaml::from_str(r#"
+rules:
+  - sourceNamespace: default
+    allowedKinds: ["Secret"]
+    allowedNamespaces: ["default", "prod"]
+"#).unwrap();
+        config.policy = policy.get_rule("default");
+        let ns = get_target_namespace(&objects[1], "default", &config).unwrap();
+        assert_eq!(ns, "prod");
+    }
+
+    #[test]
+    fn expect_to_detect_immutable_error() {
+        let msg = "Secret \"foo\" is invalid: data: Forbidden: field is immutable when `immutable` is set";
+
+        assert!(is_immutable_error(422, msg));
+        assert!(!is_immutable_error(422, "Secret \"foo\" is invalid: metadata.name: Required value"));
+        assert!(!is_immutable_error(409, msg));
+    }
+
+    #[test]
+    fn expect_annotation_to_take_precedence_over_spec() {
+        let tmpl = r#"
+apiVersion: v1
+kind: Secret
+metadata:
+  name: foo
+---
+apiVersion: v1
+kind: Secret
+metadata:
+  name: bar
+  annotations:
+    jiemi.cr/replace-on-immutable-change: "false"
+"#;
+
+        let objects = parse_rendered_objects(tmpl).unwrap();
+        let mut config = ApplyConfig::default();
+        assert!(!is_replace_allowed(&objects[0], &config));
+
+        config.replace_on_immutable_change = true;
+        assert!(is_replace_allowed(&objects[0], &config));
+        assert!(!is_replace_allowed(&objects[1], &config));
+    }
+
     #[tokio::test]
     async fn expect_to_apply_rendered_object() {
         let configmap = r#"
@@ -193,7 +774,16 @@ mod tests {
         let client = Client::try_default().await.unwrap();
 
         // creation
-        let res = apply_rendered_object(configmap.to_owned(), &client, "default").await;
+        let config = ApplyConfig {
+            tracking: Tracking {
+                name: "miwen-unit-test".to_owned(),
+                namespace: "default".to_owned(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let res = apply_rendered_object(configmap.to_owned(), &client, "default", &config, None).await;
         assert!(res.is_ok());
 
         let updated_configmap = r#"
@@ -206,7 +796,7 @@ mod tests {
           ui_properties_file_name: "user-interface.properties"
         "#;
         
-        let res = apply_rendered_object(updated_configmap.to_owned(), &client, "default").await;
+        let res = apply_rendered_object(updated_configmap.to_owned(), &client, "default", &config, None).await;
         assert!(res.is_ok());
 
         // Checking that the value is really 5
@@ -0,0 +1,416 @@
+// This mod is used to evaluate the health of the resources referenced by the Decryptor
+// once the decrypted file has been applied
+use kube::core::{ApiResource, DynamicObject};
+use gen::crd::{
+    Decryptor,
+    health::HealthCheck,
+    status::{Condition, HEALTHY_CONDITION}
+};
+use serde_json::Value;
+use crate::err::Error;
+use super::ApplyContext;
+use super::apply::{self, RenderedObject};
+
+// Constant
+const HEALTHY_REASON: &str = "Healthy";
+const PROGRESSING_REASON: &str = "Progressing";
+const DEGRADED_REASON: &str = "Degraded";
+const ROLLOUT_COMPLETE_REASON: &str = "NewReplicaSetAvailable";
+
+/// Health of a resource
+///     - Healthy: the resource is ready
+///     - Progressing: the rollout of the resource has not settled yet
+///     - Degraded: the rollout has settled but the resource is not ready
+#[derive(Debug, PartialEq)]
+enum Health {
+    Healthy,
+    Progressing(String),
+    Degraded(String)
+}
+
+/// Get a counter from the json value. Missing counters are equal to 0
+///
+/// # Arguments
+/// * `value` - &Value
+fn get_count(value: &Value) -> i64 {
+    value.as_i64().unwrap_or_default()
+}
+
+/// Get a condition from the status of the object
+///
+/// # Arguments
+/// * `object` - &DynamicObject
+/// * `condition` - &str
+fn get_condition<'a>(object: &'a DynamicObject, condition: &str) -> Option<&'a Value> {
+    object.data["status"]["conditions"]
+        .as_array()
+        .and_then(|conditions| conditions.iter().find(|c| c["type"] == condition))
+}
+
+/// Check that the controller has observed the latest generation of the object. The object is
+/// Progressing until it has been observed
+///
+/// # Arguments
+/// * `object` - &DynamicObject
+fn is_generation_observed(object: &DynamicObject) -> Option<Health> {
+    let generation = object.metadata.generation.unwrap_or_default();
+    let observed = get_count(&object.data["status"]["observedGeneration"]);
+    if observed < generation {
+        return Some(Health::Progressing(format!("generation {generation} has not been observed yet")));
+    }
+
+    None
+}
+
+/// Evaluate a Deployment. A deployment is healthy when every replicas has been updated and are available.
+/// The deployment is Progressing until the rollout completes and Degraded when the progress deadline is exceeded
+/// or when replicas become unavailable once the rollout completed
+///
+/// # Arguments
+/// * `object` - &DynamicObject
+fn evaluate_deployment(object: &DynamicObject) -> Health {
+    if let Some(health) = is_generation_observed(object) {
+        return health;
+    }
+
+    let progressing = get_condition(object, "Progressing");
+    let deadline_exceeded = progressing
+        .map(|c| c["reason"] == "ProgressDeadlineExceeded")
+        .unwrap_or_default();
+
+    if deadline_exceeded {
+        return Health::Degraded("progress deadline exceeded".to_owned());
+    }
+
+    let status = &object.data["status"];
+    let replicas = object.data["spec"]["replicas"].as_i64().unwrap_or(1);
+    let updated = get_count(&status["updatedReplicas"]);
+    let available = get_count(&status["availableReplicas"]);
+    if updated < replicas {
+        return Health::Progressing(format!("{updated}/{replicas} replicas updated"));
+    }
+
+    if available < replicas {
+        let msg = format!("{available}/{replicas} replicas available");
+        let rollout_complete = progressing
+            .map(|c| c["reason"] == ROLLOUT_COMPLETE_REASON)
+            .unwrap_or_default();
+
+        return match rollout_complete {
+            true => Health::Degraded(msg),
+            false => Health::Progressing(msg)
+        };
+    }
+
+    Health::Healthy
+}
+
+/// Evaluate a StatefulSet. A statefulset is healthy when every replicas are ready and run the latest revision.
+/// The statefulset is Progressing while the pods of the latest revision are created
+///
+/// # Arguments
+/// * `object` - &DynamicObject
+fn evaluate_statefulset(object: &DynamicObject) -> Health {
+    if let Some(health) = is_generation_observed(object) {
+        return health;
+    }
+
+    let status = &object.data["status"];
+    if status["updateRevision"] != status["currentRevision"] {
+        return Health::Progressing("rollout of the latest revision is in progress".to_owned());
+    }
+
+    let replicas = object.data["spec"]["replicas"].as_i64().unwrap_or(1);
+    let current = get_count(&status["currentReplicas"]);
+    let ready = get_count(&status["readyReplicas"]);
+    if ready < replicas {
+        let msg = format!("{ready}/{replicas} replicas ready");
+        return match current < replicas {
+            true => Health::Progressing(msg),
+            false => Health::Degraded(msg)
+        };
+    }
+
+    Health::Healthy
+}
+
+/// Evaluate a generic resource by looking for the readiness condition in it's status. The resource is
+/// Progressing while the condition is missing, Unknown or computed for a previous generation
+///
+/// # Arguments
+/// * `object` - &DynamicObject
+/// * `condition` - &str
+fn evaluate_condition(object: &DynamicObject, condition: &str) -> Health {
+    let found = match get_condition(object, condition) {
+        Some(c) => c,
+        None => return Health::Progressing(format!("condition {condition} could not be founded"))
+    };
+
+    let generation = object.metadata.generation.unwrap_or_default();
+    if found["observedGeneration"].is_i64() && get_count(&found["observedGeneration"]) < generation {
+        return Health::Progressing(format!("condition {condition} has not observed the generation {generation} yet"));
+    }
+
+    match found["status"].as_str() {
+        Some("True") => Health::Healthy,
+        Some("False") => Health::Degraded(format!("condition {condition} is False")),
+        _ => Health::Progressing(format!("condition {condition} is Unknown"))
+    }
+}
+
+/// Evaluate the object targeted by the health check
+///
+/// # Arguments
+/// * `check` - &HealthCheck
+/// * `object` - &DynamicObject
+fn evaluate(check: &HealthCheck, object: &DynamicObject) -> Health {
+    match check.kind.as_str() {
+        "Deployment" => evaluate_deployment(object),
+        "StatefulSet" => evaluate_statefulset(object),
+        _ => evaluate_condition(object, &check.get_condition())
+    }
+}
+
+/// Get the object targeted by the health check. The object is read with the client used to apply the objects
+/// of the Decryptor. The kind and the namespace of the object are checked against the policy like any applied object
+///
+/// # Arguments
+/// * `check` - &HealthCheck
+/// * `ctx` - &ApplyContext
+/// * `ns` - &str
+async fn get_object(check: &HealthCheck, ctx: &ApplyContext, ns: &str) -> Result<DynamicObject, Error> {
+    let gvk = apply::get_gvk(&check.api_version, &check.kind);
+    let mut object = DynamicObject::new(&check.name, &ApiResource::from_gvk(&gvk));
+    object.metadata.namespace = check.namespace.to_owned();
+
+    let rendered = RenderedObject {
+        gvk,
+        name: check.name.to_owned(),
+        object
+    };
+
+    apply::check_policy(std::slice::from_ref(&rendered), &ctx.client, ns, &ctx.config).await?;
+    let (api, _) = apply::get_object_api(&rendered, &ctx.client, ns, &ctx.config).await?;
+    let object = api.get(&check.name).await?;
+
+    Ok(object)
+}
+
+/// Assess the health of the resources referenced by the health checks. The result is
+/// rolled up in a Healthy condition
+///     - Degraded if any of the resources is degraded
+///     - Progressing if any of the resources has not settled yet (e.g: right after the apply)
+///     - Healthy otherwise
+///
+/// # Arguments
+/// * `checks` - &[HealthCheck]
+/// * `ctx` - &ApplyContext
+/// * `ns` - &str
+pub async fn assess_health(checks: &[HealthCheck], ctx: &ApplyContext, ns: &str) -> Condition {
+    let mut degraded = Vec::new();
+    let mut progressing = Vec::new();
+    for check in checks {
+        let health = match get_object(check, ctx, ns).await {
+            Ok(object) => evaluate(check, &object),
+            Err(err) => Health::Degraded(err.to_string())
+        };
+
+        match health {
+            Health::Healthy => {},
+            Health::Progressing(msg) => progressing.push(format!("{} {}: {msg}", check.kind, check.name)),
+            Health::Degraded(msg) => degraded.push(format!("{} {}: {msg}", check.kind, check.name))
+        }
+    }
+
+    if !degraded.is_empty() {
+        return Condition::new(HEALTHY_CONDITION, false, DEGRADED_REASON, Some(degraded.join(", ")));
+    }
+
+    if !progressing.is_empty() {
+        return Condition::new(HEALTHY_CONDITION, false, PROGRESSING_REASON, Some(progressing.join(", ")));
+    }
+
+    Condition::new(HEALTHY_CONDITION, true, HEALTHY_REASON, None)
+}
+
+/// Refresh the health of the Decryptor. The status is only updated when the health changed
+///
+/// # Arguments
+/// * `decryptor` - &mut Decryptor
+/// * `ctx` - &ApplyContext
+/// * `ns` - &str
+pub async fn refresh_health(decryptor: &mut Decryptor, ctx: &ApplyContext, ns: &str) -> Result<(), Error> {
+    let checks = match &decryptor.spec.health_checks {
+        Some(checks) => checks.to_owned(),
+        None => return Ok(())
+    };
+
+    let status = match decryptor.status.as_mut() {
+        Some(status) => status,
+        None => return Ok(())
+    };
+
+    let condition = assess_health(&checks, ctx, ns).await;
+    let changed = match status.get_condition(HEALTHY_CONDITION) {
+        Some(existing) => existing.status != condition.status
+            || existing.reason != condition.reason
+            || existing.message != condition.message,
+        None => true
+    };
+
+    if changed {
+        status.set_condition(condition);
+        decryptor.update_status().await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_object(yaml: &str) -> DynamicObject {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn expect_deployment_to_be_healthy() {
+        let object = get_object(r#"
+apiVersion: apps/v1
+kind: Deployment
+metadata:
+  name: app
+  generation: 2
+spec:
+  replicas: 2
+status:
+  observedGeneration: 2
+  updatedReplicas: 2
+  availableReplicas: 2
+"#);
+
+        assert_eq!(evaluate_deployment(&object), Health::Healthy);
+    }
+
+    #[test]
+    fn expect_crashlooping_deployment_to_be_degraded() {
+        let object = get_object(r#"
+apiVersion: apps/v1
+kind: Deployment
+metadata:
+  name: app
+  generation: 2
+spec:
+  replicas: 2
+status:
+  observedGeneration: 2
+  updatedReplicas: 2
+  availableReplicas: 1
+  conditions:
+    - type: Progressing
+      status: "True"
+      reason: NewReplicaSetAvailable
+"#);
+
+        assert!(matches!(evaluate_deployment(&object), Health::Degraded(_)));
+    }
+
+    #[test]
+    fn expect_deployment_rollout_to_be_progressing() {
+        let object = get_object(r#"
+apiVersion: apps/v1
+kind: Deployment
+metadata:
+  name: app
+  generation: 3
+spec:
+  replicas: 2
+status:
+  observedGeneration: 3
+  updatedReplicas: 1
+  availableReplicas: 1
+  conditions:
+    - type: Progressing
+      status: "True"
+      reason: ReplicaSetUpdated
+"#);
+
+        assert!(matches!(evaluate_deployment(&object), Health::Progressing(_)));
+
+        let object = get_object(r#"
+apiVersion: apps/v1
+kind: Deployment
+metadata:
+  name: app
+  generation: 3
+spec:
+  replicas: 2
+status:
+  observedGeneration: 2
+"#);
+
+        assert!(matches!(evaluate_deployment(&object), Health::Progressing(_)));
+    }
+
+    #[test]
+    fn expect_statefulset_rollout_to_be_progressing() {
+        let object = get_object(r#"
+apiVersion: apps/v1
+kind: StatefulSet
+metadata:
+  name: db
+  generation: 1
+spec:
+  replicas: 1
+status:
+  observedGeneration: 1
+  readyReplicas: 1
+  currentRevision: db-1
+  updateRevision: db-2
+"#);
+
+        assert!(matches!(evaluate_statefulset(&object), Health::Progressing(_)));
+    }
+
+    #[test]
+    fn expect_statefulset_to_be_degraded() {
+        let object = get_object(r#"
+apiVersion: apps/v1
+kind: StatefulSet
+metadata:
+  name: db
+  generation: 1
+spec:
+  replicas: 2
+status:
+  observedGeneration: 1
+  currentReplicas: 2
+  readyReplicas: 1
+  currentRevision: db-2
+  updateRevision: db-2
+"#);
+
+        assert!(matches!(evaluate_statefulset(&object), Health::Degraded(_)));
+    }
+
+    #[test]
+    fn expect_to_evaluate_readiness_condition() {
+        let object = get_object(r#"
+apiVersion: cert-manager.io/v1
+kind: Certificate
+metadata:
+  name: tls
+status:
+  conditions:
+    - type: Ready
+      status: "True"
+    - type: Issuing
+      status: "False"
+"#);
+
+        assert_eq!(evaluate_condition(&object, "Ready"), Health::Healthy);
+        assert!(matches!(evaluate_condition(&object, "Issuing"), Health::Degraded(_)));
+        assert!(matches!(evaluate_condition(&object, "Foo"), Health::Progressing(_)));
+    }
+}
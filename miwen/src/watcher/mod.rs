@@ -1,22 +1,104 @@
-use kube::{
-    Api,
-    Client,
-    api::ListParams
-};
-use kube::runtime::{
-    watcher,
-    watcher::Event
-};
+use kube::Client;
+use kube::runtime::watcher::Event;
 use gen::crd::{
     Decryptor,
     status::{SyncStatus, DecryptorStatus}
 };
-use futures::{TryStreamExt, StreamExt};
 use crate::err::Error;
 use crate::state;
+use crate::destination::{self, Cache};
+use crate::policy::SharedPolicy;
+use crate::scope::{WatchScope, ScopedWatcher};
+use crate::dependency;
+use crate::backoff;
+use crate::revision;
 use crate::client::{server, crd};
 
 pub mod apply;
+pub mod wave;
+pub mod health;
+pub mod tracking;
+pub mod output;
+pub mod substitute;
+pub mod recorder;
+pub mod snapshot;
+
+/// ApplyContext holds the client used to manage the objects of a Decryptor alongside the config of the apply.
+/// The client impersonates the ServiceAccount of the Decryptor if specified
+pub struct ApplyContext {
+    pub client: Client,
+    pub config: apply::ApplyConfig
+}
+
+/// Get the context used to manage the objects of the Decryptor on its destination. The same context is used
+/// to apply the objects and to read the objects of the health checks in order to enforce the same permissions
+/// 
+/// # Arguments
+/// * `decryptor` - &Decryptor
+/// * `hash` - &str
+/// * `client` - &Client
+/// * `ns` - &str
+/// * `cache` - &Cache
+/// * `policy` - &SharedPolicy
+pub async fn get_apply_context(
+    decryptor: &Decryptor,
+    hash: &str,
+    client: &Client,
+    ns: &str,
+    cache: &Cache,
+    policy: &SharedPolicy
+) -> Result<ApplyContext, Error> {
+    let dest = destination::get_destination(decryptor, client, ns, cache, policy).await?;
+    let apply_client = destination::get_apply_client(
+        &dest,
+        decryptor.spec.service_account_name.as_deref(),
+        ns,
+        cache,
+        policy
+    ).await?;
+
+    Ok(ApplyContext {
+        client: apply_client,
+        config: apply::ApplyConfig::new(decryptor, hash, dest.discovery.clone(), policy)?
+    })
+}
+
+/// Apply the decrypted file on the destination of the Decryptor. The decrypted file is rendered into
+/// manifests and their variables are substituted before they're applied. The objects are applied with the ServiceAccount
+/// of the Decryptor if specified. The context of the apply is returned in order to check the health
+/// of the applied resources alongside the objects which have been replaced
+/// 
+/// The live state of the objects is snapshotted in an encrypted Secret of the controller namespace. It's restored
+/// if any object of the file fails to apply
+/// 
+/// # Arguments
+/// * `decryptor` - &Decryptor
+/// * `tmpl` - String
+/// * `hash` - &str
+/// * `client` - &Client
+/// * `ns` - &str
+/// * `cache` - &Cache
+/// * `policy` - &SharedPolicy
+pub async fn apply_decrypted_file(
+    decryptor: &Decryptor,
+    tmpl: String,
+    hash: &str,
+    client: &Client,
+    ns: &str,
+    cache: &Cache,
+    policy: &SharedPolicy
+) -> Result<(ApplyContext, Vec<String>), Error> {
+    let ctx = get_apply_context(decryptor, hash, client, ns, cache, policy).await?;
+    let tmpl = output::render(tmpl, &ctx.config)?;
+    let tmpl = substitute::render(tmpl, client, ns, &ctx.config).await?;
+    let store = snapshot::Store::new(decryptor, client).await?;
+    let replaced = apply::apply_rendered_object(tmpl, &ctx.client, ns, &ctx.config, Some(store)).await?;
+    if let Err(err) = recorder::publish_replaced_objects(decryptor, client, &replaced).await {
+        error!("Unable to publish the replacement events: {err}");
+    }
+
+    Ok((ctx, replaced))
+}
 
 /// Parse the decryptor struct which we're going to use to add the Status structure
 /// 
@@ -24,61 +106,102 @@ pub mod apply;
 /// * `mut decryptor` - Decryptor
 /// * `client` - Client
 /// * `state` - State 
-async fn parse_update_of_crd(mut decryptor: Decryptor, client: Client, state: state::State) -> Result<(), Error> {
+/// * `locks` - Locks
+/// * `cache` - Cache
+/// * `policy` - SharedPolicy
+async fn parse_update_of_crd(
+    mut decryptor: Decryptor,
+    client: Client,
+    state: state::State,
+    locks: state::Locks,
+    cache: Cache,
+    policy: SharedPolicy
+) -> Result<(), Error> {
     let (name, generation_id, ns) = decryptor.get_metadata_info()?;
-    info!("ℹ️ Change has been detected on {name}");
-
-    // If the resource is not registered in the state, then this mean that the repository
-    // might not be pulled. In that case we call the rpc server to pull the repository
-    if !state::is_registered(state.clone(), &name)? {
-        // proceed to call the grpc api to pull the repo
-        server::dispatch_clone_repository(&decryptor.spec, &client, &ns).await?;
-    }
+    let key = state::get_key(&ns, &name);
+    info!("ℹ️ Change has been detected on {key}");
 
     // In order to not create an infinite loop of update...
     // we're checking the generation_id
-    let generation_exist = state::upsert_state(state, &name, generation_id)?;
+    let entry = state::Entry {
+        uid: decryptor.metadata.uid.to_owned(),
+        generation: generation_id
+    };
+    let generation_exist = state::upsert_state(state, &key, entry)?;
     if generation_exist {
-        info!("no need to update the status for decryptor {name}");
+        info!("no need to update the status for decryptor {key}");
+        return Ok(())
+    }
+
+    // The repository may have changed (url, credentials). krapao clone the repository if it's new
+    // or update the credentials of an existing repository. The generation is already stored in the state
+    // so the failure is recorded in the status in order for the sync process to retry it
+    if let Err(err) = server::dispatch_clone_repository(&decryptor, &client, &ns).await {
+        let status = backoff::get_failed_status(&decryptor, &err, None, &ns).await;
+        revision::update_status(&mut decryptor, status, &client).await?;
+
         return Ok(())
     }
 
+    // The Decryptor is held until its dependencies are Ready. It's applied afterward by the sync process
+    if let Some(condition) = dependency::check_dependencies(&decryptor, &client).await? {
+        return dependency::hold(&mut decryptor, condition).await;
+    }
+
+    // If a rollback is specified, then we're going to render the file at the targeted revision
+    let revision = match decryptor.get_rollback_revision().await {
+        Ok(res) => res,
+        Err(err) => {
+            let status = backoff::get_failed_status(&decryptor, &Error::from(err), None, &ns).await;
+            revision::update_status(&mut decryptor, status, &client).await?;
+
+            return Ok(())
+        }
+    };
+
     // Call the rpc server to get the decrypted k8s file to apply
-    let (tmpl, hash) = match crd::get_decrypted_kubernetes_object(&decryptor.spec, &ns).await {
+    let (tmpl, hash, commit) = match crd::get_decrypted_kubernetes_object(&decryptor, &client, &ns, revision).await {
         Ok(res) => res,
         Err(err) => {
             // Update the status of the current decryptor
-            decryptor.set_status(DecryptorStatus::new(
-                SyncStatus::NotSync, 
-                Some(err.to_string()), 
-                None, 
-            ));
-            decryptor.update_status().await?;
+            let status = backoff::get_failed_status(&decryptor, &err, None, &ns).await;
+            revision::update_status(&mut decryptor, status, &client).await?;
 
             return Ok(())
         }
     };
 
-    let apply_res = apply::apply_rendered_object(tmpl, &client, &ns).await;
+    // the sync process may apply the Decryptor concurrently
+    let lock = state::get_lock(&locks, decryptor.metadata.uid.as_deref().unwrap_or(&key))?;
+    let _guard = lock.lock().await;
+    let apply_res = apply_decrypted_file(&decryptor, tmpl, &hash, &client, &ns, &cache, &policy).await;
     // if an error happened while applying the rendered object. Then set an error to the crd
-    if let Err(err) = apply_res {
-        decryptor.set_status(DecryptorStatus::new(
-            SyncStatus::NotSync, 
-            Some(err.to_string()), 
-            Some(hash), 
-        ));
-        decryptor.update_status().await?;
+    let (ctx, replaced) = match apply_res {
+        Ok(res) => res,
+        Err(err) => {
+            let mut status = backoff::get_failed_status(&decryptor, &err, Some(hash), &ns).await;
+            status.set_commit(commit);
+            revision::update_status(&mut decryptor, status, &client).await?;
 
-        return Ok(())
-    }
+            return Ok(())
+        }
+    };
 
     // Otherwise update has been successsful so add a sync status
-    decryptor.set_status(DecryptorStatus::new(
+    let mut status = DecryptorStatus::new(
         SyncStatus::Sync, 
         None, 
         Some(hash), 
-    ));
-    decryptor.update_status().await?;
+    );
+
+    status.set_replaced_objects(replaced);
+    status.set_commit(commit);
+
+    if let Some(checks) = &decryptor.spec.health_checks {
+        status.set_condition(health::assess_health(checks, &ctx, &ns).await);
+    }
+
+    revision::update_status(&mut decryptor, status, &client).await?;
 
     Ok(())
 }
@@ -89,9 +212,10 @@ async fn parse_update_of_crd(mut decryptor: Decryptor, client: Client, state: st
 /// * `crd` - Decryptor
 /// * `state` - State
 fn deleted_crd(crd: Decryptor, state: state::State) -> Result<(), Error> {
-    let (name, _, _) = crd.get_metadata_info()?;
-    state::delete_item_in_state(state, &name)?;
-    info!("🗑️ {name} has been removed");
+    let (name, _, ns) = crd.get_metadata_info()?;
+    let key = state::get_key(&ns, &name);
+    state::delete_item_in_state(state, &key, crd.metadata.uid.as_deref())?;
+    info!("🗑️ {key} has been removed");
 
     Ok(())
 }
@@ -119,31 +243,55 @@ fn deleted_crd(crd: Decryptor, state: state::State) -> Result<(), Error> {
 /// 
 /// # Arguments
 /// * `state` - State
-pub async fn boostrap_watcher(state: state::State) -> Result<(), Error> {
+/// * `locks` - Locks
+/// * `cache` - Cache
+/// * `policy` - SharedPolicy
+/// * `scope` - WatchScope
+pub async fn boostrap_watcher(
+    state: state::State,
+    locks: state::Locks,
+    cache: Cache,
+    policy: SharedPolicy,
+    scope: WatchScope
+) -> Result<(), Error> {
     info!("Starting up the controller...");
     info!("Initializing client");
     let client = Client::try_default().await?;
     
-    // Watch the Decryptor ressources
-    let api: Api<Decryptor> = Api::all(client.clone());
-    let mut watcher = watcher(api, ListParams::default()).boxed();
+    // Watch the Decryptor ressources. A watcher is created per watched namespace
+    let mut watcher = ScopedWatcher::<Decryptor>::new(scope, &client).await?;
 
     // Event to listen for create / modified event on the Decryptor resources
     while let Some(event) = watcher.try_next().await? {
         let state = state.clone();
+        let locks = locks.clone();
         let client = client.clone();
+        let cache = cache.clone();
+        let policy = policy.clone();
 
         match event {
             Event::Applied(dec) => {
                 // spawn in a separate thread in order to process the update asynchronously
                 tokio::spawn( async move {
-                    let res = parse_update_of_crd( dec, client, state).await;
+                    let res = parse_update_of_crd(dec, client, state, locks, cache, policy).await;
                     if let Err(err) = res {
                         error!("{err}");
                     }
                 });
             },
             Event::Deleted(dec) => {
+                if let Some(uid) = dec.metadata.uid.to_owned() {
+                    if let Err(err) = state::remove_lock(&locks, &uid) {
+                        error!("{err}");
+                    }
+
+                    tokio::spawn(async move {
+                        if let Err(err) = snapshot::discard_snapshot(&client, &uid).await {
+                            error!("Unable to delete the snapshot of the Decryptor: {err}");
+                        }
+                    });
+                }
+
                 if let Err(err) = deleted_crd(dec, state) {
                     error!("{err}")
                 }
@@ -0,0 +1,257 @@
+// This mod is used to generate Kubernetes objects from decrypted files which are not Kubernetes manifests
+use std::collections::BTreeMap;
+use k8s_openapi::{
+    ByteString,
+    api::core::v1::Secret,
+    apimachinery::pkg::apis::meta::v1::ObjectMeta
+};
+use serde_json::Value;
+use gen::crd::output::{SecretOutput, DocumentFormat};
+use crate::err::Error;
+use super::apply::ApplyConfig;
+
+// Constant
+const JSON_PATH_ROOT: &str = "$";
+const DOTENV_COMMENT: char = '#';
+const DOTENV_EXPORT: &str = "export ";
+const DOTENV_SEPARATOR: char = '=';
+
+/// Parse a dotenv document into a flat JSON object
+///
+/// # Arguments
+/// * `doc` - &str
+fn parse_dotenv(doc: &str) -> Result<Value, Error> {
+    let mut object = serde_json::Map::new();
+    for line in doc.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with(DOTENV_COMMENT) {
+            continue;
+        }
+
+        let line = line.strip_prefix(DOTENV_EXPORT).unwrap_or(line);
+        let (key, value) = line.split_once(DOTENV_SEPARATOR)
+            .ok_or_else(|| Error::Output(format!("Invalid dotenv line for the key {}", line.split_whitespace().next().unwrap_or_default())))?;
+
+        let value = value.trim();
+        let value = value.strip_prefix('"').and_then(|v| v.strip_suffix('"'))
+            .or_else(|| value.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')))
+            .unwrap_or(value);
+
+        object.insert(key.trim().to_owned(), Value::String(value.to_owned()));
+    }
+
+    Ok(Value::Object(object))
+}
+
+/// Parse the decrypted document depending on its format
+///
+/// # Arguments
+/// * `doc` - &str
+/// * `format` - DocumentFormat
+fn parse_document(doc: &str, format: DocumentFormat) -> Result<Value, Error> {
+    let value = match format {
+        DocumentFormat::Dotenv => parse_dotenv(doc)?,
+        DocumentFormat::Json => serde_json::from_str(doc)
+            .map_err(|err| Error::Output(format!("Unable to parse the JSON document: {err}")))?,
+        DocumentFormat::Yaml => serde_yaml::from_str(doc)
+            .map_err(|err| Error::Output(format!("Unable to parse the YAML document: {err}")))?
+    };
+
+    if !value.is_object() {
+        return Err(Error::Output("The decrypted document is not a key / value document".to_owned()));
+    }
+
+    Ok(value)
+}
+
+/// Convert a JSON path (i.e: $.database.password or database.hosts.0) to a JSON pointer
+///
+/// # Arguments
+/// * `path` - &str
+fn get_pointer(path: &str) -> String {
+    let path = path.strip_prefix(JSON_PATH_ROOT).unwrap_or(path);
+
+    path.split('.')
+        .filter(|segment| !segment.is_empty())
+        .map(|segment| format!("/{}", segment.replace('~', "~0").replace('/', "~1")))
+        .collect()
+}
+
+/// Convert a value of the document to the value of a Secret key. Nested values are kept as JSON
+///
+/// # Arguments
+/// * `value` - &Value
+fn get_secret_value(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.to_owned(),
+        Value::Null => String::new(),
+        _ => value.to_string()
+    }
+}
+
+/// Get the key / value of the Secret from the decrypted document
+///
+/// # Arguments
+/// * `document` - &Value
+/// * `output` - &SecretOutput
+fn get_secret_data(document: &Value, output: &SecretOutput) -> Result<BTreeMap<String, ByteString>, Error> {
+    let mut data = BTreeMap::new();
+    match &output.mapping {
+        Some(mapping) => {
+            for item in mapping {
+                let value = document.pointer(&get_pointer(&item.path))
+                    .ok_or_else(|| Error::Output(format!("Path {} could not be founded in the decrypted document", item.path)))?;
+
+                data.insert(item.key.to_owned(), ByteString(get_secret_value(value).into_bytes()));
+            }
+        },
+        None => {
+            if let Some(object) = document.as_object() {
+                for (key, value) in object {
+                    data.insert(key.to_owned(), ByteString(get_secret_value(value).into_bytes()));
+                }
+            }
+        }
+    }
+
+    Ok(data)
+}
+
+/// Build a Secret manifest from the decrypted document
+///
+/// # Arguments
+/// * `doc` - &str
+/// * `output` - &SecretOutput
+/// * `file` - &str
+fn render_secret(doc: &str, output: &SecretOutput, file: &str) -> Result<String, Error> {
+    let document = parse_document(doc, output.get_format(file))?;
+    let secret = Secret {
+        metadata: ObjectMeta {
+            name: Some(output.name.to_owned()),
+            labels: output.labels.to_owned(),
+            ..Default::default()
+        },
+        type_: output.type_.to_owned(),
+        data: Some(get_secret_data(&document, output)?),
+        ..Default::default()
+    };
+
+    let manifest = serde_yaml::to_string(&secret)
+        .map_err(|err| Error::Output(err.to_string()))?;
+
+    Ok(manifest)
+}
+
+/// Render the decrypted file into the manifest to apply. The decrypted file is returned as it is
+/// unless an output has been specified on the Decryptor
+///
+/// # Arguments
+/// * `tmpl` - String
+/// * `config` - &ApplyConfig
+pub fn render(tmpl: String, config: &ApplyConfig) -> Result<String, Error> {
+    match &config.output {
+        Some(output) => render_secret(&tmpl, output, &config.tracking.file),
+        None => Ok(tmpl)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gen::crd::output::KeyMapping;
+    use super::super::apply::parse_rendered_objects;
+
+    #[test]
+    fn expect_to_parse_dotenv() {
+        let doc = r#"
+# database
+DB_USER=foo
+export DB_PASSWORD="bar=baz"
+API_KEY='key'
+"#;
+
+        let value = parse_dotenv(doc).unwrap();
+        assert_eq!(value["DB_USER"], "foo");
+        assert_eq!(value["DB_PASSWORD"], "bar=baz");
+        assert_eq!(value["API_KEY"], "key");
+    }
+
+    #[test]
+    fn expect_invalid_dotenv_to_return_err() {
+        let res = parse_dotenv("DB_USER");
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn expect_to_get_pointer() {
+        assert_eq!(get_pointer("$.database.password"), "/database/password");
+        assert_eq!(get_pointer("database.hosts.0"), "/database/hosts/0");
+    }
+
+    #[test]
+    fn expect_to_render_secret_from_yaml() {
+        let doc = r#"
+username: foo
+port: 5432
+"#;
+
+        let output = SecretOutput {
+            name: "app-secret".to_owned(),
+            type_: Some("Opaque".to_owned()),
+            ..Default::default()
+        };
+
+        let manifest = render_secret(doc, &output, "app.yaml").unwrap();
+        let objects = parse_rendered_objects(&manifest).unwrap();
+
+        assert_eq!(objects.len(), 1);
+        assert_eq!(objects[0].gvk.kind, "Secret");
+        assert_eq!(objects[0].name, "app-secret");
+        assert_eq!(objects[0].object.data["type"], "Opaque");
+        assert_eq!(objects[0].object.data["data"]["username"], "Zm9v");
+        assert_eq!(objects[0].object.data["data"]["port"], "NTQzMg==");
+    }
+
+    #[test]
+    fn expect_to_render_secret_with_mapping() {
+        let doc = r#"{"database": {"password": "bar"}, "other": "baz"}"#;
+        let output = SecretOutput {
+            name: "app-secret".to_owned(),
+            mapping: Some(vec![KeyMapping {
+                path: "$.database.password".to_owned(),
+                key: "DB_PASSWORD".to_owned()
+            }]),
+            ..Default::default()
+        };
+
+        let manifest = render_secret(doc, &output, "app.json").unwrap();
+        let objects = parse_rendered_objects(&manifest).unwrap();
+
+        let data = objects[0].object.data["data"].as_object().unwrap();
+        assert_eq!(data.len(), 1);
+        assert_eq!(data["DB_PASSWORD"], "YmFy");
+    }
+
+    #[test]
+    fn expect_missing_path_to_return_err() {
+        let output = SecretOutput {
+            name: "app-secret".to_owned(),
+            mapping: Some(vec![KeyMapping {
+                path: "$.missing".to_owned(),
+                key: "MISSING".to_owned()
+            }]),
+            ..Default::default()
+        };
+
+        let res = render_secret("foo: bar", &output, "app.yaml");
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn expect_to_keep_template_wo_output() {
+        let config = ApplyConfig::default();
+        let tmpl = render("foo".to_owned(), &config).unwrap();
+
+        assert_eq!(tmpl, "foo");
+    }
+}
@@ -0,0 +1,32 @@
+// This mod is used to publish Kubernetes Events on the Decryptor. Events are used to report
+// actions which are done on the applied objects (e.g: replacement of an object)
+use kube::{Client, Resource};
+use kube::runtime::events::{Event, EventType, Recorder};
+use gen::crd::Decryptor;
+use crate::err::Error;
+
+// Constant
+const REPORTER: &str = "miwen";
+const REPLACED_REASON: &str = "Replaced";
+const REPLACE_ACTION: &str = "Replace";
+
+/// Publish an Event on the Decryptor for each object which has been replaced
+///
+/// # Arguments
+/// * `decryptor` - &Decryptor
+/// * `client` - &Client
+/// * `replaced` - &[String]
+pub async fn publish_replaced_objects(decryptor: &Decryptor, client: &Client, replaced: &[String]) -> Result<(), Error> {
+    let recorder = Recorder::new(client.clone(), REPORTER.to_owned().into(), decryptor.object_ref(&()));
+    for object in replaced {
+        recorder.publish(Event {
+            type_: EventType::Normal,
+            reason: REPLACED_REASON.to_owned(),
+            note: Some(format!("{object} has been deleted and re-created as immutable fields have changed")),
+            action: REPLACE_ACTION.to_owned(),
+            secondary: None
+        }).await?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,543 @@
+// This mod is used to snapshot the live state of the objects before they're changed. When an object of the
+// rendered bundle fails to apply, the snapshot is restored in order to not leave the cluster half-updated
+use std::collections::BTreeMap;
+use kube::{
+    Api,
+    Client,
+    ResourceExt,
+    Error as KubeError,
+    core::DynamicObject,
+    api::{ListParams, Patch, PatchParams, PostParams, DeleteParams}
+};
+use k8s_openapi::{
+    ByteString,
+    api::core::v1::Secret,
+    apimachinery::pkg::apis::meta::v1::ObjectMeta
+};
+use serde::{Serialize, Deserialize};
+use aes_gcm::{
+    Aes256Gcm,
+    KeyInit,
+    Nonce,
+    aead::{Aead, AeadCore, OsRng, Payload}
+};
+use gen::crd::{Decryptor, ConflictPolicy};
+use crate::err::Error;
+use crate::destination::{self, Cache};
+use crate::policy::SharedPolicy;
+use crate::state::get_key;
+use super::apply::{self, ApplyConfig, RenderedObject};
+
+// Constant
+const SNAPSHOT_PREFIX: &str = "jiemi-snapshot";
+const SNAPSHOT_KEY: &str = "snapshot";
+const SNAPSHOT_LABEL: &str = "jiemi.cr/snapshot-of";
+const DECRYPTOR_ANNOTATION: &str = "jiemi.cr/decryptor";
+const ENCRYPTION_KEY_SECRET: &str = "jiemi-snapshot-key";
+const ENCRYPTION_KEY: &str = "key";
+const CONTROLLER_NAMESPACE_ENV: &str = "POD_NAMESPACE";
+const DEFAULT_CONTROLLER_NAMESPACE: &str = "jiemi";
+const FIELD_MANAGER: &str = "miwen";
+const NOT_FOUND_STATUS_CODE: u16 = 404;
+const CONFLICT_STATUS_CODE: u16 = 409;
+const NONCE_LENGTH: usize = 12;
+const STATUS_FIELD: &str = "status";
+
+/// Live state of an object before it's changed. The object is None when it did not exist
+struct Entry {
+    api: Api<DynamicObject>,
+    record: Record
+}
+
+/// Representation of an entry stored in the Secret of the snapshot
+#[derive(Serialize, Deserialize)]
+struct Record {
+    #[serde(rename = "apiVersion")]
+    api_version: String,
+    kind: String,
+    name: String,
+    namespace: Option<String>,
+    live: Option<DynamicObject>
+}
+
+/// Get the namespace where miwen is running. The snapshots are stored in this namespace
+fn get_controller_namespace() -> String {
+    std::env::var(CONTROLLER_NAMESPACE_ENV)
+        .unwrap_or_else(|_| DEFAULT_CONTROLLER_NAMESPACE.to_owned())
+}
+
+/// Encrypt the snapshot with the key of the controller. The uid of the Decryptor is authenticated alongside
+/// the snapshot. Hence the snapshot of a Decryptor can't be restored for another Decryptor
+///
+/// # Arguments
+/// * `key` - &[u8]
+/// * `uid` - &str
+/// * `plaintext` - &[u8]
+fn encrypt(key: &[u8], uid: &str, plaintext: &[u8]) -> Result<Vec<u8>, Error> {
+    let cipher = Aes256Gcm::new_from_slice(key)
+        .map_err(|err| Error::Snapshot(err.to_string()))?;
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = cipher.encrypt(&nonce, Payload { msg: plaintext, aad: uid.as_bytes() })
+        .map_err(|err| Error::Snapshot(err.to_string()))?;
+
+    Ok([nonce.as_slice(), &ciphertext].concat())
+}
+
+/// Decrypt the snapshot with the key of the controller
+///
+/// # Arguments
+/// * `key` - &[u8]
+/// * `uid` - &str
+/// * `data` - &[u8]
+fn decrypt(key: &[u8], uid: &str, data: &[u8]) -> Result<Vec<u8>, Error> {
+    if data.len() < NONCE_LENGTH {
+        return Err(Error::Snapshot("The snapshot is truncated".to_owned()));
+    }
+
+    let cipher = Aes256Gcm::new_from_slice(key)
+        .map_err(|err| Error::Snapshot(err.to_string()))?;
+    let (nonce, ciphertext) = data.split_at(NONCE_LENGTH);
+
+    cipher.decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: uid.as_bytes() })
+        .map_err(|_| Error::Snapshot("Unable to decrypt the snapshot".to_owned()))
+}
+
+/// Get the key used to encrypt the snapshots. The key is generated and stored in a Secret of the
+/// controller namespace the first time it's used
+///
+/// # Arguments
+/// * `api` - &Api<Secret>
+async fn get_encryption_key(api: &Api<Secret>) -> Result<Vec<u8>, Error> {
+    let secret = match api.get(ENCRYPTION_KEY_SECRET).await {
+        Ok(secret) => secret,
+        Err(KubeError::Api(err)) if err.code == NOT_FOUND_STATUS_CODE => {
+            info!("🔑 Generating the key used to encrypt the snapshots");
+            let secret = Secret {
+                metadata: ObjectMeta {
+                    name: Some(ENCRYPTION_KEY_SECRET.to_owned()),
+                    ..Default::default()
+                },
+                data: Some(BTreeMap::from([(ENCRYPTION_KEY.to_owned(), ByteString(Aes256Gcm::generate_key(&mut OsRng).to_vec()))])),
+                ..Default::default()
+            };
+
+            match api.create(&PostParams::default(), &secret).await {
+                Ok(secret) => secret,
+                // the key may have been created concurrently
+                Err(KubeError::Api(err)) if err.code == CONFLICT_STATUS_CODE => api.get(ENCRYPTION_KEY_SECRET).await?,
+                Err(err) => return Err(Error::from(err))
+            }
+        },
+        Err(err) => return Err(Error::from(err))
+    };
+
+    secret.data
+        .and_then(|mut data| data.remove(ENCRYPTION_KEY))
+        .map(|key| key.0)
+        .ok_or_else(|| Error::Snapshot(format!("Key {ENCRYPTION_KEY} could not be founded in the Secret {ENCRYPTION_KEY_SECRET}")))
+}
+
+/// Store persists the snapshot encrypted in a Secret of the controller namespace. The Secret is named
+/// after the uid of the Decryptor and is deleted once the snapshot is discarded
+pub struct Store {
+    api: Api<Secret>,
+    name: String,
+    uid: String,
+    decryptor: String,
+    key: Vec<u8>
+}
+
+impl Store {
+    /// Create a new Store for the Decryptor
+    ///
+    /// # Arguments
+    /// * `decryptor` - &Decryptor
+    /// * `client` - &Client
+    pub async fn new(decryptor: &Decryptor, client: &Client) -> Result<Self, Error> {
+        let (name, _, ns) = decryptor.get_metadata_info()?;
+        let uid = decryptor.metadata.uid.to_owned()
+            .ok_or_else(|| Error::Generator("Decryptor does not have an uid".to_owned()))?;
+
+        let api = Api::namespaced(client.clone(), &get_controller_namespace());
+        let key = get_encryption_key(&api).await?;
+
+        Ok(Store {
+            api,
+            name: get_snapshot_name(&uid),
+            uid,
+            decryptor: get_key(&ns, &name),
+            key
+        })
+    }
+
+    /// Check that the Secret of the snapshot is not used by anything else before it's written
+    ///
+    /// # Arguments
+    /// * `&self` - &Self
+    async fn check_ownership(&self) -> Result<(), Error> {
+        let secret = match self.api.get(&self.name).await {
+            Ok(secret) => secret,
+            Err(KubeError::Api(err)) if err.code == NOT_FOUND_STATUS_CODE => return Ok(()),
+            Err(err) => return Err(Error::from(err))
+        };
+
+        match secret.labels().get(SNAPSHOT_LABEL) {
+            Some(uid) if uid == &self.uid => Ok(()),
+            _ => Err(Error::Snapshot(format!("The Secret {} already exists and is not a snapshot of {}", self.name, self.decryptor)))
+        }
+    }
+
+    /// Save the entries of the snapshot in the Secret
+    ///
+    /// # Arguments
+    /// * `&self` - &Self
+    /// * `entries` - &[Entry]
+    async fn save(&self, entries: &[Entry]) -> Result<(), Error> {
+        self.check_ownership().await?;
+
+        let records: Vec<&Record> = entries.iter()
+            .map(|entry| &entry.record)
+            .collect();
+
+        let data = encrypt(&self.key, &self.uid, &serde_json::to_vec(&records)?)?;
+        let secret = Secret {
+            metadata: ObjectMeta {
+                name: Some(self.name.to_owned()),
+                labels: Some(BTreeMap::from([(SNAPSHOT_LABEL.to_owned(), self.uid.to_owned())])),
+                annotations: Some(BTreeMap::from([(DECRYPTOR_ANNOTATION.to_owned(), self.decryptor.to_owned())])),
+                ..Default::default()
+            },
+            data: Some(BTreeMap::from([(SNAPSHOT_KEY.to_owned(), ByteString(data))])),
+            ..Default::default()
+        };
+
+        self.api.patch(&self.name, &PatchParams::apply(FIELD_MANAGER).force(), &Patch::Apply(&secret)).await?;
+
+        Ok(())
+    }
+
+    /// Load the entries saved in the Secret
+    ///
+    /// # Arguments
+    /// * `&self` - &Self
+    /// * `secret` - Secret
+    fn load(&self, secret: Secret) -> Result<Vec<Record>, Error> {
+        let data = secret.data
+            .and_then(|mut data| data.remove(SNAPSHOT_KEY))
+            .ok_or_else(|| Error::Snapshot(format!("The Secret {} does not contain a snapshot", self.name)))?;
+
+        let records = serde_json::from_slice(&decrypt(&self.key, &self.uid, &data.0)?)?;
+
+        Ok(records)
+    }
+
+    /// Delete the Secret of the snapshot
+    ///
+    /// # Arguments
+    /// * `&self` - &Self
+    async fn discard(&self) -> Result<(), Error> {
+        delete_snapshot(&self.api, &self.name).await
+    }
+}
+
+/// Delete the Secret of a snapshot
+///
+/// # Arguments
+/// * `api` - &Api<Secret>
+/// * `name` - &str
+async fn delete_snapshot(api: &Api<Secret>, name: &str) -> Result<(), Error> {
+    match api.delete(name, &DeleteParams::default()).await {
+        Ok(_) => Ok(()),
+        Err(KubeError::Api(err)) if err.code == NOT_FOUND_STATUS_CODE => Ok(()),
+        Err(err) => Err(Error::from(err))
+    }
+}
+
+/// Snapshot of the objects changed by an apply. Objects are captured right before they're changed
+/// as the kind of an object may only be known once a previous wave has been applied (e.g: CustomResourceDefinition)
+pub struct Snapshot {
+    store: Option<Store>,
+    entries: Vec<Entry>
+}
+
+/// Get the name of the Secret which contains the snapshot of a Decryptor
+///
+/// # Arguments
+/// * `uid` - &str
+fn get_snapshot_name(uid: &str) -> String {
+    format!("{SNAPSHOT_PREFIX}-{uid}")
+}
+
+/// Remove the fields set by the api server from the live object in order to apply it again
+///
+/// # Arguments
+/// * `object` - DynamicObject
+fn clean_object(mut object: DynamicObject) -> DynamicObject {
+    let meta = &mut object.metadata;
+    meta.resource_version = None;
+    meta.uid = None;
+    meta.managed_fields = None;
+    meta.creation_timestamp = None;
+    meta.deletion_timestamp = None;
+    meta.generation = None;
+    meta.self_link = None;
+
+    if let Some(data) = object.data.as_object_mut() {
+        data.remove(STATUS_FIELD);
+    }
+
+    object
+}
+
+impl Snapshot {
+    /// Create a new empty Snapshot. The snapshot is only kept in memory when there is no store
+    ///
+    /// # Arguments
+    /// * `store` - Option<Store>
+    pub fn new(store: Option<Store>) -> Self {
+        Snapshot {
+            store,
+            entries: Vec::new()
+        }
+    }
+
+    /// Capture the live state of an object which is about to be changed. The snapshot is saved
+    /// before the object is applied
+    ///
+    /// # Arguments
+    /// * `&mut self` - &mut Self
+    /// * `rendered` - &RenderedObject
+    /// * `client` - &Client
+    /// * `ns` - &str
+    /// * `config` - &ApplyConfig
+    pub async fn capture(&mut self, rendered: &RenderedObject, client: &Client, ns: &str, config: &ApplyConfig) -> Result<(), Error> {
+        let (api, namespace) = apply::get_object_api(rendered, client, ns, config).await?;
+        let live = match api.get(&rendered.name).await {
+            Ok(object) => Some(clean_object(object)),
+            Err(KubeError::Api(err)) if err.code == NOT_FOUND_STATUS_CODE => None,
+            Err(err) => return Err(Error::from(err))
+        };
+
+        self.entries.push(Entry {
+            api,
+            record: Record {
+                api_version: rendered.gvk.api_version(),
+                kind: rendered.gvk.kind.to_owned(),
+                name: rendered.name.to_owned(),
+                namespace,
+                live
+            }
+        });
+
+        if let Some(store) = &self.store {
+            store.save(&self.entries).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Restore the captured objects in the reverse order of the apply
+    ///     - Objects which existed are applied with their previous state
+    ///     - Objects which have been created are deleted
+    ///
+    /// # Arguments
+    /// * `&self` - &Self
+    /// * `config` - &ApplyConfig
+    pub async fn restore(&self, config: &ApplyConfig) -> Result<(), Error> {
+        let config = ApplyConfig {
+            conflict_policy: ConflictPolicy::Force,
+            ..config.clone()
+        };
+
+        let mut failures = Vec::new();
+        for Entry { api, record } in self.entries.iter().rev() {
+            info!("⏪ Restoring {} {}", record.kind, record.name);
+            let res = match &record.live {
+                Some(object) => apply::restore_object(api.clone(), &record.name, object.clone(), &config).await,
+                None => match api.delete(&record.name, &DeleteParams::default()).await {
+                    Ok(_) => Ok(()),
+                    Err(KubeError::Api(err)) if err.code == NOT_FOUND_STATUS_CODE => Ok(()),
+                    Err(err) => Err(Error::from(err))
+                }
+            };
+
+            if let Err(err) = res {
+                failures.push(format!("{} {}: {err}", record.kind, record.name));
+            }
+        }
+
+        if !failures.is_empty() {
+            return Err(Error::Apply(format!("Unable to restore {}", failures.join(", "))));
+        }
+
+        Ok(())
+    }
+
+    /// Discard the snapshot once every objects have been applied
+    ///
+    /// # Arguments
+    /// * `&self` - &Self
+    pub async fn discard(&self) -> Result<(), Error> {
+        match &self.store {
+            Some(store) => store.discard().await,
+            None => Ok(())
+        }
+    }
+}
+
+/// Restore the snapshot left by an apply which has been interrupted (e.g: miwen restarted) or whose restore failed.
+/// The snapshot of a Decryptor which no longer exists is deleted
+///
+/// # Arguments
+/// * `secret` - Secret
+/// * `client` - &Client
+/// * `cache` - &Cache
+/// * `policy` - &SharedPolicy
+async fn recover_snapshot(secret: Secret, client: &Client, cache: &Cache, policy: &SharedPolicy) -> Result<(), Error> {
+    let name = secret.name();
+    let uid = secret.labels().get(SNAPSHOT_LABEL).cloned().unwrap_or_default();
+    let decryptor = secret.annotations().get(DECRYPTOR_ANNOTATION)
+        .and_then(|key| key.split_once('/'))
+        .map(|(ns, name)| (ns.to_owned(), name.to_owned()));
+
+    let decryptor = match decryptor {
+        Some((ns, name)) => match Api::<Decryptor>::namespaced(client.clone(), &ns).get(&name).await {
+            Ok(decryptor) if decryptor.metadata.uid.as_deref() == Some(uid.as_str()) => Some(decryptor),
+            Ok(_) => None,
+            Err(KubeError::Api(err)) if err.code == NOT_FOUND_STATUS_CODE => None,
+            Err(err) => return Err(Error::from(err))
+        },
+        None => None
+    };
+
+    let decryptor = match decryptor {
+        Some(decryptor) => decryptor,
+        None => {
+            info!("🗑️ Deleting the snapshot {name} as its Decryptor no longer exists");
+            return delete_snapshot(&Api::namespaced(client.clone(), &get_controller_namespace()), &name).await;
+        }
+    };
+
+    let (_, _, ns) = decryptor.get_metadata_info()?;
+    let store = Store::new(&decryptor, client).await?;
+    let records = store.load(secret)?;
+
+    let dest = destination::get_destination(&decryptor, client, &ns, cache, policy).await?;
+    let apply_client = destination::get_apply_client(
+        &dest,
+        decryptor.spec.service_account_name.as_deref(),
+        &ns,
+        cache,
+        policy
+    ).await?;
+    let config = ApplyConfig::new(&decryptor, "", dest.discovery.clone(), policy)?;
+
+    let mut snapshot = Snapshot::new(Some(store));
+    for record in records {
+        let mut object = record.live.clone()
+            .unwrap_or_else(|| DynamicObject {
+                types: None,
+                metadata: ObjectMeta::default(),
+                data: serde_json::Value::Null
+            });
+        object.metadata.namespace = record.namespace.clone();
+
+        let rendered = RenderedObject {
+            gvk: apply::get_gvk(&record.api_version, &record.kind),
+            name: record.name.to_owned(),
+            object
+        };
+
+        let (api, _) = apply::get_object_api(&rendered, &apply_client, &ns, &config).await?;
+        snapshot.entries.push(Entry { api, record });
+    }
+
+    info!("⏪ Restoring the snapshot of {} left by an interrupted apply", get_key(&ns, &decryptor.name()));
+    snapshot.restore(&config).await?;
+    snapshot.discard().await
+}
+
+/// Restore the snapshots stored in the controller namespace. This is done when miwen starts as an apply may
+/// have been interrupted. A snapshot which fails to be restored is kept and restored again on the next start
+///
+/// # Arguments
+/// * `cache` - &Cache
+/// * `policy` - &SharedPolicy
+pub async fn recover_snapshots(cache: &Cache, policy: &SharedPolicy) -> Result<(), Error> {
+    let client = Client::try_default().await?;
+    let api: Api<Secret> = Api::namespaced(client.clone(), &get_controller_namespace());
+    for secret in api.list(&ListParams::default().labels(SNAPSHOT_LABEL)).await? {
+        let name = secret.name();
+        if let Err(err) = recover_snapshot(secret, &client, cache, policy).await {
+            error!("Unable to restore the snapshot {name}: {err}");
+        }
+    }
+
+    Ok(())
+}
+
+/// Delete the snapshot of a Decryptor which has been deleted
+///
+/// # Arguments
+/// * `client` - &Client
+/// * `uid` - &str
+pub async fn discard_snapshot(client: &Client, uid: &str) -> Result<(), Error> {
+    let api: Api<Secret> = Api::namespaced(client.clone(), &get_controller_namespace());
+    delete_snapshot(&api, &get_snapshot_name(uid)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expect_to_clean_live_object() {
+        let object: DynamicObject = serde_json::from_value(serde_json::json!({
+            "apiVersion": "apps/v1",
+            "kind": "Deployment",
+            "metadata": {
+                "name": "foo",
+                "namespace": "default",
+                "uid": "a",
+                "resourceVersion": "42",
+                "generation": 3,
+                "creationTimestamp": "2022-01-01T00:00:00Z",
+                "managedFields": [{ "manager": "miwen" }],
+                "labels": { "app": "foo" }
+            },
+            "spec": { "replicas": 1 },
+            "status": { "readyReplicas": 1 }
+        })).unwrap();
+
+        let object = clean_object(object);
+        assert!(object.metadata.uid.is_none());
+        assert!(object.metadata.resource_version.is_none());
+        assert!(object.metadata.generation.is_none());
+        assert!(object.metadata.creation_timestamp.is_none());
+        assert!(object.metadata.managed_fields.is_none());
+        assert_eq!(object.metadata.namespace.unwrap(), "default");
+        assert_eq!(object.metadata.labels.unwrap().get("app").unwrap(), "foo");
+        assert_eq!(object.data["spec"]["replicas"], 1);
+        assert!(object.data.get("status").is_none());
+    }
+
+    #[test]
+    fn expect_to_get_snapshot_name() {
+        assert_eq!(get_snapshot_name("5c3f1a2b"), "jiemi-snapshot-5c3f1a2b");
+    }
+
+    #[test]
+    fn expect_snapshot_to_be_encrypted() {
+        let key = Aes256Gcm::generate_key(&mut OsRng);
+        let plaintext = br#"[{"kind":"Secret","live":{"data":{"password":"Zm9v"}}}]"#;
+
+        let data = encrypt(&key, "uid-a", plaintext).unwrap();
+        assert!(!data.windows(8).any(|window| window == b"password"));
+        assert_eq!(decrypt(&key, "uid-a", &data).unwrap(), plaintext);
+
+        // the snapshot can't be restored for another Decryptor nor with another key
+        assert!(decrypt(&key, "uid-b", &data).is_err());
+        assert!(decrypt(&Aes256Gcm::generate_key(&mut OsRng), "uid-a", &data).is_err());
+        assert!(decrypt(&key, "uid-a", &data[..4]).is_err());
+    }
+}
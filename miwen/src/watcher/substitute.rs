@@ -0,0 +1,219 @@
+// This mod is used to substitute the variables of the decrypted file before it's applied. Variables are
+// substituted in the string values of the parsed documents in order to not change the structure of the documents
+use std::collections::BTreeMap;
+use kube::Client;
+use serde::Deserialize;
+use serde_yaml::Value;
+use crate::err::Error;
+use super::apply::ApplyConfig;
+
+// Constant
+const VARIABLE_START: &str = "${";
+const VARIABLE_END: char = '}';
+const ESCAPED_VARIABLE_START: &str = "$${";
+
+/// Check whenever the name is a valid variable name ([A-Za-z_][A-Za-z0-9_]*)
+///
+/// # Arguments
+/// * `name` - &str
+fn is_variable_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => chars.all(|c| c.is_ascii_alphanumeric() || c == '_'),
+        _ => false
+    }
+}
+
+/// Replace the ${VAR} of the string with the variables. $${VAR} is escaped and kept as ${VAR}.
+/// Expressions which are not a valid variable name (i.e: ${foo:-bar}) are kept as it is.
+/// Undefined variables are added to the undefined list
+///
+/// # Arguments
+/// * `tmpl` - &str
+/// * `variables` - &BTreeMap<String, String>
+/// * `undefined` - &mut Vec<String>
+fn substitute_str(tmpl: &str, variables: &BTreeMap<String, String>, undefined: &mut Vec<String>) -> String {
+    let mut res = String::with_capacity(tmpl.len());
+    let mut rest = tmpl;
+
+    while let Some(idx) = rest.find(VARIABLE_START) {
+        // handle the escaped variable
+        if rest[..idx].ends_with('$') && rest[idx - 1..].starts_with(ESCAPED_VARIABLE_START) {
+            res.push_str(&rest[..idx - 1]);
+            res.push_str(VARIABLE_START);
+            rest = &rest[idx + VARIABLE_START.len()..];
+            continue;
+        }
+
+        res.push_str(&rest[..idx]);
+        let expr = &rest[idx + VARIABLE_START.len()..];
+        let name = expr.find(VARIABLE_END)
+            .map(|end| &expr[..end])
+            .filter(|name| is_variable_name(name));
+
+        match name {
+            Some(name) => {
+                match variables.get(name) {
+                    Some(value) => res.push_str(value),
+                    None => undefined.push(name.to_owned())
+                }
+                rest = &expr[name.len() + 1..];
+            },
+            None => {
+                res.push_str(VARIABLE_START);
+                rest = expr;
+            }
+        }
+    }
+
+    res.push_str(rest);
+    res
+}
+
+/// Substitute the variables of every string values of the document. Keys are kept as it is
+///
+/// # Arguments
+/// * `value` - &mut Value
+/// * `variables` - &BTreeMap<String, String>
+/// * `undefined` - &mut Vec<String>
+fn substitute_value(value: &mut Value, variables: &BTreeMap<String, String>, undefined: &mut Vec<String>) {
+    match value {
+        Value::String(content) => *content = substitute_str(content, variables, undefined),
+        Value::Sequence(items) => items.iter_mut()
+            .for_each(|item| substitute_value(item, variables, undefined)),
+        Value::Mapping(mapping) => mapping.iter_mut()
+            .for_each(|(_, item)| substitute_value(item, variables, undefined)),
+        _ => {}
+    }
+}
+
+/// Replace the ${VAR} of the YAML documents of the template with the variables. The documents are parsed
+/// and only the string values are substituted. A value containing YAML characters (i.e: ':', '#', newline)
+/// is therefore kept as a single value. The documents are serialized back once substituted
+///
+/// # Arguments
+/// * `tmpl` - &str
+/// * `variables` - &BTreeMap<String, String>
+pub fn substitute(tmpl: &str, variables: &BTreeMap<String, String>) -> Result<String, Error> {
+    let mut res = String::with_capacity(tmpl.len());
+    let mut undefined = Vec::new();
+
+    for document in serde_yaml::Deserializer::from_str(tmpl) {
+        let mut value = Value::deserialize(document)?;
+        if value.is_null() {
+            continue;
+        }
+
+        substitute_value(&mut value, variables, &mut undefined);
+        res.push_str(&serde_yaml::to_string(&value)?);
+    }
+
+    if !undefined.is_empty() {
+        undefined.sort();
+        undefined.dedup();
+        return Err(Error::Substitute(format!("undefined variables {}", undefined.join(", "))));
+    }
+
+    Ok(res)
+}
+
+/// Substitute the variables of the rendered manifests when the Decryptor specify a postRender.
+/// Variables are always loaded from the cluster where jiemi is running
+///
+/// # Arguments
+/// * `tmpl` - String
+/// * `client` - &Client
+/// * `ns` - &str
+/// * `config` - &ApplyConfig
+pub async fn render(tmpl: String, client: &Client, ns: &str, config: &ApplyConfig) -> Result<String, Error> {
+    match &config.substitute {
+        Some(sub) => {
+            let variables = sub.get_variables(client, ns).await?;
+            substitute(&tmpl, &variables)
+        },
+        None => Ok(tmpl)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_variables() -> BTreeMap<String, String> {
+        BTreeMap::from([
+            ("NAMESPACE".to_owned(), "prod".to_owned()),
+            ("CLUSTER_NAME".to_owned(), "eu-west".to_owned())
+        ])
+    }
+
+    #[test]
+    fn expect_to_substitute_variables() {
+        let tmpl = r#"
+metadata:
+  name: app-${CLUSTER_NAME}
+  namespace: ${NAMESPACE}
+"#;
+
+        let res = substitute(tmpl, &get_variables()).unwrap();
+        assert!(res.contains("name: app-eu-west"));
+        assert!(res.contains("namespace: prod"));
+    }
+
+    #[test]
+    fn expect_to_keep_escaped_and_invalid_expressions() {
+        let tmpl = "foo: $${NAMESPACE} ${bar:-baz} ${NAMESPACE}";
+
+        let mut undefined = Vec::new();
+        let res = substitute_str(tmpl, &get_variables(), &mut undefined);
+        assert_eq!(res, "foo: ${NAMESPACE} ${bar:-baz} prod");
+        assert!(undefined.is_empty());
+    }
+
+    #[test]
+    fn expect_value_to_not_change_the_structure_of_the_document() {
+        let tmpl = r#"
+apiVersion: v1
+kind: ConfigMap
+metadata:
+  name: foo
+data:
+  url: ${URL}
+  other: bar
+"#;
+        let variables = BTreeMap::from([
+            ("URL".to_owned(), "https://foo # bar\nother: baz".to_owned())
+        ]);
+
+        let res = substitute(tmpl, &variables).unwrap();
+        let value: Value = serde_yaml::from_str(&res).unwrap();
+        assert_eq!(value["data"]["url"], Value::String("https://foo # bar\nother: baz".to_owned()));
+        assert_eq!(value["data"]["other"], Value::String("bar".to_owned()));
+    }
+
+    #[test]
+    fn expect_to_substitute_multiple_documents() {
+        let tmpl = r#"
+---
+metadata:
+  namespace: ${NAMESPACE}
+---
+metadata:
+  namespace: ${NAMESPACE}
+---
+"#;
+
+        let res = substitute(tmpl, &get_variables()).unwrap();
+        assert_eq!(res.matches("namespace: prod").count(), 2);
+    }
+
+    #[test]
+    fn expect_undefined_variables_to_return_err() {
+        let tmpl = "foo: ${MISSING} ${NAMESPACE} ${OTHER}";
+
+        let res = substitute(tmpl, &get_variables());
+        assert!(res.is_err());
+
+        let msg = res.unwrap_err().to_string();
+        assert!(msg.contains("MISSING, OTHER"));
+    }
+}
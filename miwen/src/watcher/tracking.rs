@@ -0,0 +1,121 @@
+// This mod is used to inject tracking labels and annotations on every applied objects.
+// These allow to link an object back to the Decryptor, the revision and the source file
+use std::collections::BTreeMap;
+use kube::core::DynamicObject;
+use gen::crd::Decryptor;
+use gen::util::get_label_value;
+use crate::err::Error;
+
+// Constant
+pub const MANAGED_BY_LABEL: &str = "app.kubernetes.io/managed-by";
+pub const MANAGED_BY_VALUE: &str = "jiemi";
+pub const DECRYPTOR_NAME_LABEL: &str = "jiemi.cr/decryptor-name";
+pub const DECRYPTOR_NAMESPACE_LABEL: &str = "jiemi.cr/decryptor-namespace";
+pub const DECRYPTOR_NAME_ANNOTATION: &str = "jiemi.cr/decryptor";
+pub const SOURCE_FILE_ANNOTATION: &str = "jiemi.cr/source-file";
+pub const REVISION_ANNOTATION: &str = "jiemi.cr/revision";
+
+#[derive(Debug, Clone, Default)]
+pub struct Tracking {
+    pub name: String,
+    pub namespace: String,
+    pub file: String,
+    pub revision: String
+}
+
+impl Tracking {
+    /// Create a new Tracking from the Decryptor and the revision which is applied
+    /// 
+    /// # Arguments
+    /// * `decryptor` - &Decryptor
+    /// * `revision` - &str
+    pub fn new(decryptor: &Decryptor, revision: &str) -> Result<Self, Error> {
+        let (name, _, namespace) = decryptor.get_metadata_info()?;
+
+        Ok(Tracking {
+            name,
+            namespace,
+            file: decryptor.spec.source.file_to_decrypt.to_owned(),
+            revision: revision.trim().to_owned()
+        })
+    }
+
+    /// Inject the tracking labels and annotations in the object. Existing labels and annotations are kept.
+    /// The name of the Decryptor may exceed the length of a label value. The label contains a hashed value
+    /// in that case while the annotation always contains the name of the Decryptor
+    /// 
+    /// # Arguments
+    /// * `&self` - &Self
+    /// * `object` - &mut DynamicObject
+    pub fn inject(&self, object: &mut DynamicObject) {
+        let labels = object.metadata.labels.get_or_insert_with(BTreeMap::new);
+        labels.insert(MANAGED_BY_LABEL.to_owned(), MANAGED_BY_VALUE.to_owned());
+        labels.insert(DECRYPTOR_NAME_LABEL.to_owned(), get_label_value(&self.name));
+        labels.insert(DECRYPTOR_NAMESPACE_LABEL.to_owned(), self.namespace.to_owned());
+
+        let annotations = object.metadata.annotations.get_or_insert_with(BTreeMap::new);
+        annotations.insert(DECRYPTOR_NAME_ANNOTATION.to_owned(), self.name.to_owned());
+        annotations.insert(SOURCE_FILE_ANNOTATION.to_owned(), self.file.to_owned());
+        annotations.insert(REVISION_ANNOTATION.to_owned(), self.revision.to_owned());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_tracking() -> Tracking {
+        Tracking {
+            name: "pgp-decryptor".to_owned(),
+            namespace: "default".to_owned(),
+            file: "pgp/secret.enc.yaml".to_owned(),
+            revision: "a888f02e1111beb2c543d729faa5d516ecaa9e12".to_owned()
+        }
+    }
+
+    #[test]
+    fn expect_to_inject_tracking_metadata() {
+        let mut object: DynamicObject = serde_yaml::from_str(r#"
+apiVersion: v1
+kind: Secret
+metadata:
+  name: foo
+  labels:
+    app: foo
+"#).unwrap();
+
+        get_tracking().inject(&mut object);
+
+        let labels = object.metadata.labels.unwrap();
+        assert_eq!(labels.get("app").unwrap(), "foo");
+        assert_eq!(labels.get(MANAGED_BY_LABEL).unwrap(), "jiemi");
+        assert_eq!(labels.get(DECRYPTOR_NAME_LABEL).unwrap(), "pgp-decryptor");
+        assert_eq!(labels.get(DECRYPTOR_NAMESPACE_LABEL).unwrap(), "default");
+
+        let annotations = object.metadata.annotations.unwrap();
+        assert_eq!(annotations.get(SOURCE_FILE_ANNOTATION).unwrap(), "pgp/secret.enc.yaml");
+        assert_eq!(annotations.get(REVISION_ANNOTATION).unwrap(), "a888f02e1111beb2c543d729faa5d516ecaa9e12");
+    }
+
+    #[test]
+    fn expect_long_decryptor_name_to_fit_in_label() {
+        let mut tracking = get_tracking();
+        tracking.name = "a".repeat(200);
+
+        let mut object: DynamicObject = serde_yaml::from_str(r#"
+apiVersion: v1
+kind: Secret
+metadata:
+  name: foo
+"#).unwrap();
+
+        tracking.inject(&mut object);
+
+        let labels = object.metadata.labels.unwrap();
+        assert!(labels.get(DECRYPTOR_NAME_LABEL).unwrap().len() <= 63);
+
+        let annotations = object.metadata.annotations.unwrap();
+        assert_eq!(annotations.get(DECRYPTOR_NAME_ANNOTATION).unwrap(), &tracking.name);
+        assert_ne!(DECRYPTOR_NAME_ANNOTATION, DECRYPTOR_NAME_LABEL);
+    }
+}
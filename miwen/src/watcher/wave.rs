@@ -0,0 +1,264 @@
+// This mod is used to order the rendered objects before applying them on the cluster.
+// Objects are grouped by sync wave and then sorted by kind within a wave. Namespaces and CustomResourceDefinitions
+// are split in implicit sub-waves as the objects which use them can only be applied once they're established
+use std::collections::BTreeMap;
+use std::time::Duration;
+use tokio::time::{sleep, Instant};
+use kube::{
+    Client,
+    core::DynamicObject
+};
+use crate::err::Error;
+use super::apply::{self, RenderedObject, ApplyConfig};
+
+// Constant
+pub const SYNC_WAVE_ANNOTATION: &str = "jiemi.cr/sync-wave";
+const WAVE_TIMEOUT: u64 = 60;
+const WAVE_POLL_INTERVAL: u64 = 2;
+const OBJECT_TIER: u8 = 2;
+const RBAC_KINDS: [&str; 5] = [
+    "ServiceAccount",
+    "Role",
+    "ClusterRole",
+    "RoleBinding",
+    "ClusterRoleBinding"
+];
+
+#[derive(Debug)]
+pub struct Wave {
+    pub id: i64,
+    pub objects: Vec<RenderedObject>
+}
+
+/// Get the priority of a kind. Lower priority are applied first
+///     - Namespace
+///     - CustomResourceDefinition
+///     - RBAC
+///     - Everything else
+///
+/// # Arguments
+/// * `kind` - &str
+fn get_kind_priority(kind: &str) -> u8 {
+    match kind {
+        "Namespace" => 0,
+        "CustomResourceDefinition" => 1,
+        k if RBAC_KINDS.contains(&k) => 2,
+        _ => 3
+    }
+}
+
+/// Get the tier of a kind within a wave. Each tier is applied as an implicit sub-wave
+///     - Namespace
+///     - CustomResourceDefinition
+///     - Everything else (RBAC first)
+///
+/// # Arguments
+/// * `kind` - &str
+fn get_kind_tier(kind: &str) -> u8 {
+    get_kind_priority(kind).min(OBJECT_TIER)
+}
+
+/// Get the sync wave of the object from the jiemi.cr/sync-wave annotation. Default to 0
+///
+/// # Arguments
+/// * `object` - &RenderedObject
+fn get_sync_wave(object: &RenderedObject) -> Result<i64, Error> {
+    let wave = object.object.metadata.annotations
+        .as_ref()
+        .and_then(|annotations| annotations.get(SYNC_WAVE_ANNOTATION));
+
+    match wave {
+        Some(value) => value.parse::<i64>()
+            .map_err(|_| Error::Apply(format!("Invalid {SYNC_WAVE_ANNOTATION} annotation {value} for {}", object.name))),
+        None => Ok(0)
+    }
+}
+
+/// Group the rendered objects by sync wave. Within a wave the objects are sorted by kind
+/// while the order of the file is kept for objects of the same kind. The Namespaces and the
+/// CustomResourceDefinitions of a wave are returned as sub-waves sharing the id of the wave
+/// in order to be established before the rest of the wave is applied
+///
+/// # Arguments
+/// * `objects` - Vec<RenderedObject>
+pub fn group_by_wave(objects: Vec<RenderedObject>) -> Result<Vec<Wave>, Error> {
+    let mut waves: BTreeMap<(i64, u8), Vec<RenderedObject>> = BTreeMap::new();
+    for object in objects {
+        let wave = get_sync_wave(&object)?;
+        let tier = get_kind_tier(&object.gvk.kind);
+        waves.entry((wave, tier)).or_default().push(object);
+    }
+
+    let waves = waves.into_iter()
+        .map(|((id, _), mut objects)| {
+            objects.sort_by_key(|o| get_kind_priority(&o.gvk.kind));
+            Wave { id, objects }
+        })
+        .collect();
+
+    Ok(waves)
+}
+
+/// Check whenever an object is established on the cluster
+///     - CustomResourceDefinition needs to have the Established condition
+///     - Namespace needs to be in the Active phase
+///     - Other objects are established as soon as they exist
+///
+/// # Arguments
+/// * `kind` - &str
+/// * `object` - &DynamicObject
+fn is_established(kind: &str, object: &DynamicObject) -> bool {
+    let status = &object.data["status"];
+    match kind {
+        "CustomResourceDefinition" => status["conditions"]
+            .as_array()
+            .map(|conditions| conditions.iter().any(|c| c["type"] == "Established" && c["status"] == "True"))
+            .unwrap_or_default(),
+        "Namespace" => status["phase"] == "Active",
+        _ => true
+    }
+}
+
+/// Wait for every objects of the wave to be established before moving to the next wave
+///
+/// # Arguments
+/// * `wave` - &Wave
+/// * `client` - &Client
+/// * `ns` - &str
+/// * `config` - &ApplyConfig
+pub async fn wait_for_wave(wave: &Wave, client: &Client, ns: &str, config: &ApplyConfig) -> Result<(), Error> {
+    let deadline = Instant::now() + Duration::from_secs(WAVE_TIMEOUT);
+    for object in &wave.objects {
+        let (api, _) = apply::get_object_api(object, client, ns, config).await
+            .map_err(|err| Error::Wave(wave.id, err.to_string()))?;
+
+        loop {
+            let res = api.get(&object.name).await
+                .map_err(|err| Error::Wave(wave.id, format!("Unable to get {} {}: {err}", object.gvk.kind, object.name)))?;
+            if is_established(&object.gvk.kind, &res) {
+                break;
+            }
+
+            if Instant::now() > deadline {
+                return Err(Error::Wave(wave.id, format!("{} {} is not established", object.gvk.kind, object.name)));
+            }
+
+            sleep(Duration::from_secs(WAVE_POLL_INTERVAL)).await;
+        }
+    }
+
+    info!("🌊 Wave {} has been established", wave.id);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::apply::parse_rendered_objects;
+
+    #[test]
+    fn expect_to_order_objects_by_kind() {
+        let tmpl = r#"
+apiVersion: v1
+kind: ConfigMap
+metadata:
+  name: config
+---
+apiVersion: rbac.authorization.k8s.io/v1
+kind: Role
+metadata:
+  name: role
+---
+apiVersion: apiextensions.k8s.io/v1
+kind: CustomResourceDefinition
+metadata:
+  name: foos.jiemi.cr
+---
+apiVersion: v1
+kind: Namespace
+metadata:
+  name: foo
+"#;
+
+        let objects = parse_rendered_objects(tmpl).unwrap();
+        let waves = group_by_wave(objects).unwrap();
+        assert_eq!(waves.len(), 3);
+        assert!(waves.iter().all(|w| w.id == 0));
+
+        let kinds: Vec<&str> = waves.iter()
+            .flat_map(|w| w.objects.iter())
+            .map(|o| o.gvk.kind.as_str())
+            .collect();
+
+        assert_eq!(kinds, vec!["Namespace", "CustomResourceDefinition", "Role", "ConfigMap"]);
+    }
+
+    #[test]
+    fn expect_crd_to_be_established_before_custom_resource() {
+        let tmpl = r#"
+apiVersion: jiemi.cr/v1alpha1
+kind: Foo
+metadata:
+  name: foo
+---
+apiVersion: apiextensions.k8s.io/v1
+kind: CustomResourceDefinition
+metadata:
+  name: foos.jiemi.cr
+"#;
+
+        let objects = parse_rendered_objects(tmpl).unwrap();
+        let waves = group_by_wave(objects).unwrap();
+        assert_eq!(waves.len(), 2);
+        assert_eq!(waves[0].objects[0].gvk.kind, "CustomResourceDefinition");
+        assert_eq!(waves[1].objects[0].gvk.kind, "Foo");
+    }
+
+    #[test]
+    fn expect_to_group_objects_by_wave() {
+        let tmpl = r#"
+apiVersion: v1
+kind: Secret
+metadata:
+  name: second
+  annotations:
+    jiemi.cr/sync-wave: "1"
+---
+apiVersion: v1
+kind: Secret
+metadata:
+  name: first
+  annotations:
+    jiemi.cr/sync-wave: "-1"
+---
+apiVersion: v1
+kind: Secret
+metadata:
+  name: default
+"#;
+
+        let objects = parse_rendered_objects(tmpl).unwrap();
+        let waves = group_by_wave(objects).unwrap();
+
+        let ids: Vec<i64> = waves.iter().map(|w| w.id).collect();
+        assert_eq!(ids, vec![-1, 0, 1]);
+        assert_eq!(waves[0].objects[0].name, "first");
+        assert_eq!(waves[2].objects[0].name, "second");
+    }
+
+    #[test]
+    fn expect_invalid_wave_to_return_err() {
+        let tmpl = r#"
+apiVersion: v1
+kind: Secret
+metadata:
+  name: foo
+  annotations:
+    jiemi.cr/sync-wave: "bar"
+"#;
+
+        let objects = parse_rendered_objects(tmpl).unwrap();
+        assert!(group_by_wave(objects).is_err());
+    }
+}